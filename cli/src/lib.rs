@@ -1,11 +1,12 @@
-use std::io::{self};
+use std::io::{self, Write};
 use std::sync::Arc;
 use std::sync::mpsc::{channel, Sender, Receiver};
 
 use log::{info, error};
 
 use zecwalletlitelib::{commands,
-    lightclient::{LightClient, LightClientConfig, AddressParameters},
+    lightclient::{LightClient, LightClientConfig},
+    grpcconnector::ConnectionOptions,
 };
 
 pub mod version;
@@ -33,6 +34,21 @@ macro_rules! configure_clapapp {
                 .value_name("seed_phrase")
                 .help("Create a new wallet with the given 24-word seed phrase. Will fail if wallet already exists")
                 .takes_value(true))
+            .arg(Arg::with_name("passphrase")
+                .long("passphrase")
+                .value_name("passphrase")
+                .help("BIP-39 passphrase (the '25th word') to use with the seed phrase when creating a new wallet or restoring one with --seed. It is not saved, and is needed along with the seed phrase to restore the wallet.")
+                .takes_value(true))
+            .arg(Arg::with_name("watch-only")
+                .long("watch-only")
+                .value_name("viewing_key")
+                .help("Create a new watch-only wallet with the given viewing key. The wallet can see the key's transactions, but can never spend. Will fail if wallet already exists")
+                .takes_value(true))
+            .arg(Arg::with_name("watch-only-bundle")
+                .long("watch-only-bundle")
+                .value_name("file")
+                .help("Create a new watch-only wallet from a bundle saved from the 'exportwatchonly' command. It has all the bundle's viewing keys, and syncs from the bundle's birthday. Will fail if wallet already exists")
+                .takes_value(true))
             .arg(Arg::with_name("birthday")
                 .long("birthday")
                 .value_name("birthday")
@@ -41,9 +57,38 @@ macro_rules! configure_clapapp {
             .arg(Arg::with_name("server")
                 .long("server")
                 .value_name("server")
-                .help("Lightwalletd server to connect to.")
+                .help("Lightwalletd server to connect to. Use unix:///path/to/socket for a server on this host. Pass a comma separated list of servers to fail over to the next server if one is unavailable.")
                 .takes_value(true)
                 .default_value(lightclient::DEFAULT_SERVER))
+            .arg(Arg::with_name("tls-pinning")
+                .long("tls-pinning")
+                .help("Only trust servers whose certificate chains to the embedded zecwallet.co certificate chain, instead of any server with a certificate from a well known CA. Off by default, because the embedded chain isn't the default server's.")
+                .takes_value(false))
+            .arg(Arg::with_name("tls-client-cert")
+                .long("tls-client-cert")
+                .value_name("file")
+                .help("PEM file with the client certificate to present to servers that require mutual TLS. Needs --tls-client-key.")
+                .requires("tls-client-key")
+                .takes_value(true))
+            .arg(Arg::with_name("tls-client-key")
+                .long("tls-client-key")
+                .value_name("file")
+                .help("PEM file with the private key of the client certificate given with --tls-client-cert.")
+                .requires("tls-client-cert")
+                .takes_value(true))
+            .arg(Arg::with_name("compress-wallet")
+                .long("compress-wallet")
+                .help("Save the wallet file compressed, which makes it smaller and faster to write. Compressed and uncompressed wallet files can always be read.")
+                .takes_value(false))
+            .arg(Arg::with_name("params-dir")
+                .long("params-dir")
+                .value_name("dir")
+                .help("Directory with sapling-spend.params and sapling-output.params, if they aren't embedded in this binary. Defaults to the zcash params directory.")
+                .takes_value(true))
+            .arg(Arg::with_name("no-save")
+                .long("no-save")
+                .help("Never write the wallet file. Changes made while running, like synced blocks or new addresses, are lost on exit.")
+                .takes_value(false))
             .arg(Arg::with_name("COMMAND")
                 .help("Command to execute. If a command is not specified, zecwallet-cli will start in interactive mode.")
                 .required(false)
@@ -77,23 +122,41 @@ pub fn report_permission_error() {
     }
 }
 
-pub fn startup(server: http::Uri, seed: Option<String>, birthday: u64, first_sync: bool, print_updates: bool)
+pub fn startup(servers: Vec<http::Uri>, connection: ConnectionOptions, persist: bool, compress_wallet: bool, params_dir: Option<String>, seed: Option<String>, passphrase: Option<String>, watch_only_key: Option<String>, watch_only_bundle: Option<String>, birthday: u64, first_sync: bool, print_updates: bool)
         -> io::Result<(Sender<(String, Vec<String>)>, Receiver<String>)> {
-    // Try to get the configuration
-    let (config, latest_block_height) = LightClientConfig::create(server.clone())?;
-
-    let lightclient = match seed {
-        Some(phrase) => Arc::new(LightClient::new_from_phrase(phrase, &config, birthday, false)?),
-        None => {
+    // Try to get the configuration from the first server that responds
+    let mut maybe_config = Err(io::Error::new(io::ErrorKind::InvalidInput, "No server specified"));
+    for server in servers.iter() {
+        maybe_config = LightClientConfig::create(server.clone(), connection.clone());
+        match &maybe_config {
+            Ok(_)  => break,
+            Err(e) => eprintln!("Couldn't connect to {}: {}", server, e)
+        }
+    }
+    let (mut config, latest_block_height) = maybe_config?;
+    config.persist = persist;
+    config.compress_wallet = compress_wallet;
+    config.params_dir = params_dir;
+
+    let lightclient = match (seed, watch_only_key, watch_only_bundle) {
+        (Some(phrase), _, _) => Arc::new(LightClient::new_from_phrase_with_passphrase(phrase, passphrase, &config, birthday, false)?),
+        (None, Some(viewing_key), _) => Arc::new(LightClient::new_watch_only(viewing_key, &config, birthday)?),
+        (None, None, Some(bundle)) => Arc::new(LightClient::new_watch_only_from_bundle(&bundle, &config)?),
+        (None, None, None) => {
             if config.wallet_exists() {
+                if passphrase.is_some() {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "A wallet already exists, --passphrase is only used when creating or restoring a wallet"));
+                }
                 Arc::new(LightClient::read_from_disk(&config)?)
             } else {
                 println!("Creating a new wallet");
-                Arc::new(LightClient::new(&config, latest_block_height)?)
+                Arc::new(LightClient::new_with_passphrase(&config, latest_block_height, passphrase)?)
             }
         }
     };
 
+    lightclient.set_server_list(servers);
+
     // Initialize logging
     lightclient.init_logging()?;
 
@@ -104,6 +167,22 @@ pub fn startup(server: http::Uri, seed: Option<String>, birthday: u64, first_syn
 
     if print_updates {
         println!("Lightclient connecting to {}", config.server);
+
+        // Draw a progress line while syncing, finishing it once the sync is done
+        lightclient.set_sync_progress_callback(Some(Box::new(|synced_blocks, total_blocks| {
+            if synced_blocks == total_blocks {
+                println!("Syncing {}/{}", synced_blocks, total_blocks);
+            } else if synced_blocks % 100 == 0 {
+                print!("Syncing {}/{}\r", synced_blocks, total_blocks);
+                io::stdout().flush().ok();
+            }
+        })));
+    }
+
+    // Sending needs the sapling params, so warn now instead of on the first send
+    if let Err(e) = lightclient.check_sapling_params() {
+        error!("{}", e);
+        eprintln!("WARNING: {}", e);
     }
 
     // At startup, run a sync.
@@ -236,15 +315,7 @@ pub fn command_loop(lightclient: Arc<LightClient>) -> (Sender<(String, Vec<Strin
 
 pub fn attempt_recover_seed(password: Option<String>) {
     // Create a Light Client Config in an attempt to recover the file.
-    let config = LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "main".to_string(),
-        sapling_activation_height: 0,
-        consensus_branch_id: "000000".to_string(),
-        anchor_offset: 0,
-        data_dir: None,
-        address_params: AddressParameters::new()
-    };
+    let config = LightClientConfig::create_unconnected("main".to_string(), None);
 
     match LightClient::attempt_recover_seed(&config, password) {
         Ok(seed) => {
@@ -1,4 +1,4 @@
-use zecwalletlitelib::lightclient::{self, LightClientConfig};
+use zecwalletlitelib::{grpcconnector, lightclient::{self, LightClientConfig}};
 use zecwallet_cli::{configure_clapapp,
                     report_permission_error,
                     startup,
@@ -26,9 +26,24 @@ pub fn main() {
     let maybe_server   = matches.value_of("server").map(|s| s.to_string());
 
     let seed           = matches.value_of("seed").map(|s| s.to_string());
+    let passphrase     = matches.value_of("passphrase").map(|s| s.to_string());
+    let watch_only_key = matches.value_of("watch-only").map(|s| s.to_string());
+    let bundle_file    = matches.value_of("watch-only-bundle");
     let maybe_birthday = matches.value_of("birthday");
-    
-    if seed.is_some() && maybe_birthday.is_none() {
+
+    if [seed.is_some(), watch_only_key.is_some(), bundle_file.is_some()].iter().filter(|b| **b).count() > 1 {
+        eprintln!("ERROR!");
+        eprintln!("Please pass only one of --seed, --watch-only or --watch-only-bundle.");
+        return;
+    }
+
+    if passphrase.is_some() && (watch_only_key.is_some() || bundle_file.is_some()) {
+        eprintln!("ERROR!");
+        eprintln!("--passphrase can't be used with a watch-only wallet, which has no seed.");
+        return;
+    }
+
+    if (seed.is_some() || watch_only_key.is_some()) && maybe_birthday.is_none() {
         eprintln!("ERROR!");
         eprintln!("Please specify the wallet birthday (eg. '--birthday 600000') to restore from seed.");
         eprintln!("This should be the block height where the wallet was created. If you don't remember the block height, you can pass '--birthday 0' to scan from the start of the blockchain.");
@@ -43,16 +58,65 @@ pub fn main() {
                         }
                     };
 
-    let server = LightClientConfig::get_server_or_default(maybe_server);
+    // The bundle has its own birthday, so it doesn't need --birthday
+    let watch_only_bundle = match bundle_file {
+        Some(f) => match std::fs::read_to_string(f) {
+            Ok(bundle) => Some(bundle),
+            Err(e)     => {
+                eprintln!("Couldn't read the watch-only bundle {}: {}", f, e);
+                return;
+            }
+        },
+        None => None,
+    };
 
-    // Test to make sure the server has all of scheme, host and port
-    if server.scheme_str().is_none() || server.host().is_none() || server.port().is_none() {
-        eprintln!("Please provide the --server parameter as [scheme]://[host]:[port].\nYou provided: {}", server);
+    // Unix domain sockets have no host or port, so their paths are checked on their own
+    if let Some(e) = maybe_server.iter()
+                        .flat_map(|s| s.split(','))
+                        .map(|s| s.trim())
+                        .filter(|s| s.starts_with("unix:"))
+                        .find_map(|s| grpcconnector::uds_uri(s).err()) {
+        eprintln!("{}", e);
         return;
     }
 
+    // Multiple servers can be passed as a comma separated list. They are tried in order.
+    let servers = match maybe_server {
+        Some(s) => s.split(',').map(|s| LightClientConfig::get_server_or_default(Some(s.trim().to_string()))).collect::<Vec<_>>(),
+        None    => vec![LightClientConfig::get_server_or_default(None)]
+    };
+
+    // Test to make sure the servers have all of scheme, host and port
+    for server in servers.iter() {
+        if grpcconnector::is_uds(server) {
+            continue;
+        }
+
+        if server.scheme_str().is_none() || server.host().is_none() || server.port().is_none() {
+            eprintln!("Please provide the --server parameter as [scheme]://[host]:[port] or unix:///path/to/socket.\nYou provided: {}", server);
+            return;
+        }
+    }
+
     let nosync = matches.is_present("nosync");
-    let (command_tx, resp_rx) = match startup(server, seed, birthday, !nosync, command.is_none()) {
+    let pin_tls_certificate = matches.is_present("tls-pinning");
+
+    let client_identity = match (matches.value_of("tls-client-cert"), matches.value_of("tls-client-key")) {
+        (Some(cert), Some(key)) => match grpcconnector::ClientIdentity::from_pem_files(cert, key) {
+            Ok(identity) => Some(identity),
+            Err(e)       => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        _ => None,
+    };
+
+    let persist = !matches.is_present("no-save");
+    let compress_wallet = matches.is_present("compress-wallet");
+    let params_dir = matches.value_of("params-dir").map(|s| s.to_string());
+    let connection = grpcconnector::ConnectionOptions { pin_tls_certificate, client_identity, ..grpcconnector::ConnectionOptions::default() };
+    let (command_tx, resp_rx) = match startup(servers, connection, persist, compress_wallet, params_dir, seed, passphrase, watch_only_key, watch_only_bundle, birthday, !nosync, command.is_none()) {
         Ok(c) => c,
         Err(e) => {
             let emsg = format!("Error during startup:{}\nIf you repeatedly run into this issue, you might have to restore your wallet from your seed phrase.", e);
@@ -5,7 +5,19 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &["proto/service.proto", "proto/compact_formats.proto"],
             &["proto"],
          )?;
+
+    // The server stubs are only used by the mock server in the tests. They are generated into their
+    // own file, which only the test build includes, so they aren't compiled into the library
+    let server_dir = std::path::PathBuf::from(std::env::var("OUT_DIR")?).join("server");
+    std::fs::create_dir_all(&server_dir)?;
+    tonic_build::configure()
+         .build_client(false)
+         .out_dir(&server_dir)
+         .compile(
+            &["proto/service.proto", "proto/compact_formats.proto"],
+            &["proto"],
+         )?;
+
     println!("cargo:rerun-if-changed=proto/service.proto");
     Ok(())
  }
- 
\ No newline at end of file
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
-use json::{object};
+use json::{object, array};
 
-use crate::lightclient::LightClient;
+use crate::lightclient::{LightClient, ListOptions, PAYMENT_URI_SCHEME, arrr_json_to_zatoshis};
+use crate::lightwallet::{LightWallet, NoteSelectionStrategy, NoteOutpoint, SendOptions, MAX_MEMO_BYTES};
 
 pub trait Command {
     fn help(&self) -> String;
@@ -56,6 +57,29 @@ impl Command for EncryptionStatusCommand {
     }
 }
 
+struct ParamsStatusCommand {}
+impl Command for ParamsStatusCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Check if the sapling params, which are needed to send, are available");
+        h.push("Usage:");
+        h.push("paramsstatus");
+        h.push("");
+        h.push("If the params aren't embedded in this binary, they are read at startup from the directory given");
+        h.push("with --params-dir, or the zcash params directory. Sends fail until they are available.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Check if the sapling params needed to send are available".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_params_status().pretty(2)
+    }
+}
+
 struct SyncStatusCommand {}
 impl Command for SyncStatusCommand {
     fn help(&self) -> String {
@@ -78,192 +102,196 @@ impl Command for SyncStatusCommand {
             false => object!{ "syncing" => "false" },
             true  => object!{ "syncing" => "true",
                               "synced_blocks" => status.synced_blocks,
-                              "total_blocks" => status.total_blocks }
+                              "total_blocks" => status.total_blocks,
+                              "blocks_per_second" => status.blocks_per_second,
+                              "eta_seconds" => status.eta_seconds }
         }.pretty(2)
     }
 }
 
-struct RescanCommand {}
-impl Command for RescanCommand {
+struct SyncEstimateCommand {}
+impl Command for SyncEstimateCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Rescan the wallet, rescanning all blocks for new transactions");
+        h.push("Estimate how long it will take to sync the wallet, without starting a sync");
         h.push("Usage:");
-        h.push("rescan");
+        h.push("syncestimate");
         h.push("");
-        h.push("This command will download all blocks since the intial block again from the light client server");
-        h.push("and attempt to scan each block for transactions belonging to the wallet.");
+        h.push("The estimate is the number of blocks the wallet is behind the server, divided by the average");
+        h.push("speed of the previous syncs. Until the wallet has finished a sync of at least 100 blocks, a slow");
+        h.push("default speed is used and 'rough' is true.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Rescan the wallet, downloading and scanning all blocks and transactions".to_string()
+        "Estimate how long a sync will take".to_string()
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        match lightclient.do_rescan() {
-            Ok(j) => j.pretty(2),
-            Err(e) => e
-        }
+        match lightclient.do_sync_estimate() {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
     }
 }
 
-
-struct ClearCommand {}
-impl Command for ClearCommand {
+struct ListOperationsCommand {}
+impl Command for ListOperationsCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Clear the wallet state, rolling back the wallet to an empty state.");
+        h.push("List the running and recently finished syncs, rescans and sends");
         h.push("Usage:");
-        h.push("clear");
+        h.push("listoperations");
         h.push("");
-        h.push("This command will clear all notes, utxos and transactions from the wallet, setting up the wallet to be synced from scratch.");
+        h.push("Each operation has an 'id', its 'kind', and a 'status' of 'running', 'cancelling', 'finished', 'cancelled' or 'failed'.");
+        h.push("A running sync or rescan also shows its progress in 'synced_blocks' and 'total_blocks'.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Clear the wallet state, rolling back the wallet to an empty state.".to_string()
+        "List running and recent long operations".to_string()
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-       lightclient.clear_state();
-
-       let result = object!{ "result" => "success" };
-
-       result.pretty(2)
+        lightclient.do_list_operations().pretty(2)
     }
 }
 
-struct HelpCommand {}
-impl Command for HelpCommand {
+struct CancelOperationCommand {}
+impl Command for CancelOperationCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("List all available commands");
+        h.push("Cancel a running sync or rescan");
         h.push("Usage:");
-        h.push("help [command_name]");
-        h.push("");
-        h.push("If no \"command_name\" is specified, a list of all available commands is returned");
-        h.push("Example:");
-        h.push("help send");
+        h.push("canceloperation <id>");
         h.push("");
+        h.push("The operation stops once it has scanned its current batch of blocks, so the wallet stays consistent.");
+        h.push("A cancelled rescan carries on from its last checkpoint the next time 'rescan' is run. Sends can't be cancelled.");
+        h.push("Use 'listoperations' to find the id.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Lists all available commands".to_string()
+        "Cancel a running sync or rescan".to_string()
     }
 
-    fn exec(&self, args: &[&str], _: &LightClient) -> String {
-        let mut responses = vec![];
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
 
-        // Print a list of all commands
-        match args.len() {
-            0 => {
-                responses.push(format!("Available commands:"));
-                get_commands().iter().for_each(| (cmd, obj) | {
-                    responses.push(format!("{} - {}", cmd, obj.short_help()));
-                });
+        let id = match args[0].parse::<u64>() {
+            Ok(id) => id,
+            Err(_) => return format!("Error: '{}' is not an operation id\n{}", args[0], self.help()),
+        };
 
-                responses.join("\n")
-            },
-            1 => {
-                match get_commands().get(args[0]) {
-                    Some(cmd) => cmd.help(),
-                    None => format!("Command {} not found", args[0])
-                }
-            },
-            _ => self.help()
-        }
+        match lightclient.do_cancel_operation(id) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
     }
 }
 
-struct InfoCommand {}
-impl Command for InfoCommand {
+struct AbortRescanCommand {}
+impl Command for AbortRescanCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Get info about the lightwalletd we're connected to");
+        h.push("Abort a running rescan");
         h.push("Usage:");
-        h.push("info");
+        h.push("abortrescan");
         h.push("");
+        h.push("The rescan stops once it has scanned its current batch of blocks, so the wallet is left at the last fully");
+        h.push("scanned block. A checkpoint is saved there, and the next 'rescan' carries on from it.");
+        h.push("If no rescan is running, nothing happens.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Get the lightwalletd server's info".to_string()
+        "Abort a running rescan".to_string()
     }
 
-    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        lightclient.do_info()
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 0 {
+            return self.help();
+        }
+
+        lightclient.do_abort_rescan().pretty(2)
     }
 }
 
-struct BalanceCommand {}
-impl Command for BalanceCommand {
+struct DisconnectCommand {}
+impl Command for DisconnectCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Show the current ARRR balance in the wallet");
+        h.push("Stop talking to the server, for example before the computer sleeps or when the network changes");
         h.push("Usage:");
-        h.push("balance");
+        h.push("disconnect");
         h.push("");
-        h.push("Shielded balances, along with the addresses they belong to are displayed");
+        h.push("A running sync or rescan is cancelled once it finishes its current batch of blocks. Until 'reconnect',");
+        h.push("syncs and sends fail right away instead of waiting for a dead connection to time out.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Show the current ARRR balance in the wallet".to_string()
+        "Stop talking to the server until 'reconnect'".to_string()
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        format!("{}", lightclient.do_balance().pretty(2))
+        lightclient.disconnect().pretty(2)
     }
 }
 
-
-struct AddressCommand {}
-impl Command for AddressCommand {
+struct ReconnectCommand {}
+impl Command for ReconnectCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("List current addresses in the wallet");
+        h.push("Connect to the server again after 'disconnect'");
         h.push("Usage:");
-        h.push("address");
+        h.push("reconnect");
         h.push("");
+        h.push("The server is checked with a fresh connection. If it doesn't answer within a few seconds, the next");
+        h.push("available server in the server list is used instead.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "List all addresses in the wallet".to_string()
+        "Connect to the server again".to_string()
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        format!("{}", lightclient.do_address().pretty(2))
+        match lightclient.reconnect() {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
     }
 }
 
-struct ExportCommand {}
-impl Command for ExportCommand {
+struct RescanCommand {}
+impl Command for RescanCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Export private key for an individual wallet addresses.");
-        h.push("Note: To backup the whole wallet, use the 'seed' command instead");
+        h.push("Rescan the wallet, rescanning all blocks for new transactions");
         h.push("Usage:");
-        h.push("export [z-address]");
-        h.push("");
-        h.push("If no address is passed, private key for all addresses in the wallet are exported.");
+        h.push("rescan [height | restart]");
         h.push("");
-        h.push("Example:");
-        h.push("export zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d");
+        h.push("This command will download all blocks since the intial block again from the light client server");
+        h.push("and attempt to scan each block for transactions belonging to the wallet.");
+        h.push("A rescan saves its progress every few thousand blocks. If it is interrupted, it resumes from there");
+        h.push("the next time the wallet syncs or is rescanned. Use 'rescan restart' to start it over instead.");
+        h.push("If a height is given, only the blocks from that height onwards are rescanned, and the wallet's");
+        h.push("transactions from before it are kept. The height has to be within the last 100 blocks scanned.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Export private key for wallet addresses".to_string()
+        "Rescan the wallet, downloading and scanning all blocks and transactions".to_string()
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
@@ -271,35 +299,41 @@ impl Command for ExportCommand {
             return self.help();
         }
 
-        let address = if args.is_empty() { None } else { Some(args[0].to_string()) };
-        match lightclient.do_export(address) {
-            Ok(j)  => j,
-            Err(e) => object!{ "error" => e }
-        }.pretty(2)
+        let result = if args.len() == 1 && args[0] == "restart" {
+            lightclient.do_rescan_with(true)
+        } else if args.len() == 1 {
+            match args[0].parse::<u64>() {
+                Ok(height) => lightclient.do_rescan_from(height),
+                Err(e)     => return format!("Couldn't parse height: {}\n{}", e, self.help())
+            }
+        } else {
+            lightclient.do_rescan()
+        };
+
+        match result {
+            Ok(j) => j.pretty(2),
+            Err(e) => e
+        }
     }
 }
 
-struct EncryptCommand {}
-impl Command for EncryptCommand {
+
+struct RewindCommand {}
+impl Command for RewindCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Encrypt the wallet with a password");
-        h.push("Note 1: This will encrypt the seed and the private keys.");
-        h.push("        Use 'unlock' to temporarily unlock the wallet for spending or 'decrypt' ");
-        h.push("        to permanatly remove the encryption");
-        h.push("Note 2: If you forget the password, the only way to recover the wallet is to restore");
-        h.push("        from the seed phrase.");
+        h.push("Roll the wallet back by a number of blocks, and sync again from there");
         h.push("Usage:");
-        h.push("encrypt password");
+        h.push("rewind <blocks>");
         h.push("");
-        h.push("Example:");
-        h.push("encrypt my_strong_password");
+        h.push("The notes, transactions and witnesses from the dropped blocks are undone, and the wallet keeps everything from before them.");
+        h.push("Useful after a reorg. Only the last 99 blocks can be rewound, use 'rescan' to go back further.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Encrypt the wallet with a password".to_string()
+        "Roll back the last blocks and rescan them".to_string()
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
@@ -307,38 +341,37 @@ impl Command for EncryptCommand {
             return self.help();
         }
 
-        let passwd = args[0].to_string();
+        let blocks = match args[0].parse::<u64>() {
+            Ok(b)  => b,
+            Err(e) => return format!("Couldn't parse number of blocks: {}\n{}", e, self.help())
+        };
 
-        match lightclient.wallet.write().unwrap().encrypt(passwd) {
-            Ok(_)  => object!{ "result" => "success" },
-            Err(e) => object!{
-                "result" => "error",
-                "error"  => e.to_string()
-            }
-        }.pretty(2)
+        match lightclient.do_rewind(blocks) {
+            Ok(j)  => j.pretty(2),
+            Err(e) => e
+        }
     }
 }
 
-struct DecryptCommand {}
-impl Command for DecryptCommand {
+struct ForgetUnconfirmedCommand {}
+impl Command for ForgetUnconfirmedCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Completely remove wallet encryption, storing the wallet in plaintext on disk");
-        h.push("Note 1: This will decrypt the seed and the private keys and store them on disk.");
-        h.push("        Use 'unlock' to temporarily unlock the wallet for spending");
-        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
-        h.push("        from the seed phrase.");
+        h.push("Forget sends that were never mined");
         h.push("Usage:");
-        h.push("decrypt password");
+        h.push("forgetunconfirmed <minutes>");
         h.push("");
+        h.push("Removes the wallet's unconfirmed sends that are at least <minutes> old, and makes the notes they spent");
+        h.push("spendable again. Use it for a send that will never be mined, for example because it expired. Mined");
+        h.push("transactions are not touched. If a forgotten send is mined after all, the next sync picks it up.");
         h.push("Example:");
-        h.push("decrypt my_strong_password");
+        h.push("forgetunconfirmed 60");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Completely remove wallet encryption".to_string()
+        "Forget unconfirmed sends and release their notes".to_string()
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
@@ -346,205 +379,1849 @@ impl Command for DecryptCommand {
             return self.help();
         }
 
-        let passwd = args[0].to_string();
+        let minutes = match args[0].parse::<u64>() {
+            Ok(m)  => m,
+            Err(e) => return format!("Couldn't parse number of minutes: {}\n{}", e, self.help())
+        };
 
-        match lightclient.wallet.write().unwrap().remove_encryption(passwd) {
-            Ok(_)  => object!{ "result" => "success" },
-            Err(e) => object!{
-                "result" => "error",
-                "error"  => e.to_string()
-            }
-        }.pretty(2)
+        lightclient.do_forget_unconfirmed(minutes).pretty(2)
     }
 }
 
-
-struct UnlockCommand {}
-impl Command for UnlockCommand {
+struct ClearCommand {}
+impl Command for ClearCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Unlock the wallet's encryption in memory, allowing spending from this wallet.");
-        h.push("Note 1: This will decrypt spending keys in memory only. The wallet remains encrypted on disk");
-        h.push("        Use 'decrypt' to remove the encryption permanatly.");
-        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
-        h.push("        from the seed phrase.");
+        h.push("Clear the wallet state, rolling back the wallet to an empty state.");
         h.push("Usage:");
-        h.push("unlock password");
+        h.push("clear");
         h.push("");
-        h.push("Example:");
-        h.push("unlock my_strong_password");
+        h.push("This command will clear all notes, utxos and transactions from the wallet, setting up the wallet to be synced from scratch.");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Unlock wallet encryption for spending".to_string()
+        "Clear the wallet state, rolling back the wallet to an empty state.".to_string()
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
-            return self.help();
-        }
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+       lightclient.clear_state();
 
-        let passwd = args[0].to_string();
+       let result = object!{ "result" => "success" };
 
-        match lightclient.wallet.write().unwrap().unlock(passwd) {
-            Ok(_)  => object!{ "result" => "success" },
-            Err(e) => object!{
-                "result" => "error",
-                "error"  => e.to_string()
-            }
-        }.pretty(2)
+       result.pretty(2)
     }
 }
 
-
-struct LockCommand {}
-impl Command for LockCommand {
+struct HelpCommand {}
+impl Command for HelpCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Lock a wallet that's been temporarily unlocked. You should already have encryption enabled.");
-        h.push("Note 1: This will remove all spending keys from memory. The wallet remains encrypted on disk");
-        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
-        h.push("        from the seed phrase.");
+        h.push("List all available commands");
         h.push("Usage:");
-        h.push("lock");
+        h.push("help [command_name]");
         h.push("");
+        h.push("If no \"command_name\" is specified, a list of all available commands is returned");
         h.push("Example:");
-        h.push("lock");
+        h.push("help send");
+        h.push("");
 
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Lock a wallet that's been temporarily unlocked".to_string()
+        "Lists all available commands".to_string()
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 0 {
-            let mut h = vec![];
-            h.push("Extra arguments to lock. Did you mean 'encrypt'?");
-            h.push("");
+    fn exec(&self, args: &[&str], _: &LightClient) -> String {
+        let mut responses = vec![];
 
-            return format!("{}\n{}", h.join("\n"), self.help());
-        }
+        // Print a list of all commands
+        match args.len() {
+            0 => {
+                responses.push(format!("Available commands:"));
+                get_commands().iter().for_each(| (cmd, obj) | {
+                    responses.push(format!("{} - {}", cmd, obj.short_help()));
+                });
 
-        match lightclient.wallet.write().unwrap().lock() {
-            Ok(_)  => object!{ "result" => "success" },
-            Err(e) => object!{
-                "result" => "error",
-                "error"  => e.to_string()
-            }
-        }.pretty(2)
+                responses.join("\n")
+            },
+            1 => {
+                match get_commands().get(args[0]) {
+                    Some(cmd) => cmd.help(),
+                    None => format!("Command {} not found", args[0])
+                }
+            },
+            _ => self.help()
+        }
     }
 }
 
-
-struct SendCommand {}
-impl Command for SendCommand {
+struct HealthCheckCommand {}
+impl Command for HealthCheckCommand {
     fn help(&self) -> String {
         let mut h = vec![];
-        h.push("Send ARRR to a given address(es)");
+        h.push("Check that the wallet and its server are healthy, for liveness and readiness probes");
         h.push("Usage:");
-        h.push("send '{'input': <address>, 'output': [{'address': <address>, 'amount': <amount in zatoshis>, 'memo': <optional memo>}, ...]}");
+        h.push("healthcheck");
         h.push("");
-        h.push("NOTE: The fee required to send this transaction (currently ZEC 0.0001) is additionally detected from your balance.");
-        h.push("Example:");
-        h.push("send '{\"input\":\"ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d\", \"output\": [{ \"address\": \"ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d\", \"amount\": 200000, \"memo\": \"Hello from the command line\"}]}'");
+        h.push("The 'status' is 'ok', 'syncing' if a sync is running or the wallet is well behind the server, or");
+        h.push("'disconnected' if the server didn't answer within a few seconds. It also shows the wallet's 'height',");
+        h.push("the server's 'server_height' and how many blocks the wallet is 'behind'. This doesn't start a sync.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Quick health status of the wallet and server".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_healthcheck().pretty(2)
+    }
+}
+
+struct InfoCommand {}
+impl Command for InfoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Get info about the lightwalletd we're connected to");
+        h.push("Usage:");
+        h.push("info");
         h.push("");
+        h.push("The info is checked against the wallet: 'chain_name_matches' is false if the server is on another chain, and");
+        h.push("'server_behind' is true if the server's latest block is well behind the wallet's. Problems are listed in 'warnings'.");
+        h.push("'default_fee' is the fee used by sends that don't give one (see 'setfee').");
+
         h.join("\n")
     }
 
     fn short_help(&self) -> String {
-        "Send ARRR to the given address".to_string()
+        "Get the lightwalletd server's info".to_string()
     }
 
-    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        // Parse the args.
-        // A single argument in the form of a JSON string that is "{input: address, output: [{address: address, value: value, memo: memo},...], fee: fee}"
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_info()
+    }
+}
 
-        // 1 - Destination address. T or Z address
+struct WalletBirthdayCommand {}
+impl Command for WalletBirthdayCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the wallet's birthday and the height at which the wallet was created");
+        h.push("Usage:");
+        h.push("walletbirthday");
+        h.push("");
+        h.push("The birthday is the block from which rescans start. Importing older keys lowers the birthday");
+        h.push("to the imported key's birthday, but doesn't change the creation height.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the wallet's birthday".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_wallet_birthday().pretty(2)
+    }
+}
+
+struct ServerListCommand {}
+impl Command for ServerListCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("List the lightwalletd servers the wallet can fail over to, and the currently active server");
+        h.push("Usage:");
+        h.push("serverlist");
+        h.push("");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "List the lightwalletd servers".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_server_list().pretty(2)
+    }
+}
+
+struct SwitchServerCommand {}
+impl Command for SwitchServerCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Switch the active lightwalletd server");
+        h.push("Usage:");
+        h.push("switchserver <server>");
+        h.push("");
+        h.push("The server is added to the server list if it isn't already in it.");
+        h.push("Example:");
+        h.push("switchserver https://lightd.pirate.black:443");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Switch the active lightwalletd server".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
         if args.len() != 1 {
             return self.help();
         }
 
-        use std::convert::TryInto;
-        use zcash_primitives::transaction::components::amount::DEFAULT_FEE;
+        let server = match args[0].parse::<http::Uri>() {
+            Ok(s) => s,
+            Err(e) => return format!("Couldn't parse {} as a server: {}", args[0], e)
+        };
 
-        // Check for a single argument that can be parsed as JSON
-        let arg_list = args[0];
+        match lightclient.switch_server(server) {
+            Ok(_)  => lightclient.do_server_list(),
+            Err(e) => object!{ "result" => "error", "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct BalanceCommand {}
+impl Command for BalanceCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the current ARRR balance in the wallet");
+        h.push("Usage:");
+        h.push("balance [account]");
+        h.push("");
+        h.push("Shielded balances, along with the addresses they belong to are displayed");
+        h.push("The balance of account 0 is shown, unless another account number is given.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the current ARRR balance in the wallet".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let account = match args {
+            []        => 0,
+            [account] => match account.parse::<usize>() {
+                Ok(a)  => a,
+                Err(_) => return self.help()
+            },
+            _         => return self.help()
+        };
 
-        let json_args = match json::parse(&arg_list) {
+        match lightclient.do_account_balance(account) {
             Ok(j)  => j,
-            Err(e) => {
-                let es = format!("Couldn't understand JSON: {}", e);
-                return format!("{}\n{}", es, self.help());
-            }
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct TotalBalanceCommand {}
+impl Command for TotalBalanceCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the total ARRR balance of the wallet, summed across all addresses");
+        h.push("Usage:");
+        h.push("totalbalance [price per ARRR]");
+        h.push("");
+        h.push("If a price is given, the total is also converted into 'fiat_value' using it. The price is not looked up anywhere.");
+        h.push("Example:");
+        h.push("totalbalance 0.25");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the total ARRR balance, optionally in fiat".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let price = match args {
+            []      => None,
+            [price] => match price.parse::<f64>() {
+                Ok(p)  => Some(p),
+                Err(e) => return format!("Couldn't parse price: {}\n{}", e, self.help())
+            },
+            _       => return self.help()
         };
 
-        //Check for a fee key and convert to u64
-        let fee: u64 = if json_args.has_key("fee") {
-            match json_args["fee"].as_u64() {
-                Some(f) => f.clone(),
-                None => DEFAULT_FEE.try_into().unwrap()
-            }
-        } else {
-            DEFAULT_FEE.try_into().unwrap()
+        match lightclient.do_total_balance(price) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+
+struct AddressCommand {}
+impl Command for AddressCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("List current addresses in the wallet");
+        h.push("Usage:");
+        h.push("address [account] [raw] [--nonzero] [--sort]");
+        h.push("");
+        h.push("The addresses of account 0 are listed, unless another account number is given.");
+        h.push("If 'raw' is passed, the addresses are printed one per line (z addresses first), without any JSON.");
+        h.push("With --nonzero, only addresses that have a balance are listed. With --sort, the addresses are sorted");
+        h.push("by balance, largest first. Either flag adds each address's 'balance' to its details.");
+        h.push("The address details include 'first_received_height' and 'last_activity_height', the heights at which the");
+        h.push("address first received funds and last received or spent them. Both are null for addresses that were never used.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "List all addresses in the wallet".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let only_nonzero = args.contains(&"--nonzero");
+        let sort_by_balance = args.contains(&"--sort");
+        let args = args.iter().filter(|a| **a != "--nonzero" && **a != "--sort").cloned().collect::<Vec<&str>>();
+
+        let (account, raw) = match parse_account_and_raw(&args) {
+            Some(a) => a,
+            None    => return self.help()
         };
 
-        //Check for a input key and convert to str
-        let from = if json_args.has_key("input") {
-            json_args["input"].as_str().unwrap().clone()
+        let addresses = if only_nonzero || sort_by_balance {
+            lightclient.do_account_address_filtered(account, only_nonzero, sort_by_balance)
         } else {
-            return format!("Error: {}\n{}", "Need input address", self.help());
+            lightclient.do_account_address(account)
         };
 
-        //Check for output key
-        let json_tos = if json_args.has_key("output") {
-            &json_args["output"]
+        let addresses = match addresses {
+            Ok(j)  => j,
+            Err(e) => return object!{ "error" => e }.pretty(2)
+        };
+
+        if raw {
+            addresses["z_addresses"].members()
+                .chain(addresses["t_addresses"].members())
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>()
+                .join("\n")
         } else {
-            return format!("Error: {}\n{}", "Need output address", self.help());
+            addresses.pretty(2)
+        }
+    }
+}
+
+struct AddressAtIndexCommand {}
+impl Command for AddressAtIndexCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Derive the z and t addresses at an HD index from the wallet's seed");
+        h.push("Usage:");
+        h.push("addressatindex <index>");
+        h.push("");
+        h.push("The addresses are not added to the wallet. The same seed always derives the same addresses,");
+        h.push("so this shows which addresses another wallet restored from the seed will have. 'address' shows the index of each address.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Derive the addresses at an HD index".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        let index = match args[0].parse::<u32>() {
+            Ok(i)  => i,
+            Err(_) => return format!("Error: '{}' is not an index\n{}", args[0], self.help()),
         };
 
-        //Check output is in the form of an array
-        if !json_tos.is_array() {
-            return format!("Couldn't parse argument as array\n{}", self.help());
+        match lightclient.do_address_at_index(index) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct KeyInfoCommand {}
+impl Command for KeyInfoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the ZIP-32 derivation path and fingerprint of each z key in the wallet");
+        h.push("Usage:");
+        h.push("keyinfo [account]");
+        h.push("");
+        h.push("The keys of account 0 are listed, unless another account number is given.");
+        h.push("The derivation path is relative to the account's seed, e.g. m/32'/141'/0'. Imported keys weren't derived");
+        h.push("from the seed, so their 'derivation_path' is null.");
+        h.push("'fingerprint' is the short (4 byte) ZIP-32 fingerprint of the key's full viewing key, as other ZIP-32 tools show it.");
+        h.push("'full_fingerprint' is all 32 bytes of it.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the derivation path and fingerprint of each z key".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() > 1 {
+            return self.help();
         }
 
-        //Check array for manadantory address and amount keys
-        let maybe_send_args = json_tos.members().map( |j| {
-            if !j.has_key("address") || !j.has_key("amount") {
-                Err(format!("Need 'address' and 'amount'\n"))
-            } else {
-                let amount = match j["amount"].as_str() {
-                    Some("entire-verified-zbalance") => lightclient.wallet.read().unwrap().verified_zbalance(None).checked_sub(fee),
-                    _ => Some(j["amount"].as_u64().unwrap())
-                };
+        let account = match args.first() {
+            Some(a) => match a.parse::<usize>() {
+                Ok(a)  => a,
+                Err(_) => return format!("Error: '{}' is not an account number\n{}", a, self.help()),
+            },
+            None    => 0
+        };
+
+        match lightclient.do_key_info(account) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+// Parse the optional "[account] [raw]" args, in any order. The account defaults to 0.
+fn parse_account_and_raw(args: &[&str]) -> Option<(usize, bool)> {
+    let mut account = None;
+    let mut raw = false;
+
+    for arg in args {
+        if *arg == "raw" && !raw {
+            raw = true;
+        } else if account.is_none() {
+            account = Some(arg.parse::<usize>().ok()?);
+        } else {
+            return None;
+        }
+    }
+
+    Some((account.unwrap_or(0), raw))
+}
+
+struct ValidateAddressCommand {}
+impl Command for ValidateAddressCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Check if an address is a valid Pirate address, and if it belongs to this wallet");
+        h.push("Usage:");
+        h.push("validateaddress <address>");
+        h.push("");
+        h.push("Example:");
+        h.push("validateaddress zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Check if an address is valid".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        lightclient.do_validate_address(args[0]).pretty(2)
+    }
+}
+
+struct ExportCommand {}
+impl Command for ExportCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Export private key for an individual wallet addresses.");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
+        h.push("Note: To backup the whole wallet, use the 'seed' command instead");
+        h.push("Usage:");
+        h.push("export [--format=json|zecwallet] [z-address]");
+        h.push("");
+        h.push("If no address is passed, private key for all addresses in the wallet are exported.");
+        h.push("The 'zecwallet' format is the layout other light wallets import when migrating:");
+        h.push("{ wallet_version, seed, birthday, keys: [{address, private_key, viewing_key}] }");
+        h.push("The seed is only included when all addresses are exported. If the wallet uses a BIP-39 passphrase,");
+        h.push("'passphrase_required' is true, and the seed can only be restored with it. 'json' is the default.");
+        h.push("");
+        h.push("Example:");
+        h.push("export zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d");
+        h.push("export --format=zecwallet");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Export private key for wallet addresses".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let format = args.iter().find_map(|a| a.strip_prefix("--format=")).unwrap_or("json");
+        let args = args.iter().filter(|a| !a.starts_with("--format=")).collect::<Vec<_>>();
+        if args.len() > 1 {
+            return self.help();
+        }
+
+        let address = args.first().map(|a| a.to_string());
+        match format {
+            "json"      => lightclient.do_export(address),
+            "zecwallet" => lightclient.do_export_zecwallet(address),
+            _           => return object!{ "error" => format!("Unknown format '{}', use json or zecwallet", format) }.pretty(2),
+        }.unwrap_or_else(|e| object!{ "error" => e }).pretty(2)
+    }
+}
+
+struct ExportViewingCommand {}
+impl Command for ExportViewingCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Export the viewing key of a z-address, which gives read-only access to it.");
+        h.push("The spending key is never exported, and the wallet doesn't have to be unlocked.");
+        h.push("Usage:");
+        h.push("export-viewing z-address");
+        h.push("");
+        h.push("Example:");
+        h.push("export-viewing zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Export the viewing key of a z-address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        match lightclient.do_export_viewing_key(args[0]) {
+            Ok(vk) => object!{ "address" => args[0], "viewing_key" => vk },
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct EncryptCommand {}
+impl Command for EncryptCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Encrypt the wallet with a password");
+        h.push("Note 1: This will encrypt the seed and the private keys.");
+        h.push("        Use 'unlock' to temporarily unlock the wallet for spending or 'decrypt' ");
+        h.push("        to permanatly remove the encryption");
+        h.push("Note 2: If you forget the password, the only way to recover the wallet is to restore");
+        h.push("        from the seed phrase.");
+        h.push("Usage:");
+        h.push("encrypt password");
+        h.push("");
+        h.push("Example:");
+        h.push("encrypt my_strong_password");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Encrypt the wallet with a password".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        let passwd = args[0].to_string();
+
+        match lightclient.wallet.write().unwrap().encrypt(passwd) {
+            Ok(_)  => object!{ "result" => "success" },
+            Err(e) => object!{
+                "result" => "error",
+                "error"  => e.to_string()
+            }
+        }.pretty(2)
+    }
+}
+
+struct ChangePasswordCommand {}
+impl Command for ChangePasswordCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Change the password of an encrypted wallet");
+        h.push("Usage:");
+        h.push("changepassword old_password new_password");
+        h.push("");
+        h.push("The keys are re-encrypted with the new password without decrypting the wallet, so unlike 'decrypt' followed by");
+        h.push("'encrypt', the unencrypted keys are never written to disk. The wallet is saved with the new password right away.");
+        h.push("If the old password is wrong, or the wallet can't be saved, the wallet keeps its old password.");
+        h.push("Example:");
+        h.push("changepassword my_old_password my_new_strong_password");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Change the wallet's password".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 2 {
+            return self.help();
+        }
+
+        match lightclient.do_change_password(args[0].to_string(), args[1].to_string()) {
+            Ok(j)  => j,
+            Err(e) => object!{
+                "result" => "error",
+                "error"  => e
+            }
+        }.pretty(2)
+    }
+}
+
+struct DecryptCommand {}
+impl Command for DecryptCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Completely remove wallet encryption, storing the wallet in plaintext on disk");
+        h.push("Note 1: This will decrypt the seed and the private keys and store them on disk.");
+        h.push("        Use 'unlock' to temporarily unlock the wallet for spending");
+        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
+        h.push("        from the seed phrase.");
+        h.push("Usage:");
+        h.push("decrypt password");
+        h.push("");
+        h.push("Example:");
+        h.push("decrypt my_strong_password");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Completely remove wallet encryption".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        let passwd = args[0].to_string();
+
+        match lightclient.wallet.write().unwrap().remove_encryption(passwd) {
+            Ok(_)  => object!{ "result" => "success" },
+            Err(e) => object!{
+                "result" => "error",
+                "error"  => e.to_string()
+            }
+        }.pretty(2)
+    }
+}
+
+
+struct UnlockCommand {}
+impl Command for UnlockCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Unlock the wallet's encryption in memory, allowing spending from this wallet.");
+        h.push("Note 1: This will decrypt spending keys in memory only. The wallet remains encrypted on disk");
+        h.push("        Use 'decrypt' to remove the encryption permanatly.");
+        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
+        h.push("        from the seed phrase.");
+        h.push("Usage:");
+        h.push("unlock password [timeout]");
+        h.push("");
+        h.push("If a timeout (in seconds) is given, the wallet is automatically locked again once it has gone");
+        h.push("that long without spending.");
+        h.push("Example:");
+        h.push("unlock my_strong_password 300");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Unlock wallet encryption for spending".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() < 1 || args.len() > 2 {
+            return self.help();
+        }
+
+        let passwd = args[0].to_string();
+
+        let timeout = match args.get(1).map(|t| t.parse::<u64>()) {
+            None         => None,
+            Some(Ok(t))  => Some(std::time::Duration::from_secs(t)),
+            Some(Err(e)) => return format!("Couldn't parse timeout: {}\n{}", e, self.help())
+        };
+
+        let result = lightclient.wallet.write().unwrap().unlock(passwd);
+        if let (Ok(_), Some(timeout)) = (&result, timeout) {
+            let generation = lightclient.wallet.read().unwrap().set_auto_lock(timeout);
+            LightWallet::start_auto_lock_timer(lightclient.wallet.clone(), generation);
+        }
+
+        match result {
+            Ok(_)  => object!{ "result" => "success" },
+            Err(e) => object!{
+                "result" => "error",
+                "error"  => e.to_string()
+            }
+        }.pretty(2)
+    }
+}
+
+
+struct LockCommand {}
+impl Command for LockCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Lock a wallet that's been temporarily unlocked. You should already have encryption enabled.");
+        h.push("Note 1: This will remove all spending keys from memory. The wallet remains encrypted on disk");
+        h.push("Note 2: If you've forgotten the password, the only way to recover the wallet is to restore");
+        h.push("        from the seed phrase.");
+        h.push("Usage:");
+        h.push("lock");
+        h.push("");
+        h.push("Example:");
+        h.push("lock");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Lock a wallet that's been temporarily unlocked".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 0 {
+            let mut h = vec![];
+            h.push("Extra arguments to lock. Did you mean 'encrypt'?");
+            h.push("");
+
+            return format!("{}\n{}", h.join("\n"), self.help());
+        }
+
+        match lightclient.wallet.write().unwrap().lock() {
+            Ok(_)  => object!{ "result" => "success" },
+            Err(e) => object!{
+                "result" => "error",
+                "error"  => e.to_string()
+            }
+        }.pretty(2)
+    }
+}
+
+
+struct SendCommand {}
+impl Command for SendCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Send ARRR to a given address(es)");
+        h.push("Usage:");
+        h.push("send '{'input': <address>, 'output': [{'address': <address>, 'amount': <amount in zatoshis>, 'memo': <optional memo>}, ...], 'dryrun': <optional true/false>, 'splitmemo': <optional true/false>, 'strategy': <optional strategy>, 'from_notes': [<optional outpoint>, ...]}");
+        h.push("");
+        h.push("NOTE: The fee is additionally deducted from your balance. It is the wallet's default fee (see 'setfee'), unless 'fee' is given in zatoshis.");
+        h.push("With 'fee_from_recipients': true, the fee is taken out of the outputs instead, so exactly the sum of the outputs leaves the wallet.");
+        h.push("Each output pays fee * amount / total, rounded down, and the zatoshis lost to rounding are taken from the largest output.");
+        h.push("'strategy' picks the notes to spend, and is one of 'largestfirst' (the default), 'fewestinputs' or 'oldestfirst'.");
+        h.push("'from_notes' is a list of note outpoints (as shown by the 'notes' command) to spend, instead of letting the wallet pick the notes.");
+        h.push("Memos longer than 512 bytes are rejected, unless 'splitmemo' is true, in which case they are split across multiple outputs to the same address.");
+        h.push("Instead of 'amount' in zatoshis, an output can have 'amount_arrr' in ARRR, as a string or a number with up to 8 decimal places, like \"1.5\".");
+        h.push("'memo_encoding' is how the memos are given: 'utf8' (the default), 'hex' or 'base64', for binary memos. It can also be set on a single output.");
+        h.push("Outputs to z-addresses that have no 'memo' get the wallet's default memo (see 'setdefaultmemo'), if there is one.");
+        h.push("An output's own 'memo' always wins over the default memo, and 'memo': null sends no memo at all, even with a default memo.");
+        h.push("If 'dryrun' is true, the transaction is built and signed but not broadcast. The raw transaction, fee and inputs are returned instead.");
+        h.push("'account' is the account to send from (default 0). If 'input' is left out, the account's first z-address is used.");
+        h.push("'input' can also be an array of addresses, whose funds are pooled for the send, or \"*\" for all the account's addresses.");
+        h.push("The first address of the array is the source address that 'change_to_source' sends the change back to.");
+        h.push("PRIVACY: spending from several addresses in one transaction links them. Transparent inputs are public, so anyone");
+        h.push("can see that those addresses have the same owner. \"*\" includes the transparent addresses, and spends all their funds.");
+        h.push("Shielded inputs don't show on chain, but someone with the viewing key of one of the addresses sees it pay for more");
+        h.push("than its own notes cover.");
+        h.push("If the outputs add up to more than the limit set with 'setlimit', 'confirm_large': true is needed to send them.");
+        h.push("The change goes to the address of the first note spent. With 'change_to_source': true, it goes back to the 'input' address");
+        h.push("instead. They only differ when notes received at other diversified addresses of the same key are spent.");
+        h.push("'idempotency_key' is an optional string that makes retries safe: repeating a send with the same key returns the original");
+        h.push("txid instead of sending again, for as long as the key is remembered (see 'setlimit idempotency').");
+        h.push("");
+        h.push("A payment URI can be given instead of the JSON. The funds are sent from the given input address, or the first z-address if there is none:");
+        h.push("send pirate:<address>?amount=<amount in ARRR>&memo=<optional url encoded memo> [input address]");
+        h.push("Example:");
+        h.push("send '{\"input\":\"ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d\", \"output\": [{ \"address\": \"ztestsapling1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d\", \"amount\": 200000, \"memo\": \"Hello from the command line\"}]}'");
+        h.push("");
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Send ARRR to the given address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        // Parse the args.
+        // A single argument in the form of a JSON string that is "{input: address, output: [{address: address, value: value, memo: memo},...], fee: fee}"
+
+        // 1 - Destination address. T or Z address
+        let is_uri = args.len() > 0 && args[0].to_lowercase().starts_with(PAYMENT_URI_SCHEME);
+        if args.len() != 1 && !(is_uri && args.len() == 2) {
+            return self.help();
+        }
+
+        // Check for a single argument that can be parsed as JSON, or a payment URI
+        let arg_list = args[0];
+
+        let json_args = if is_uri {
+            let send_args = match LightClient::parse_payment_uri(arg_list) {
+                Ok(a)  => a,
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            };
+
+            let from = match args.get(1) {
+                Some(a) => a.to_string(),
+                None    => match lightclient.do_address()["z_addresses"][0].as_str() {
+                    Some(a) => a.to_string(),
+                    None    => return format!("Error: {}\n{}", "No z-address to send from", self.help())
+                }
+            };
+
+            let mut output = object!{
+                "address" => send_args.address,
+                "amount"  => send_args.amount,
+            };
+            if let Some(m) = send_args.memo {
+                output["memo"] = m.into();
+            }
+
+            object!{
+                "input"  => from,
+                "output" => array![output],
+            }
+        } else {
+            match json::parse(&arg_list) {
+                Ok(j)  => j,
+                Err(e) => {
+                    let es = format!("Couldn't understand JSON: {}", e);
+                    return format!("{}\n{}", es, self.help());
+                }
+            }
+        };
+
+        //Check for a fee key and convert to u64
+        let fee: u64 = if json_args.has_key("fee") {
+            match json_args["fee"].as_u64() {
+                Some(f) => f.clone(),
+                None => lightclient.default_fee()
+            }
+        } else {
+            lightclient.default_fee()
+        };
+
+        let dry_run = json_args["dryrun"].as_bool().unwrap_or(false);
+        let memo_encoding = json_args["memo_encoding"].as_str().unwrap_or("utf8");
+        let confirm_large = json_args["confirm_large"].as_bool().unwrap_or(false);
+        let split_memo = json_args["splitmemo"].as_bool().unwrap_or(false);
+        let change_to_source = json_args["change_to_source"].as_bool().unwrap_or(false);
+        let fee_from_recipients = json_args["fee_from_recipients"].as_bool().unwrap_or(false);
+
+        let idempotency_key = if json_args.has_key("idempotency_key") {
+            match json_args["idempotency_key"].as_str() {
+                Some(k) => Some(k.to_string()),
+                None    => return format!("Error: 'idempotency_key' should be a string\n{}", self.help())
+            }
+        } else {
+            None
+        };
+
+        //Check for a note selection strategy, otherwise the client's default is used
+        let strategy = match json_args["strategy"].as_str() {
+            None    => None,
+            Some(s) => match NoteSelectionStrategy::from_str(s) {
+                Some(strategy) => Some(strategy),
+                None => return format!("Error: Unknown note selection strategy '{}'\n{}", s, self.help())
+            }
+        };
+
+        //Check for an explicit list of notes to spend, by their outpoints
+        let from_notes = if json_args.has_key("from_notes") {
+            if !json_args["from_notes"].is_array() {
+                return format!("Error: 'from_notes' should be an array of note outpoints\n{}", self.help());
+            }
+
+            let maybe_outpoints = json_args["from_notes"].members()
+                .map(|j| match j.as_str() {
+                    Some(s) => NoteOutpoint::from_str(s),
+                    None    => Err(format!("Note outpoint should be a string, found {}", j))
+                })
+                .collect::<Result<Vec<_>, String>>();
+
+            match maybe_outpoints {
+                Ok(o)  => Some(o),
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            }
+        } else {
+            None
+        };
+
+        //Check for an account to send from. Without an input address, its first z address is used
+        let account = if json_args.has_key("account") {
+            match json_args["account"].as_usize() {
+                Some(a) => Some(a),
+                None    => return format!("Error: 'account' should be an account number\n{}", self.help())
+            }
+        } else {
+            None
+        };
+
+        //Check for the input address. An array of addresses pools their funds, and "*" pools all the account's addresses
+        let from = if json_args["input"].is_array() {
+            let maybe_inputs = json_args["input"].members()
+                .map(|j| j.as_str().map(|a| a.to_string()).ok_or(format!("Input address should be a string, found {}", j)))
+                .collect::<Result<Vec<String>, String>>();
+
+            match maybe_inputs {
+                Ok(i) if i.is_empty() => return format!("Error: {}\n{}", "Need input address", self.help()),
+                Ok(i)  => i.join(","),
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            }
+        } else if json_args["input"] == "*" {
+            match lightclient.do_account_address(account.unwrap_or(0)) {
+                Ok(j)  => j["z_addresses"].members().chain(j["t_addresses"].members())
+                            .map(|a| a.as_str().unwrap().to_string())
+                            .collect::<Vec<String>>()
+                            .join(","),
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            }
+        } else if json_args.has_key("input") {
+            match json_args["input"].as_str() {
+                Some(a) => a.to_string(),
+                None    => return format!("Error: 'input' should be an address, an array of addresses or \"*\"\n{}", self.help())
+            }
+        } else if let Some(account) = account {
+            match lightclient.do_account_address(account) {
+                Ok(j)  => match j["z_addresses"][0].as_str() {
+                    Some(a) => a.to_string(),
+                    None    => return format!("Error: Account {} has no z-address\n{}", account, self.help())
+                },
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            }
+        } else {
+            return format!("Error: {}\n{}", "Need input address", self.help());
+        };
+        let from = from.as_str();
+
+        if let Some(account) = account {
+            for input in LightWallet::split_inputs(from) {
+                if lightclient.wallet.read().unwrap().account_of_address(input) != Some(account) {
+                    return format!("Error: {} is not an address of account {}\n{}", input, account, self.help());
+                }
+            }
+        }
+
+        //Check for output key
+        let json_tos = if json_args.has_key("output") {
+            &json_args["output"]
+        } else {
+            return format!("Error: {}\n{}", "Need output address", self.help());
+        };
+
+        //Check output is in the form of an array
+        if !json_tos.is_array() {
+            return format!("Couldn't parse argument as array\n{}", self.help());
+        }
+
+        //Check array for manadantory address and amount keys. The amount is either 'amount' in zatoshis or 'amount_arrr' in ARRR
+        let maybe_send_args = json_tos.members().map( |j| {
+            if !j.has_key("address") || !(j.has_key("amount") || j.has_key("amount_arrr")) {
+                Err(format!("Need 'address' and 'amount' (or 'amount_arrr')\n"))
+            } else if j.has_key("amount") && j.has_key("amount_arrr") {
+                Err(format!("Give either 'amount' in zatoshis or 'amount_arrr' in ARRR, not both\n"))
+            } else {
+                let amount = if j.has_key("amount_arrr") {
+                    Some(arrr_json_to_zatoshis(&j["amount_arrr"])?)
+                } else {
+                    match j["amount"].as_str() {
+                        Some("entire-verified-zbalance") => lightclient.wallet.read().unwrap().verified_zbalance(None).checked_sub(fee),
+                        _ => match j["amount"].as_u64() {
+                            Some(amt) => Some(amt),
+                            None      => return Err(format!("'amount' is a whole number of zatoshis, found {}. Use 'amount_arrr' for an amount in ARRR\n", j["amount"]))
+                        }
+                    }
+                };
+
+                // Binary memos are decoded into the "0x" hex form. A null memo means no memo, even if
+                // there's a default memo, which outputs without a 'memo' get
+                let memo = if !j.has_key("memo") {
+                    None
+                } else if j["memo"].is_null() {
+                    Some(None)
+                } else {
+                    match j["memo"].as_str() {
+                        Some(m) => Some(Some(LightWallet::decode_memo(m, j["memo_encoding"].as_str().unwrap_or(memo_encoding))?)),
+                        None    => return Err(format!("'memo' should be a string, or null for no memo, found {}\n", j["memo"]))
+                    }
+                };
+
+                let address = j["address"].as_str().unwrap().to_string();
+                let memo = lightclient.memo_for_output(&address, memo);
+
+                match amount {
+                    Some(amt) => Ok((address, amt, memo)),
+                    None => Err(format!("Not enough in wallet to pay transaction fee"))
+                }
+            }
+        }).collect::<Result<Vec<(String, u64, Option<String>)>, String>>();
+
+        let send_args = match maybe_send_args {
+            Ok(a) =>  a.clone(),
+            Err(s) => { return format!("Error: {}\n{}", s, self.help()); }
+        };
+
+        let send_args = if fee_from_recipients {
+            match LightClient::deduct_fee_from_outputs(send_args, fee) {
+                Ok(a)  => a,
+                Err(e) => return format!("Error: {}\n{}", e, self.help())
+            }
+        } else {
+            send_args
+        };
+
+        // Memos that don't fit in a single output are either split across several outputs to
+        // the same address (the first output carries the amount), or rejected
+        let mut split_send_args = vec![];
+        for (addr, amt, memo) in send_args {
+            match memo {
+                Some(m) if m.as_bytes().len() > MAX_MEMO_BYTES && !m.to_lowercase().starts_with("0x") => {
+                    if !split_memo {
+                        let e = format!("Memo to {} is {} bytes, but the maximum is {} bytes. Use 'splitmemo': true to split it across multiple outputs",
+                                        addr, m.as_bytes().len(), MAX_MEMO_BYTES);
+                        return format!("Error: {}\n{}", e, self.help());
+                    }
+
+                    for (i, chunk) in LightWallet::split_memo(&m).into_iter().enumerate() {
+                        split_send_args.push((addr.clone(), if i == 0 { amt } else { 0 }, Some(chunk)));
+                    }
+                },
+                _ => split_send_args.push((addr, amt, memo)),
+            }
+        }
+        let send_args = split_send_args;
+
+
+        match lightclient.do_sync(true) {
+            Ok(_) => {
+                // Convert to the right format. String -> &str.
+                let tos = send_args.iter().map(|(a, v, m)| (a.as_str(), *v, m.clone()) ).collect::<Vec<_>>();
+                let options = SendOptions { strategy, from_notes, confirm_large, change_to_source, ..SendOptions::default() };
+                if dry_run {
+                    return match lightclient.do_send_dry_run(from, tos, &fee, options) {
+                        Ok(j)  => j,
+                        Err(e) => object!{ "error" => e }
+                    }.pretty(2);
+                }
+
+                let result = match idempotency_key {
+                    Some(key) => lightclient.do_send_idempotent(&key, from, tos, &fee, options),
+                    None      => lightclient.do_send(from, tos, &fee, options),
+                };
+
+                match result {
+                    Ok(j)  => j,
+                    Err(e) => object!{ "error" => e }
+                }.pretty(2)
+            },
+            Err(e) => e
+        }
+    }
+}
+
+struct SetLimitCommand {}
+impl Command for SetLimitCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show or change the guards against mistaken sends");
+        h.push("Usage:");
+        h.push("setlimit");
+        h.push("setlimit max <amount in zatoshis | off>");
+        h.push("setlimit taddrmemo <on | off>");
+        h.push("setlimit idempotency <seconds>");
+        h.push("");
+        h.push("'max' refuses sends whose outputs add up to more than the amount, unless the send has 'confirm_large': true.");
+        h.push("'taddrmemo' refuses sends with a memo to a transparent address, which would otherwise drop the memo. It is on by default.");
+        h.push("'idempotency' is how long a send's 'idempotency_key' is remembered. The default is 86400 (24 hours).");
+        h.push("The guards are not saved with the wallet.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show or change the send limit and memo guard".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        match args {
+            [] => {},
+            ["max", "off"] => lightclient.set_max_send_zatoshis(None),
+            ["max", amount] => match amount.parse::<u64>() {
+                Ok(a)  => lightclient.set_max_send_zatoshis(Some(a)),
+                Err(_) => return format!("Error: '{}' is not an amount in zatoshis\n{}", amount, self.help()),
+            },
+            ["taddrmemo", "on"]  => lightclient.set_reject_taddr_memos(true),
+            ["taddrmemo", "off"] => lightclient.set_reject_taddr_memos(false),
+            ["idempotency", secs] => match secs.parse::<u64>() {
+                Ok(s)  => lightclient.set_idempotency_window(s),
+                Err(_) => return format!("Error: '{}' is not a number of seconds\n{}", secs, self.help()),
+            },
+            _ => return self.help(),
+        }
+
+        lightclient.do_send_limits().pretty(2)
+    }
+}
+
+struct SetFeeCommand {}
+impl Command for SetFeeCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show or change the fee used by sends that don't give one");
+        h.push("Usage:");
+        h.push("setfee");
+        h.push("setfee <fee in zatoshis | default> [confirm]");
+        h.push("");
+        h.push("The fee is saved in the wallet. 'default' goes back to the built-in default fee.");
+        h.push("A fee of 0, or more than 1000000 zatoshis (0.01 ARRR), is refused unless 'confirm' is given.");
+        h.push("Example:");
+        h.push("setfee 10000");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show or change the default fee".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let (fee, confirm) = match args {
+            []                   => return lightclient.do_default_fee().pretty(2),
+            [fee]                => (*fee, false),
+            [fee, "confirm"]     => (*fee, true),
+            _                    => return self.help(),
+        };
+
+        let fee = match fee {
+            "default" => None,
+            f         => match f.parse::<u64>() {
+                Ok(f)  => Some(f),
+                Err(_) => return format!("Error: '{}' is not a fee in zatoshis\n{}", f, self.help()),
+            }
+        };
+
+        match lightclient.do_set_default_fee(fee, confirm) {
+            Ok(j)  => j.pretty(2),
+            Err(e) => object!{ "error" => e }.pretty(2)
+        }
+    }
+}
+
+struct SetDefaultMemoCommand {}
+impl Command for SetDefaultMemoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show or change the memo attached to sends that don't give one");
+        h.push("Usage:");
+        h.push("setdefaultmemo");
+        h.push("setdefaultmemo <memo | none>");
+        h.push("");
+        h.push("The memo is saved in the wallet, and attached to every output to a z-address that has no 'memo' of its own.");
+        h.push("'none' goes back to sending no memo. Without a default memo, outputs without a memo have an empty memo.");
+        h.push("In 'send', an output's own 'memo' wins over the default memo, and 'memo': null sends no memo even with a default memo.");
+        h.push("Example:");
+        h.push("setdefaultmemo \"Sent from my wallet\"");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show or change the default memo".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let memo = match args {
+            []       => return lightclient.do_default_memo().pretty(2),
+            ["none"] => None,
+            [memo]   => Some(memo.to_string()),
+            _        => return self.help(),
+        };
+
+        match lightclient.do_set_default_memo(memo) {
+            Ok(j)  => j.pretty(2),
+            Err(e) => object!{ "error" => e }.pretty(2)
+        }
+    }
+}
+
+struct SetDustThresholdCommand {}
+impl Command for SetDustThresholdCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show or change the value below which notes are dust");
+        h.push("Usage:");
+        h.push("setdustthreshold");
+        h.push("setdustthreshold <zatoshis | off>");
+        h.push("");
+        h.push("Sends only spend dust notes when the other notes don't cover the amount, so dust doesn't make");
+        h.push("transactions bigger. 'notes' flags dust notes. 'consolidate' and sends with 'from_notes' spend the notes");
+        h.push("they're given either way, so dust can still be swept up on purpose. The threshold is off by default.");
+        h.push("Example:");
+        h.push("setdustthreshold 1000");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show or change the dust threshold for note selection".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        match args {
+            []      => {},
+            ["off"] => lightclient.set_min_note_value(0),
+            [value] => match value.parse::<u64>() {
+                Ok(v)  => lightclient.set_min_note_value(v),
+                Err(_) => return format!("Error: '{}' is not an amount in zatoshis\n{}", value, self.help()),
+            },
+            _       => return self.help(),
+        }
+
+        let min = lightclient.get_min_note_value();
+        object!{
+            "dust_threshold" => if min == 0 { None } else { Some(min) },
+        }.pretty(2)
+    }
+}
+
+struct EncryptMessageCommand {}
+impl Command for EncryptMessageCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Encrypt a message to a z-address, to send to its owner outside of the chain");
+        h.push("Usage:");
+        h.push("encryptmessage <z-address> \"<message>\"");
+        h.push("");
+        h.push("This is not a transaction. Nothing is broadcast and there is no fee.");
+        h.push("The message is encrypted like a memo, so it can be at most 512 bytes. The owner of the address can read it with 'decryptmessage'.");
+        h.push("Example:");
+        h.push("encryptmessage zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d \"Meet at the dock\"");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Encrypt a message to a z-address, without a transaction".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 2 {
+            return self.help();
+        }
+
+        match lightclient.encrypt_message(args[0], args[1]) {
+            Ok(m)  => object!{ "encrypted_message" => m },
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct DecryptMessageCommand {}
+impl Command for DecryptMessageCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Decrypt a message made by 'encryptmessage' to one of this wallet's z-addresses");
+        h.push("Usage:");
+        h.push("decryptmessage <encrypted message>");
+        h.push("");
+        h.push("This only reads the message. Nothing is sent to the server.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Decrypt a message made by 'encryptmessage'".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        match lightclient.decrypt_message(args[0]) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct SignMessageCommand {}
+impl Command for SignMessageCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Sign a message with the key of one of the wallet's addresses, to prove that you hold the address");
+        h.push("Usage:");
+        h.push("signmessage <address> \"message\"");
+        h.push("");
+        h.push("A t-address signs with its private key, the same way as the full node's 'signmessage', which proves that you can spend from it.");
+        h.push("A z-address makes a viewing key proof instead, with the address's incoming viewing key. It proves that you can see");
+        h.push("the address's funds, which a viewing key holder can also do, not that you can spend them.");
+        h.push("Either can be checked by anyone with 'verifymessage'.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Sign a message with an address's key".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 2 {
+            return self.help();
+        }
+
+        match lightclient.do_sign_message(args[0], args[1]) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct VerifyMessageCommand {}
+impl Command for VerifyMessageCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Check that a message was signed with the key of an address");
+        h.push("Usage:");
+        h.push("verifymessage <address> \"message\" <signature>");
+        h.push("");
+        h.push("The signature is the one made by 'signmessage'. The address doesn't have to be in this wallet.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Verify a message signed with signmessage".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 3 {
+            return self.help();
+        }
+
+        match lightclient.do_verify_message(args[0], args[1], args[2]) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct MemoCommand {}
+impl Command for MemoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the memo of an incoming transaction");
+        h.push("Usage:");
+        h.push("memo <txid>");
+        h.push("");
+        h.push("Memos that aren't valid UTF-8 are shown as hex.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the memo of an incoming transaction".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        match lightclient.do_get_memo(args[0]) {
+            Ok(memo) => object!{ "txid" => args[0], "memo" => memo },
+            Err(e)   => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct ListReceivedByAddressCommand {}
+impl Command for ListReceivedByAddressCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("Show the total received by each z-address in the wallet, and the number of transactions that paid it");
+        h.push("Usage:");
+        h.push("listreceivedbyaddress [min_height] [max_height]");
+        h.push("");
+        h.push("Only mined notes the wallet can decrypt are counted. Transparent receipts are not included.");
+        h.push("Change is left out: a note is change if its transaction also spent a note of the same key,");
+        h.push("so sends between two addresses of the same key count as change too.");
+        h.push("The height range is inclusive. Leave it out to count all transactions.");
+        h.push("Example:");
+        h.push("listreceivedbyaddress 1000000 1100000");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the total received by each address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() > 2 {
+            return format!("Didn't understand arguments\n{}", self.help());
+        }
+
+        let heights = match args.iter().map(|a| a.parse::<u64>()).collect::<Result<Vec<u64>, _>>() {
+            Ok(h) => h,
+            Err(_) => return format!("Couldn't parse the heights as numbers\n{}", self.help())
+        };
+
+        let min_height = heights.get(0).map(|h| *h);
+        let max_height = heights.get(1).map(|h| *h);
+        if let (Some(min), Some(max)) = (min_height, max_height) {
+            if min > max {
+                return format!("min_height {} is above max_height {}", min, max);
+            }
+        }
+
+        format!("{}", lightclient.do_received_by_address(min_height, max_height).pretty(2))
+    }
+}
+
+struct SearchMemoCommand {}
+impl Command for SearchMemoCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Find the transactions with a memo that contains some text");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
+        h.push("Usage:");
+        h.push("searchmemo <text> [--hex]");
+        h.push("");
+        h.push("Incoming and outgoing memos are searched, ignoring case. Memos that aren't valid UTF-8 are");
+        h.push("skipped, unless --hex is given, in which case they are searched and shown as hex.");
+        h.push("Example:");
+        h.push("searchmemo INV-2041");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Find the transactions with a memo that contains some text".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let include_hex = args.contains(&"--hex");
+        let words = args.iter().filter(|a| **a != "--hex").cloned().collect::<Vec<&str>>();
+        if words.is_empty() {
+            return self.help();
+        }
+
+        match lightclient.do_search_memo(&words.join(" "), include_hex) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct TransactionCommand {}
+impl Command for TransactionCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show everything the wallet knows about one transaction");
+        h.push("Usage:");
+        h.push("transaction <txid> [fetch]");
+        h.push("");
+        h.push("Shows the outputs with their memos (also as hex and base64), the fee, block height and time, the notes and utxos");
+        h.push("of this wallet that it spent, and whether it is incoming, outgoing or a send to yourself.");
+        h.push("If the transaction isn't in the wallet and you include 'fetch', it is downloaded from the server and the outputs");
+        h.push("this wallet's keys can decrypt are shown. It is not added to the wallet.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the details of a transaction".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() < 1 || args.len() > 2 {
+            return self.help();
+        }
+
+        let fetch = match args.get(1) {
+            None           => false,
+            Some(&"fetch") => true,
+            Some(a)        => return format!("Couldn't understand argument '{}'\n{}", a, self.help()),
+        };
+
+        match lightclient.do_get_transaction(args[0], fetch) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct PaymentUriCommand {}
+impl Command for PaymentUriCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Make or read a payment URI, like the ones in payment QR codes");
+        h.push("Usage:");
+        h.push("paymenturi make <address> <amount in zatoshis> [memo]");
+        h.push("paymenturi parse <uri>");
+        h.push("");
+        h.push("Payment URIs look like pirate:<address>?amount=<amount in ARRR>&memo=<url encoded memo>. Use 'send <uri>' to pay one.");
+        h.push("Example:");
+        h.push("paymenturi make zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 150000000 \"Thanks!\"");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Make or read a payment URI".to_string()
+    }
+
+    fn exec(&self, args: &[&str], _lightclient: &LightClient) -> String {
+        match args {
+            ["make", address, amount] | ["make", address, amount, _] => {
+                let amount = match amount.parse::<u64>() {
+                    Ok(a)  => a,
+                    Err(e) => return format!("Couldn't parse amount: {}\n{}", e, self.help())
+                };
+
+                match LightClient::make_payment_uri(address, amount, args.get(3).map(|m| *m)) {
+                    Ok(uri) => object!{ "uri" => uri },
+                    Err(e)  => object!{ "error" => e }
+                }.pretty(2)
+            },
+            ["parse", uri] => {
+                match LightClient::parse_payment_uri(uri) {
+                    Ok(a)  => object!{
+                        "address" => a.address,
+                        "amount"  => a.amount,
+                        "memo"    => a.memo,
+                    },
+                    Err(e) => object!{ "error" => e }
+                }.pretty(2)
+            },
+            _ => self.help()
+        }
+    }
+}
+
+struct ShieldCommand {}
+impl Command for ShieldCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Move all the confirmed transparent funds in the wallet into a z-address");
+        h.push("Usage:");
+        h.push("shield [z-address]");
+        h.push("");
+        h.push("If no z-address is given, a new one is created. The fee is paid from the transparent funds.");
+        h.push("All the transparent addresses are spent in one transaction, so the fee is only paid once, but anyone");
+        h.push("can see on chain that those addresses belong together.");
+        h.push("Example:");
+        h.push("shield zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Shield transparent funds into a z-address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() > 1 {
+            return self.help();
+        }
+
+        match lightclient.do_sync(true) {
+            Ok(_) => {
+                match lightclient.do_shield(args.get(0).map(|a| *a)) {
+                    Ok(j)  => j,
+                    Err(e) => object!{ "error" => e }
+                }.pretty(2)
+            },
+            Err(e) => e
+        }
+    }
+}
+
+struct ConsolidateCommand {}
+impl Command for ConsolidateCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Merge the smallest notes of a z-address into a single note, by sending them back to the same address");
+        h.push("Usage:");
+        h.push("consolidate <z-address> [max_inputs]");
+        h.push("");
+        h.push("Up to max_inputs (default 50) of the smallest spendable notes are merged. The fee is paid from the merged notes.");
+        h.push("Example:");
+        h.push("consolidate zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d 20");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Merge many small notes into one".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() < 1 || args.len() > 2 {
+            return self.help();
+        }
+
+        let max_inputs = if args.len() == 2 {
+            match args[1].parse::<usize>() {
+                Ok(n)  => n,
+                Err(e) => return format!("Couldn't parse max_inputs: {}\n{}", e, self.help())
+            }
+        } else {
+            50
+        };
+
+        match lightclient.do_sync(true) {
+            Ok(_) => {
+                match lightclient.do_consolidate(args[0], max_inputs) {
+                    Ok(j)  => j,
+                    Err(e) => object!{ "error" => e }
+                }.pretty(2)
+            },
+            Err(e) => e
+        }
+    }
+}
+
+struct SweepCommand {}
+impl Command for SweepCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Send the entire spendable balance of one address to another, less the fee");
+        h.push("Usage:");
+        h.push("sweep <from address> <to address> [confirm]");
+        h.push("");
+        h.push("All spendable notes of a z-address, or all confirmed utxos of a t-address, are spent in a single transaction.");
+        h.push("Add 'confirm' to sweep more than the limit set with 'setlimit maxsend'.");
+        h.push("Example:");
+        h.push("sweep zs1x65nq4dgp0qfywgxcwk9n0fvm4fysmapgr2q00p85ju252h6l7mmxu2jg9cqqhtvzd69jwhgv8d zs1va5902apnzlhdu0pw9r9q7ca8s4vnsrp2alr6xndt69jnepn2v2qrj9vg3wfcnjyks5pg65g9dc");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Move all of an address's funds to another address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() < 2 || args.len() > 3 {
+            return self.help();
+        }
+
+        let confirm = match args.get(2) {
+            None            => false,
+            Some(&"confirm") => true,
+            Some(a)         => return format!("Unknown option '{}'\n{}", a, self.help()),
+        };
+
+        match lightclient.do_sync(true) {
+            Ok(_) => {
+                match lightclient.do_sweep(args[0], args[1], confirm) {
+                    Ok(j)  => j,
+                    Err(e) => object!{ "error" => e }
+                }.pretty(2)
+            },
+            Err(e) => e
+        }
+    }
+}
+
+struct DecodeCommand {}
+impl Command for DecodeCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Decode a raw transaction without importing or broadcasting it");
+        h.push("Usage:");
+        h.push("decode <raw tx hex>");
+        h.push("");
+        h.push("Shows the version, lock time, expiry height, transparent inputs and outputs, the number of shielded");
+        h.push("spends and outputs, and the value balance. This doesn't use the wallet's keys or the server, so it works offline.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Decode a raw transaction".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        match lightclient.do_decode_tx(args[0]) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct SpendPreimageCommand {}
+impl Command for SpendPreimageCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show what an external signer, like a hardware wallet, needs to sign and prove a send");
+        h.push("Usage:");
+        h.push("spendpreimage '{'input': <z-address>, 'output': [{'address': <address>, 'amount': <amount in zatoshis>, 'memo': <optional memo>}, ...], 'fee': <optional fee>}'");
+        h.push("");
+        h.push("For every note the send would spend, the note, its nullifier and commitment, its viewing key and its merkle path");
+        h.push("to the anchor are shown, along with the outputs and the change, which goes back to the input address.");
+        h.push("Notes are picked largest first. Nothing is signed or sent, so this works for watch-only and locked wallets.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the notes and witnesses an external signer needs for a send".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        let json_args = match json::parse(args[0]) {
+            Ok(j)  => j,
+            Err(e) => return format!("Couldn't understand JSON: {}\n{}", e, self.help())
+        };
+
+        let from = match json_args["input"].as_str() {
+            Some(f) => f,
+            None    => return format!("Error: Need input address\n{}", self.help())
+        };
+        let fee = json_args["fee"].as_u64().unwrap_or(lightclient.default_fee());
+
+        let maybe_tos = json_args["output"].members()
+            .map(|j| match (j["address"].as_str(), j["amount"].as_u64()) {
+                (Some(a), Some(v)) => Ok((a, v, j["memo"].as_str().map(|m| m.to_string()))),
+                _                  => Err(format!("Need 'address' and 'amount' in zatoshis for every output, found {}", j))
+            })
+            .collect::<Result<Vec<_>, String>>();
+
+        let tos = match maybe_tos {
+            Ok(t)  => t,
+            Err(e) => return format!("Error: {}\n{}", e, self.help())
+        };
+
+        match lightclient.do_spend_preimage(from, tos, &fee) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct BroadcastCommand {}
+impl Command for BroadcastCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Broadcast a raw, signed transaction to the network");
+        h.push("Usage:");
+        h.push("broadcast <raw tx hex>");
+        h.push("");
+        h.push("The transaction is checked to be well formed before it is sent to the server.");
+        h.push("Use 'send' with 'dryrun': true to create a raw transaction without broadcasting it.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Broadcast a raw transaction".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        match lightclient.do_broadcast(args[0]) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct VerifyWalletCommand {}
+impl Command for VerifyWalletCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Check that the wallet saves and loads cleanly, and that its notes can be spent");
+        h.push("Usage:");
+        h.push("verifywallet");
+        h.push("");
+        h.push("The wallet is serialized, read back and compared with the wallet in memory, and the wallet file");
+        h.push("is loaded. Shows the number of keys, transactions and notes, the size and sha256 of the file,");
+        h.push("and any unspent note whose witness is missing or out of date. 'ok' is true if nothing is wrong.");
+        h.push("Like 'save', this locks an encrypted wallet that is unlocked.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Check the wallet file and the wallet's notes for problems".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if !args.is_empty() {
+            return self.help();
+        }
 
-                match amount {
-                    Some(amt) => Ok((j["address"].as_str().unwrap().to_string().clone(), amt, j["memo"].as_str().map(|s| s.to_string().clone()))),
-                    None => Err(format!("Not enough in wallet to pay transaction fee"))
-                }
-            }
-        }).collect::<Result<Vec<(String, u64, Option<String>)>, String>>();
+        match lightclient.do_verify_wallet() {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
 
-        let send_args = match maybe_send_args {
-            Ok(a) =>  a.clone(),
-            Err(s) => { return format!("Error: {}\n{}", s, self.help()); }
-        };
+struct VerifyAddressesCommand {}
+impl Command for VerifyAddressesCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Check that every address in the wallet derives from the seed or from an imported key");
+        h.push("Usage:");
+        h.push("verifyaddresses");
+        h.push("");
+        h.push("Each address is re-derived from the seed at its HD index, or from the key it was imported with.");
+        h.push("Addresses that don't match are listed with the problem, and 'ok' is true if there are none.");
+        h.push("HD indexes below the highest one in use that have no address are listed in 'missing_hd_indexes'.");
+        h.push("This doesn't rescan. The wallet has to be unlocked.");
 
+        h.join("\n")
+    }
 
-        match lightclient.do_sync(true) {
-            Ok(_) => {
-                // Convert to the right format. String -> &str.
-                let tos = send_args.iter().map(|(a, v, m)| (a.as_str(), *v, m.clone()) ).collect::<Vec<_>>();
-                match lightclient.do_send(from, tos, &fee) {
-                    Ok(txid) => { object!{ "txid" => txid } },
-                    Err(e)   => { object!{ "error" => e } }
-                }.pretty(2)
-            },
-            Err(e) => e
+    fn short_help(&self) -> String {
+        "Check that every address derives from the seed or an imported key".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if !args.is_empty() {
+            return self.help();
         }
+
+        match lightclient.do_verify_addresses() {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
     }
 }
 
@@ -558,6 +2235,7 @@ impl Command for SaveCommand {
         h.push("");
         h.push("The wallet is saved to disk. The wallet is periodically saved to disk (and also saved upon exit)");
         h.push("but you can use this command to explicitly save it to disk");
+        h.push("When started with --no-save, the wallet is never saved.");
 
         h.join("\n")
     }
@@ -567,6 +2245,13 @@ impl Command for SaveCommand {
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        if !lightclient.config.persist {
+            return object!{
+                "result" => "skipped",
+                "error"  => "This client doesn't save the wallet to disk"
+            }.pretty(2);
+        }
+
         match lightclient.do_save() {
             Ok(_) => {
                 let r = object!{ "result" => "success" };
@@ -588,10 +2273,12 @@ impl Command for SeedCommand {
     fn help(&self) -> String {
         let mut h = vec![];
         h.push("Show the wallet's seed phrase");
+        h.push("This is the seed of account 0, the main wallet. The seed of another account is only shown by 'account new'.");
         h.push("Usage:");
         h.push("seed");
         h.push("");
         h.push("Your wallet is entirely recoverable from the seed phrase. Please save it carefully and don't share it with anyone");
+        h.push("If the wallet was created or restored with a BIP-39 passphrase, the passphrase is also needed to recover it, and is not shown here");
 
         h.join("\n")
     }
@@ -608,15 +2295,106 @@ impl Command for SeedCommand {
     }
 }
 
+struct TreeStateCommand {}
+impl Command for TreeStateCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the sapling note commitment tree at the wallet's last scanned block");
+        h.push("Usage:");
+        h.push("treestate");
+        h.push("");
+        h.push("Shows the height and hash of the block, the serialized commitment tree and its root, all hex encoded.");
+        h.push("The tree is the one the wallet built while syncing, so run 'sync' first to bring it up to date.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the wallet's sapling commitment tree".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        match lightclient.do_tree_state() {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
+struct StatsCommand {}
+impl Command for StatsCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show a summary of the wallet's health");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
+        h.push("Usage:");
+        h.push("stats");
+        h.push("");
+        h.push("Shows the number of notes (total, unspent, spendable and pending), the number of pending transactions,");
+        h.push("the size of the wallet file, when the wallet was last synced and the default fee. Use 'notes' to list the notes themselves.");
+        h.push("'total_fees_paid' adds up the fees of all the wallet's mined sends. 'unknown_fee_sends' counts the sends whose fee");
+        h.push("the wallet can't tell, because it didn't fund all of their inputs. Those aren't in the total.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show wallet statistics".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_wallet_stats().pretty(2)
+    }
+}
+
+struct SeedWordsCommand {}
+impl Command for SeedWordsCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Show the words of the wallet's seed phrase, each with its position in the phrase (starting at 1)");
+        h.push("Usage:");
+        h.push("seedwords");
+        h.push("");
+        h.push("Useful for checking that the seed phrase was written down, by asking for individual words.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Display the seed phrase's words with their positions".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        let words = lightclient.do_seed_words();
+        if words.is_empty() {
+            return object!{ "error" => "Wallet is locked" }.pretty(2);
+        }
+
+        json::JsonValue::Array(words.into_iter().map(|(index, word)| object!{
+            "index" => index,
+            "word"  => word,
+        }).collect()).pretty(2)
+    }
+}
+
 struct TransactionsCommand {}
 impl Command for TransactionsCommand {
     fn help(&self)  -> String {
         let mut h = vec![];
-        h.push("List all incoming and outgoing transactions from this wallet");
+        h.push("List all incoming and outgoing transactions from this wallet, newest first");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
         h.push("Usage:");
-        h.push("list [allmemos]");
+        h.push("list [allmemos] [includeraw] [offset] [limit]");
         h.push("");
-        h.push("If you include the 'allmemos' argument, all memos are returned in their raw hex format");
+        h.push("If you include the 'allmemos' argument, all memos are also returned in their raw form, as hex ('memohex') and base64 ('memobase64')");
+        h.push("If you include the 'includeraw' argument, every transaction has a 'rawtx' field with the full transaction as hex.");
+        h.push("It is null for transactions the wallet doesn't have the full transaction of.");
+        h.push("'fee' is the fee the transaction paid. It is null when the wallet didn't fund all of the transaction's inputs,");
+        h.push("like for incoming transactions, because then it can't tell.");
+        h.push("Use 'offset' and 'limit' to page through the transactions. The output is then an object instead of a list:");
+        h.push("'transactions' has the page of transactions, and 'total' the total number of transactions in the wallet.");
+        h.push("Example:");
+        h.push("list allmemos 0 50");
 
         h.join("\n")
     }
@@ -626,21 +2404,105 @@ impl Command for TransactionsCommand {
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() > 1 {
+        if args.len() > 4 {
             return format!("Didn't understand arguments\n{}", self.help());
         }
 
-        let include_memo_hex = if args.len() == 1 {
-            if args[0] == "allmemos" || args[0] == "true" || args[0] == "yes" {
-                true
-            } else {
-                return format!("Couldn't understand first argument '{}'\n{}", args[0], self.help());
+        let mut args = args.to_vec();
+
+        let mut include_memo_hex = false;
+        let mut include_raw = false;
+        while !args.is_empty() && args[0].parse::<usize>().is_err() {
+            match args[0] {
+                "allmemos" | "true" | "yes" => include_memo_hex = true,
+                "includeraw"                => include_raw = true,
+                _ => return format!("Couldn't understand argument '{}'\n{}", args[0], self.help()),
             }
-        } else {
-            false
+            args.remove(0);
+        }
+
+        let paging = match args.iter().map(|a| a.parse::<usize>()).collect::<Result<Vec<usize>, _>>() {
+            Ok(p) => p,
+            Err(_) => return format!("Couldn't parse offset and limit as numbers\n{}", self.help())
+        };
+
+        if paging.len() > 2 {
+            return format!("Didn't understand arguments\n{}", self.help());
+        }
+
+        let options = ListOptions {
+            include_memo_hex,
+            include_raw,
+            offset: paging.get(0).map(|o| *o),
+            limit:  paging.get(1).map(|l| *l),
+            ..ListOptions::default()
         };
 
-        format!("{}", lightclient.do_list_transactions(include_memo_hex).pretty(2))
+        format!("{}", lightclient.do_list_transactions(options).pretty(2))
+    }
+}
+
+struct ExportCsvCommand {}
+impl Command for ExportCsvCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("Export all incoming and outgoing transactions in CSV format");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
+        h.push("Usage:");
+        h.push("exportcsv [filename]");
+        h.push("");
+        h.push("The columns are: datetime, txid, direction, address, amount_arrr, fee, memo, block_height");
+        h.push("The datetime is in UTC, in ISO-8601 format. A transaction's fee is only on one of its rows.");
+        h.push("If a filename is specified, the CSV is written to that file instead of being printed.");
+        h.push("Example:");
+        h.push("exportcsv /home/user/pirate-transactions.csv");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Export all transactions as CSV".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() > 1 {
+            return format!("Didn't understand arguments\n{}", self.help());
+        }
+
+        let csv = lightclient.do_export_transactions_csv();
+
+        if args.len() == 1 {
+            match std::fs::write(args[0], csv) {
+                Ok(_)  => object!{ "result" => "success", "file" => args[0] },
+                Err(e) => object!{ "result" => "error", "error" => e.to_string() }
+            }.pretty(2)
+        } else {
+            csv
+        }
+    }
+}
+
+struct ExportWatchOnlyCommand {}
+impl Command for ExportWatchOnlyCommand {
+    fn help(&self) -> String {
+        let mut h = vec![];
+        h.push("Export the viewing keys of all the wallet's z-addresses, with the wallet birthday, as one bundle");
+        h.push("Usage:");
+        h.push("exportwatchonly");
+        h.push("");
+        h.push("The bundle can be given to 'import', or saved to a file and passed to '--watch-only-bundle' to create a");
+        h.push("watch-only copy of this wallet. It has no spending keys. Transparent addresses have no viewing keys, so");
+        h.push("they aren't in the bundle.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Export all viewing keys as a watch-only bundle".to_string()
+    }
+
+    fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
+        lightclient.do_export_watch_only().pretty(2)
     }
 }
 
@@ -650,12 +2512,17 @@ impl Command for ImportCommand {
         let mut h = vec![];
         h.push("Import an external spending or viewing key into the wallet");
         h.push("Usage:");
-        h.push("import <spending_key | viewing_key> <birthday> [norescan]");
+        h.push("import <spending_key | viewing_key> <birthday> [norescan [scanfrombirthday]]");
         h.push("OR");
-        h.push("import '{'key': <spending_key or viewing_key>, 'birthday': <birthday>, 'norescan': <true>}'");
+        h.push("import '{'key': <spending_key or viewing_key>, 'birthday': <birthday>, 'norescan': <true>, 'scanfrombirthday': <true>}'");
+        h.push("OR");
+        h.push("import <watch-only bundle from 'exportwatchonly'>");
         h.push("");
         h.push("Birthday is the earliest block number that has transactions belonging to the imported key. Rescanning will start from this block. If not sure, you can specify '0', which will start rescanning from the first sapling block.");
+        h.push("With 'norescan' and 'scanfrombirthday', the wallet isn't rescanned now. Instead, the next sync scans the blocks from the birthday onwards.");
+        h.push("The wallet only keeps the last 100 blocks, so if the birthday is older than that, the next sync rescans the whole wallet from its birthday instead.");
         h.push("Note that you can import only the full spending (private) key or the full viewing key.");
+        h.push("A watch-only bundle imports all its viewing keys with the bundle's birthday, and then rescans.");
 
         h.join("\n")
     }
@@ -666,11 +2533,11 @@ impl Command for ImportCommand {
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() == 0 || args.len() > 3 {
+        if args.len() == 0 || args.len() > 4 {
             return format!("Insufficient arguments\n\n{}", self.help());
         }
 
-        let (key, birthday, rescan) = if args.len() == 1 {
+        let (key, birthday, rescan, scan_from_birthday) = if args.len() == 1 {
             // If only one arg, parse it as JSON
             let json_args = match json::parse(&args[0]) {
                 Ok(j) => j,
@@ -684,6 +2551,21 @@ impl Command for ImportCommand {
                 return format!("Couldn't parse argument as a JSON object\n{}", self.help());
             }
 
+            if json_args.has_key("viewing_keys") {
+                let r = match lightclient.do_import_watch_only_bundle(&args[0]) {
+                    Ok(r)  => r,
+                    Err(e) => return format!("Error: {}", e),
+                };
+
+                if r["imported"].len() > 0 {
+                    if let Err(e) = lightclient.do_rescan() {
+                        return format!("Error: Rescan failed: {}", e);
+                    }
+                }
+
+                return r.pretty(2);
+            }
+
             if !json_args.has_key("key") {
                 return format!("'key' field is required in the JSON, containing the spending or viewing key to import\n{}", self.help());
             }
@@ -692,7 +2574,8 @@ impl Command for ImportCommand {
                 return format!("'birthday' field is required in the JSON, containing the birthday of the spending or viewing key\n{}", self.help());
             }
 
-            (json_args["key"].as_str().unwrap().to_string(), json_args["birthday"].as_u64().unwrap(), !json_args.has_key("norescan"))
+            (json_args["key"].as_str().unwrap().to_string(), json_args["birthday"].as_u64().unwrap(), !json_args.has_key("norescan"),
+             json_args["scanfrombirthday"].as_bool().unwrap_or(false))
         } else {
             let key = args[0];
             let birthday = match args[1].parse::<u64>() {
@@ -700,7 +2583,7 @@ impl Command for ImportCommand {
                 Err(_) => return format!("Couldn't parse {} as birthday. Please specify an integer. Ok to use '0'", args[1]),
             };
     
-            let rescan = if args.len() == 3 {
+            let rescan = if args.len() >= 3 {
                 if args[2] == "norescan" || args[2] == "false" || args[2] == "no" { 
                     false 
                 } else {
@@ -710,9 +2593,23 @@ impl Command for ImportCommand {
                 true
             };
 
-            (key.to_string(), birthday, rescan)
+            let scan_from_birthday = if args.len() == 4 {
+                if args[3] == "scanfrombirthday" {
+                    true
+                } else {
+                    return format!("Couldn't undestand the argument '{}'. Please pass 'scanfrombirthday' to scan from the birthday during the next sync", args[3]);
+                }
+            } else {
+                false
+            };
+
+            (key.to_string(), birthday, rescan, scan_from_birthday)
         };
 
+        if scan_from_birthday && rescan {
+            return format!("'scanfrombirthday' can only be used with 'norescan'\n{}", self.help());
+        }
+
         let r = match lightclient.do_import_key(key, birthday) {
             Ok(r) => r.pretty(2),
             Err(e) => return format!("Error: {}", e),
@@ -723,12 +2620,53 @@ impl Command for ImportCommand {
                 Ok(_) => {},
                 Err(e) => return format!("Error: Rescan failed: {}", e),
             };
+        } else if scan_from_birthday {
+            match lightclient.do_schedule_scan_from(birthday) {
+                Ok(_) => {},
+                Err(e) => return format!("Error: Couldn't schedule the scan: {}", e),
+            };
         }
 
         return r;
     }
 }
 
+struct RawBlockCommand {}
+impl Command for RawBlockCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("Show what the server returns for the compact block at a height");
+        h.push("Usage:");
+        h.push("rawblock <height>");
+        h.push("");
+        h.push("The block's hash, previous hash, time and the number of transactions, spends and outputs in it are shown.");
+        h.push("This is for debugging, and doesn't change the wallet. The server's compact blocks don't include the");
+        h.push("commitment tree size, so it isn't shown.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Show the server's compact block at a height".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 1 {
+            return self.help();
+        }
+
+        let height = match args[0].parse::<u64>() {
+            Ok(h)  => h,
+            Err(e) => return format!("Couldn't parse height: {}\n{}", e, self.help())
+        };
+
+        match lightclient.do_raw_block(height) {
+            Ok(j)  => j,
+            Err(e) => object!{ "error" => e }
+        }.pretty(2)
+    }
+}
+
 struct HeightCommand {}
 impl Command for HeightCommand {
     fn help(&self)  -> String {
@@ -758,8 +2696,10 @@ impl Command for NewAddressCommand {
         let mut h = vec![];
         h.push("Create a new address in this wallet");
         h.push("Usage:");
-        h.push("new [z | t]");
+        h.push("new [z | t] [account] [raw]");
         h.push("");
+        h.push("The address is created in account 0, unless another account number is given.");
+        h.push("If 'raw' is passed, only the new address is printed, without any JSON.");
         h.push("Example:");
         h.push("To create a new z address:");
         h.push("new z");
@@ -771,14 +2711,87 @@ impl Command for NewAddressCommand {
     }
 
     fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
-        if args.len() != 1 {
+        if args.len() < 1 || args.len() > 3 {
             return format!("No address type specified\n{}", self.help());
         }
 
-        match lightclient.do_new_address(args[0]) {
-            Ok(j)  => j,
-            Err(e) => object!{ "error" => e }
-        }.pretty(2)
+        let (account, raw) = match parse_account_and_raw(&args[1..]) {
+            Some(a) => a,
+            None    => return self.help()
+        };
+
+        match lightclient.do_new_address_in_account(args[0], account) {
+            Ok(j)  => if raw { j[0].to_string() } else { j.pretty(2) },
+            Err(e) => object!{ "error" => e }.pretty(2)
+        }
+    }
+}
+
+struct NewDiversifiedCommand {}
+impl Command for NewDiversifiedCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("Create a new diversified address of one of the wallet's z addresses");
+        h.push("Usage:");
+        h.push("newdiversified <z address> [raw]");
+        h.push("");
+        h.push("A diversified address is another address of the same key. Funds received at any of a key's addresses");
+        h.push("are in that key's balance (see 'z_keys' in 'balance'), and can be sent from any of its addresses.");
+        h.push("'addresses' shows the key_address each z address belongs to.");
+        h.push("If 'raw' is passed, only the new address is printed, without any JSON.");
+        h.push("Example:");
+        h.push("newdiversified zs1va5902apnzlhdu0pw9r9q7ca8s4vnsrp2alr6xndt69jnepn2v2qrj9vg3wfcnjyks5pg65g9dc");
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "Create a new diversified address of a z address".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        let raw = match args {
+            [_]        => false,
+            [_, "raw"] => true,
+            _          => return self.help()
+        };
+
+        match lightclient.do_new_diversified_address(args[0]) {
+            Ok(j)  => if raw { j["address"].to_string() } else { j.pretty(2) },
+            Err(e) => object!{ "error" => e }.pretty(2)
+        }
+    }
+}
+
+struct AccountCommand {}
+impl Command for AccountCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("List the accounts in this wallet, or add a new one");
+        h.push("Usage:");
+        h.push("account [list | new]");
+        h.push("");
+        h.push("Each account has its own seed and addresses. The main wallet is account 0, and 'balance', 'address',");
+        h.push("'new', 'send' and 'keyinfo' take an account number to work on another account.");
+        h.push("'list', 'notes', 'listunspent', 'stats', 'exportcsv', 'export', 'seed' and 'searchmemo' only cover account 0.");
+        h.push("Every account scans each block with its own copy of the chain state, so syncing takes longer with more accounts.");
+        h.push("'new' creates an account with a new seed and prints the seed, which has to be backed up as well.");
+        h.push("An encrypted wallet has to be decrypted before adding an account.");
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "List or add accounts in this wallet".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        match args {
+            [] | ["list"] => lightclient.do_list_accounts().pretty(2),
+            ["new"]       => match lightclient.do_new_account() {
+                Ok(j)  => j,
+                Err(e) => object!{ "error" => e }
+            }.pretty(2),
+            _             => self.help()
+        }
     }
 }
 
@@ -787,10 +2800,12 @@ impl Command for NotesCommand {
     fn help(&self)  -> String {
         let mut h = vec![];
         h.push("Show all sapling notes and utxos in this wallet");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
         h.push("Usage:");
         h.push("notes [all]");
         h.push("");
         h.push("If you supply the \"all\" parameter, all previously spent sapling notes and spent utxos are also included");
+        h.push("Notes worth less than the dust threshold (see 'setdustthreshold') have \"dust\": true");
 
         h.join("\n")
     }
@@ -819,6 +2834,35 @@ impl Command for NotesCommand {
     }
 }
 
+struct ListUnspentCommand {}
+impl Command for ListUnspentCommand {
+    fn help(&self)  -> String {
+        let mut h = vec![];
+        h.push("List the wallet's unspent sapling notes and utxos, like bitcoind's listunspent");
+        h.push("Only account 0, the main wallet, is included. See 'account'.");
+        h.push("Usage:");
+        h.push("listunspent");
+        h.push("");
+        h.push("Each output has its txid, output_index, address, value and confirmations.");
+        h.push("\"spendable\" is true if the output has enough confirmations to be spent, isn't reserved by an");
+        h.push("unconfirmed send, and the wallet has its spending key and is unlocked.");
+
+        h.join("\n")
+    }
+
+    fn short_help(&self) -> String {
+        "List unspent notes and utxos with their confirmations".to_string()
+    }
+
+    fn exec(&self, args: &[&str], lightclient: &LightClient) -> String {
+        if args.len() != 0 {
+            return self.help();
+        }
+
+        format!("{}", lightclient.do_list_unspent().pretty(2))
+    }
+}
+
 struct QuitCommand {}
 impl Command for QuitCommand {
     fn help(&self)  -> String {
@@ -827,6 +2871,8 @@ impl Command for QuitCommand {
         h.push("Usage:");
         h.push("quit");
         h.push("");
+        h.push("A sync that is running stops after the batch of blocks it's scanning, before the wallet is saved.");
+        h.push("When started with --no-save, the wallet is not saved.");
 
         h.join("\n")
     }
@@ -836,7 +2882,7 @@ impl Command for QuitCommand {
     }
 
     fn exec(&self, _args: &[&str], lightclient: &LightClient) -> String {
-        match lightclient.do_save() {
+        match lightclient.request_shutdown() {
             Ok(_) => {"".to_string()},
             Err(e) => e
         }
@@ -848,25 +2894,74 @@ pub fn get_commands() -> Box<HashMap<String, Box<dyn Command>>> {
 
     map.insert("sync".to_string(),              Box::new(SyncCommand{}));
     map.insert("syncstatus".to_string(),        Box::new(SyncStatusCommand{}));
+    map.insert("syncestimate".to_string(),      Box::new(SyncEstimateCommand{}));
+    map.insert("listoperations".to_string(),    Box::new(ListOperationsCommand{}));
+    map.insert("canceloperation".to_string(),   Box::new(CancelOperationCommand{}));
+    map.insert("disconnect".to_string(),        Box::new(DisconnectCommand{}));
+    map.insert("reconnect".to_string(),         Box::new(ReconnectCommand{}));
     map.insert("encryptionstatus".to_string(),  Box::new(EncryptionStatusCommand{}));
+    map.insert("paramsstatus".to_string(),      Box::new(ParamsStatusCommand{}));
     map.insert("rescan".to_string(),            Box::new(RescanCommand{}));
+    map.insert("abortrescan".to_string(),       Box::new(AbortRescanCommand{}));
+    map.insert("rewind".to_string(),            Box::new(RewindCommand{}));
     map.insert("clear".to_string(),             Box::new(ClearCommand{}));
+    map.insert("forgetunconfirmed".to_string(), Box::new(ForgetUnconfirmedCommand{}));
     map.insert("help".to_string(),              Box::new(HelpCommand{}));
     map.insert("balance".to_string(),           Box::new(BalanceCommand{}));
+    map.insert("totalbalance".to_string(),      Box::new(TotalBalanceCommand{}));
     map.insert("addresses".to_string(),         Box::new(AddressCommand{}));
     map.insert("height".to_string(),            Box::new(HeightCommand{}));
+    map.insert("rawblock".to_string(),          Box::new(RawBlockCommand{}));
     map.insert("import".to_string(),            Box::new(ImportCommand{}));
     map.insert("export".to_string(),            Box::new(ExportCommand{}));
+    map.insert("exportwatchonly".to_string(),   Box::new(ExportWatchOnlyCommand{}));
+    map.insert("export-viewing".to_string(),    Box::new(ExportViewingCommand{}));
+    map.insert("validateaddress".to_string(),   Box::new(ValidateAddressCommand{}));
     map.insert("info".to_string(),              Box::new(InfoCommand{}));
+    map.insert("serverlist".to_string(),        Box::new(ServerListCommand{}));
+    map.insert("switchserver".to_string(),      Box::new(SwitchServerCommand{}));
+    map.insert("walletbirthday".to_string(),    Box::new(WalletBirthdayCommand{}));
     map.insert("send".to_string(),              Box::new(SendCommand{}));
+    map.insert("broadcast".to_string(),         Box::new(BroadcastCommand{}));
+    map.insert("decode".to_string(),            Box::new(DecodeCommand{}));
+    map.insert("spendpreimage".to_string(),     Box::new(SpendPreimageCommand{}));
+    map.insert("consolidate".to_string(),       Box::new(ConsolidateCommand{}));
+    map.insert("sweep".to_string(),             Box::new(SweepCommand{}));
+    map.insert("shield".to_string(),            Box::new(ShieldCommand{}));
+    map.insert("paymenturi".to_string(),        Box::new(PaymentUriCommand{}));
+    map.insert("memo".to_string(),              Box::new(MemoCommand{}));
+    map.insert("searchmemo".to_string(),        Box::new(SearchMemoCommand{}));
+    map.insert("transaction".to_string(),       Box::new(TransactionCommand{}));
+    map.insert("encryptmessage".to_string(),    Box::new(EncryptMessageCommand{}));
+    map.insert("decryptmessage".to_string(),    Box::new(DecryptMessageCommand{}));
+    map.insert("signmessage".to_string(),       Box::new(SignMessageCommand{}));
+    map.insert("verifymessage".to_string(),     Box::new(VerifyMessageCommand{}));
     map.insert("save".to_string(),              Box::new(SaveCommand{}));
+    map.insert("verifywallet".to_string(),      Box::new(VerifyWalletCommand{}));
+    map.insert("verifyaddresses".to_string(),   Box::new(VerifyAddressesCommand{}));
     map.insert("quit".to_string(),              Box::new(QuitCommand{}));
     map.insert("list".to_string(),              Box::new(TransactionsCommand{}));
+    map.insert("exportcsv".to_string(),         Box::new(ExportCsvCommand{}));
+    map.insert("listreceivedbyaddress".to_string(), Box::new(ListReceivedByAddressCommand{}));
     map.insert("notes".to_string(),             Box::new(NotesCommand{}));
+    map.insert("listunspent".to_string(),       Box::new(ListUnspentCommand{}));
     map.insert("new".to_string(),               Box::new(NewAddressCommand{}));
+    map.insert("newdiversified".to_string(),    Box::new(NewDiversifiedCommand{}));
+    map.insert("account".to_string(),           Box::new(AccountCommand{}));
     map.insert("seed".to_string(),              Box::new(SeedCommand{}));
+    map.insert("seedwords".to_string(),         Box::new(SeedWordsCommand{}));
+    map.insert("setlimit".to_string(),          Box::new(SetLimitCommand{}));
+    map.insert("setfee".to_string(),            Box::new(SetFeeCommand{}));
+    map.insert("setdefaultmemo".to_string(),    Box::new(SetDefaultMemoCommand{}));
+    map.insert("setdustthreshold".to_string(),  Box::new(SetDustThresholdCommand{}));
+    map.insert("addressatindex".to_string(),    Box::new(AddressAtIndexCommand{}));
+    map.insert("keyinfo".to_string(),           Box::new(KeyInfoCommand{}));
+    map.insert("stats".to_string(),             Box::new(StatsCommand{}));
+    map.insert("healthcheck".to_string(),       Box::new(HealthCheckCommand{}));
+    map.insert("treestate".to_string(),         Box::new(TreeStateCommand{}));
     map.insert("encrypt".to_string(),           Box::new(EncryptCommand{}));
     map.insert("decrypt".to_string(),           Box::new(DecryptCommand{}));
+    map.insert("changepassword".to_string(),    Box::new(ChangePasswordCommand{}));
     map.insert("unlock".to_string(),            Box::new(UnlockCommand{}));
     map.insert("lock".to_string(),              Box::new(LockCommand{}));
 
@@ -903,6 +2998,43 @@ pub mod tests {
                    do_user_command("Addresses", &vec![], &lc));
     }
 
+    #[test]
+    pub fn test_raw_addresses() {
+        let lc = LightClient::unconnected(TEST_SEED.to_string(), None).unwrap();
+
+        // A raw new address is just the address, without quotes
+        let new_addr = do_user_command("new", &vec!["z", "raw"], &lc);
+        assert!(!new_addr.contains('"'));
+
+        let addresses = lc.do_address();
+        assert!(addresses["z_addresses"].members().any(|a| a.as_str().unwrap() == new_addr));
+
+        let expected = addresses["z_addresses"].members()
+            .chain(addresses["t_addresses"].members())
+            .map(|a| a.as_str().unwrap().to_string())
+            .collect::<Vec<_>>()
+            .join("\n");
+        assert_eq!(do_user_command("addresses", &vec!["raw"], &lc), expected);
+    }
+
+    #[test]
+    pub fn test_send_amounts() {
+        let lc = LightClient::unconnected(TEST_SEED.to_string(), None).unwrap();
+        let zaddr = lc.do_address()["z_addresses"][0].as_str().unwrap().to_string();
+
+        let send = |output: &str| {
+            let args = format!("{{\"input\": \"{}\", \"output\": [{{\"address\": \"{}\", {}}}]}}", zaddr, zaddr, output);
+            do_user_command("send", &vec![args.as_str()], &lc)
+        };
+
+        // Amounts with too much precision, or ARRR amounts given as zatoshis, are refused before anything is sent
+        assert!(send("\"amount_arrr\": \"1.123456789\"").starts_with("Error: Invalid amount"));
+        assert!(send("\"amount_arrr\": 1.123456789").starts_with("Error: Invalid amount"));
+        assert!(send("\"amount\": 1.5").starts_with("Error: 'amount' is a whole number of zatoshis"));
+        assert!(send("\"amount\": 150000000, \"amount_arrr\": 1.5").starts_with("Error: Give either 'amount'"));
+        assert!(send("\"memo\": \"hi\"").starts_with("Error: Need 'address' and 'amount'"));
+    }
+
     #[test]
     pub fn test_nosync_commands() {
         // The following commands should run
@@ -1,31 +1,305 @@
-use log::{error};
+use log::{error, warn};
+use rand::{Rng, rngs::OsRng};
+use std::future::Future;
+use std::time::{Duration, Instant};
 use zcash_primitives::transaction::{TxId};
 
 use crate::grpc_client::{ChainSpec, BlockId, BlockRange, RawTransaction, CompactBlock,
                          TransparentAddressBlockFilter, TxFilter, Empty, LightdInfo};
-use tonic::transport::{Channel, ClientTlsConfig};
-use tokio_rustls::{rustls::ClientConfig};
-use tonic::{Request};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tokio_rustls::{rustls::{ClientConfig, TLSError, Certificate, PrivateKey, internal::pemfile, internal::msgs::enums::AlertDescription}};
+use tonic::{Code, Request, Status, Streaming};
 
 use threadpool::ThreadPool;
-use std::sync::mpsc::channel;
+use std::sync::mpsc::{channel, sync_channel};
 
 use crate::PubCertificate;
 use crate::grpc_client::compact_tx_streamer_client::CompactTxStreamerClient;
 
-async fn get_client(uri: &http::Uri) -> Result<CompactTxStreamerClient<Channel>, Box<dyn std::error::Error>> {
-    let channel = if uri.scheme_str() == Some("http") {
-        //println!("http");
+/// How gRPC calls time out, and how failed ones are retried. Only transport errors, timeouts and
+/// transient server errors are retried, anything else (like a rejected transaction) fails right away.
+/// Broadcasting a transaction only retries the connection, never the send itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    pub max_attempts    : u32,       // Including the first attempt, so 1 means no retries
+    pub initial_backoff : Duration,  // Doubled after every failed attempt
+    pub max_backoff     : Duration,
+    pub connect_timeout : Duration,  // For opening the connection to the server
+    pub request_timeout : Duration,  // For each call, including the connection. For streams, for each message
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts    : 5,
+            initial_backoff : Duration::from_millis(500),
+            max_backoff     : Duration::from_secs(10),
+            connect_timeout : Duration::from_secs(30),
+            request_timeout : Duration::from_secs(120),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn no_retries() -> Self {
+        RetryPolicy { max_attempts: 1, ..RetryPolicy::default() }
+    }
+
+    // The wait before the given retry (starting at 1), with up to 50% random jitter so that
+    // many clients don't retry at the same moment
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self.initial_backoff
+                        .checked_mul(1 << std::cmp::min(retry - 1, 16))
+                        .map(|b| std::cmp::min(b, self.max_backoff))
+                        .unwrap_or(self.max_backoff);
+
+        let jitter = OsRng.gen_range(0, backoff.as_millis() as u64 / 2 + 1);
+        backoff - Duration::from_millis(jitter)
+    }
+}
+
+/// A client certificate and its private key, presented to servers that require mutual TLS
+#[derive(Clone, PartialEq)]
+pub struct ClientIdentity {
+    certs : Vec<Certificate>,  // The client certificate first, then any intermediates
+    key   : PrivateKey,
+}
+
+impl ClientIdentity {
+    /// Parse a PEM certificate chain and a PEM private key, in PKCS#8 or RSA format
+    pub fn from_pem(cert_pem: &[u8], key_pem: &[u8]) -> Result<Self, String> {
+        let certs = pemfile::certs(&mut &cert_pem[..])
+            .map_err(|_| "Couldn't parse the client certificate as PEM".to_string())?;
+        if certs.is_empty() {
+            return Err("No certificate found in the client certificate PEM".to_string());
+        }
+
+        let mut keys = pemfile::pkcs8_private_keys(&mut &key_pem[..])
+            .map_err(|_| "Couldn't parse the client key as PEM".to_string())?;
+        if keys.is_empty() {
+            keys = pemfile::rsa_private_keys(&mut &key_pem[..])
+                .map_err(|_| "Couldn't parse the client key as PEM".to_string())?;
+        }
+        if keys.is_empty() {
+            return Err("No PKCS#8 or RSA private key found in the client key PEM".to_string());
+        }
+
+        Ok(ClientIdentity { certs, key: keys.remove(0) })
+    }
+
+    /// Read the PEM certificate chain and private key from files
+    pub fn from_pem_files(cert_path: &str, key_path: &str) -> Result<Self, String> {
+        let cert_pem = std::fs::read(cert_path).map_err(|e| format!("Couldn't read client certificate {}: {}", cert_path, e))?;
+        let key_pem = std::fs::read(key_path).map_err(|e| format!("Couldn't read client key {}: {}", key_path, e))?;
+
+        ClientIdentity::from_pem(&cert_pem, &key_pem)
+    }
+}
+
+// Never print the private key
+impl std::fmt::Debug for ClientIdentity {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "ClientIdentity {{ certs: {}, key: <hidden> }}", self.certs.len())
+    }
+}
+
+/// How to connect to the server. Every call takes these, so they are kept together in `LightClientConfig`
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConnectionOptions {
+    pub pin_tls_certificate : bool,                    // Only trust servers whose certificate chains to the embedded PubCertificate
+    pub client_identity     : Option<ClientIdentity>,  // Presented to servers that require mutual TLS
+    pub retry_policy        : RetryPolicy,
+}
+
+/// The longest a throttled download waits before reading the next block. Below the rate where a
+/// block takes this long, the limit isn't kept exactly, so the stream keeps moving and the server
+/// doesn't time it out.
+pub const MAX_THROTTLE_WAIT: Duration = Duration::from_secs(5);
+
+/// A token bucket that limits the average rate of a download. The tokens are bytes, refilled at
+/// `bytes_per_sec` up to one second's worth, so a short burst after a pause is allowed.
+#[derive(Debug)]
+pub struct RateLimiter {
+    bytes_per_sec : f64,
+    tokens        : f64,
+    last_refill   : Instant,
+}
+
+impl RateLimiter {
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let bytes_per_sec = std::cmp::max(1, bytes_per_sec) as f64;
+
+        RateLimiter {
+            bytes_per_sec,
+            tokens      : bytes_per_sec,
+            last_refill : Instant::now(),
+        }
+    }
+
+    /// Take `bytes` that were just downloaded from the bucket, and return how long to wait
+    /// before downloading more
+    pub fn take(&mut self, bytes: u64) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.last_refill = now;
+
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.bytes_per_sec) - bytes as f64;
+        if self.tokens >= 0.0 {
+            return Duration::from_secs(0);
+        }
+
+        // Don't let the debt grow beyond what can be waited for, or a very low limit would keep
+        // waiting the maximum long after the rate was back under it
+        self.tokens = self.tokens.max(-self.bytes_per_sec * MAX_THROTTLE_WAIT.as_secs_f64());
+
+        std::cmp::min(Duration::from_secs_f64(-self.tokens / self.bytes_per_sec), MAX_THROTTLE_WAIT)
+    }
+}
+
+// Transport errors and servers that are temporarily down or overloaded are worth retrying.
+// TLS errors and everything the server deliberately rejected are not.
+fn is_retryable(e: &(dyn std::error::Error + 'static)) -> bool {
+    if find_tls_error(e).is_some() {
+        return false;
+    }
+
+    if let Some(status) = e.downcast_ref::<Status>() {
+        return match status.code() {
+            Code::Unavailable | Code::DeadlineExceeded | Code::Aborted | Code::ResourceExhausted | Code::Unknown => true,
+            _ => false,
+        };
+    }
+
+    e.downcast_ref::<tonic::transport::Error>().is_some()
+}
+
+// Give up on `f` once `timeout` has passed. The error is a DeadlineExceeded status, so it is
+// retried like the server not answering.
+async fn with_timeout<T, E, R>(timeout: Duration, name: &str, f: R) -> Result<T, Box<dyn std::error::Error>>
+where R: Future<Output = Result<T, E>>, E: Into<Box<dyn std::error::Error>> {
+    match tokio::time::timeout(timeout, f).await {
+        Ok(r)  => r.map_err(|e| e.into()),
+        Err(_) => Err(Box::new(Status::deadline_exceeded(format!("{} timed out after {:?}", name, timeout)))),
+    }
+}
+
+// Run the call made by `f`, retrying it according to the policy. Every attempt gets the policy's
+// request timeout.
+async fn with_retry<T, F, R>(retry_policy: &RetryPolicy, name: &str, mut f: F) -> Result<T, Box<dyn std::error::Error>>
+where F: FnMut() -> R, R: Future<Output = Result<T, Box<dyn std::error::Error>>> {
+    let mut attempt = 1;
+    loop {
+        match with_timeout(retry_policy.request_timeout, name, f()).await {
+            Err(e) if attempt < retry_policy.max_attempts && is_retryable(e.as_ref()) => {
+                let backoff = retry_policy.backoff(attempt);
+                warn!("{} failed (attempt {} of {}), retrying in {:?}: {}", name, attempt, retry_policy.max_attempts, backoff, e);
+
+                tokio::time::delay_for(backoff).await;
+                attempt += 1;
+            },
+            result => return result,
+        }
+    }
+}
+
+// Find the TLS error, if any, in the chain of errors that caused `e`
+fn find_tls_error(e: &(dyn std::error::Error + 'static)) -> Option<TLSError> {
+    let mut source = Some(e);
+    while let Some(err) = source {
+        if let Some(tls_err) = err.downcast_ref::<TLSError>() {
+            return Some(tls_err.clone());
+        }
+
+        // rustls errors are wrapped in io errors by the TLS stream
+        if let Some(tls_err) = err.downcast_ref::<std::io::Error>()
+                                    .and_then(|io_err| io_err.get_ref())
+                                    .and_then(|inner| inner.downcast_ref::<TLSError>()) {
+            return Some(tls_err.clone());
+        }
+
+        source = err.source();
+    }
+
+    None
+}
+
+// The alerts a server sends when it didn't get a client certificate it accepts
+fn is_client_cert_alert(e: &TLSError) -> bool {
+    match e {
+        TLSError::AlertReceived(alert) => match alert {
+            AlertDescription::BadCertificate | AlertDescription::UnsupportedCertificate | AlertDescription::CertificateRevoked |
+            AlertDescription::CertificateExpired | AlertDescription::CertificateUnknown | AlertDescription::UnknownCA |
+            AlertDescription::AccessDenied | AlertDescription::CertificateRequired => true,
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+/// The scheme of servers reached over a Unix domain socket. http::Uri needs an authority, so
+/// `unix:///path/to/socket` is kept as `unix://localhost/path/to/socket`, see `uds_uri`.
+pub const UDS_SCHEME: &str = "unix";
+
+pub fn is_uds(uri: &http::Uri) -> bool {
+    uri.scheme_str() == Some(UDS_SCHEME)
+}
+
+/// Parse a `unix:///path/to/socket` server into the URI the rest of the client uses
+pub fn uds_uri(server: &str) -> Result<http::Uri, String> {
+    let path = server.strip_prefix("unix://")
+                     .ok_or(format!("{} is not a unix:///path/to/socket address", server))?;
+    if !path.starts_with('/') {
+        return Err(format!("The socket path in {} must be absolute, like unix:///path/to/socket", server));
+    }
+
+    format!("{}://localhost{}", UDS_SCHEME, path).parse().map_err(|e| format!("Invalid socket path in {}: {}", server, e))
+}
+
+// Connections over a Unix domain socket never leave the host, so they don't use TLS
+#[cfg(unix)]
+async fn connect_uds(uri: &http::Uri) -> Result<Channel, Box<dyn std::error::Error>> {
+    let path = uri.path().to_string();
+
+    // The endpoint's URI is only used for the requests, the connection always goes to the socket
+    let channel = Endpoint::from_static("http://localhost")
+        .connect_with_connector(tower::service_fn(move |_: http::Uri| tokio::net::UnixStream::connect(path.clone())))
+        .await?;
+
+    Ok(channel)
+}
+
+#[cfg(not(unix))]
+async fn connect_uds(uri: &http::Uri) -> Result<Channel, Box<dyn std::error::Error>> {
+    Err(Box::from(format!("Can't connect to {}, Unix domain sockets are not supported on this platform", uri)))
+}
+
+// Connect to the server, giving up after the retry policy's `connect_timeout`
+async fn get_client(uri: &http::Uri, options: &ConnectionOptions) -> Result<CompactTxStreamerClient<Channel>, Box<dyn std::error::Error>> {
+    with_timeout(options.retry_policy.connect_timeout, &format!("Connecting to {}", uri), connect(uri, options)).await
+}
+
+async fn connect(uri: &http::Uri, options: &ConnectionOptions) -> Result<CompactTxStreamerClient<Channel>, Box<dyn std::error::Error>> {
+    let channel = if is_uds(uri) {
+        connect_uds(uri).await?
+    } else if uri.scheme_str() == Some("http") {
         Channel::builder(uri.clone()).connect().await?
     } else {
-        //println!("https");
         let mut config = ClientConfig::new();
 
+        // When pinned, the embedded certificate chain is the only thing that is trusted. It is the
+        // zecwallet.co chain, not the chain of the default Pirate server, so pinning is opt-in
         config.alpn_protocols.push(b"h2".to_vec());
-        config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        if !options.pin_tls_certificate {
+            config.root_store.add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+        }
         config.root_store.add_pem_file(
                 &mut PubCertificate::get("lightwalletd-zecwallet-co-chain.pem").unwrap().as_ref()).unwrap();
 
+        // Servers that require mutual TLS ask for this during the handshake
+        if let Some(identity) = &options.client_identity {
+            config.set_single_client_cert(identity.certs.clone(), identity.key.clone());
+        }
+
         let tls = ClientTlsConfig::new()
             .rustls_client_config(config)
             .domain_name(uri.host().unwrap());
@@ -33,7 +307,20 @@ async fn get_client(uri: &http::Uri) -> Result<CompactTxStreamerClient<Channel>,
         Channel::builder(uri.clone())
             .tls_config(tls)
             .connect()
-            .await?
+            .await
+            .map_err(|e| -> Box<dyn std::error::Error> {
+                match find_tls_error(&e) {
+                    Some(tls_err) if is_client_cert_alert(&tls_err) => match options.client_identity {
+                        Some(_) => Box::from(format!("TLS error: {} rejected the client certificate ({})", uri, tls_err)),
+                        None    => Box::from(format!("TLS error: {} requires a client certificate ({})", uri, tls_err)),
+                    },
+                    Some(tls_err) if options.pin_tls_certificate => Box::from(format!(
+                        "TLS error: {} doesn't have the pinned certificate ({}). If this is your own server, turn off certificate pinning",
+                        uri, tls_err)),
+                    Some(tls_err) => Box::from(format!("TLS error connecting to {}: {}", uri, tls_err)),
+                    None          => Box::new(e),
+                }
+            })?
     };
 
     Ok(CompactTxStreamerClient::new(channel))
@@ -42,8 +329,8 @@ async fn get_client(uri: &http::Uri) -> Result<CompactTxStreamerClient<Channel>,
 // ==============
 // GRPC code
 // ==============
-async fn get_lightd_info(uri: &http::Uri) -> Result<LightdInfo, Box<dyn std::error::Error>> {
-    let mut client = get_client(uri).await?;
+async fn get_lightd_info(uri: &http::Uri, options: &ConnectionOptions) -> Result<LightdInfo, Box<dyn std::error::Error>> {
+    let mut client = get_client(uri, options).await?;
 
     let request = Request::new(Empty {});
 
@@ -52,25 +339,54 @@ async fn get_lightd_info(uri: &http::Uri) -> Result<LightdInfo, Box<dyn std::err
     Ok(response.into_inner())
 }
 
-pub fn get_info(uri: &http::Uri) -> Result<LightdInfo, String> {
+pub fn get_info(uri: &http::Uri, options: &ConnectionOptions) -> Result<LightdInfo, String> {
     let mut rt = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
 
-    rt.block_on(get_lightd_info(uri)).map_err( |e| e.to_string())
+    rt.block_on(with_retry(&options.retry_policy, "get_lightd_info", || get_lightd_info(uri, options)))
+        .map_err( |e| e.to_string())
 }
 
+/// `get_info` with a single attempt that gives up after `timeout`, including the time to connect.
+/// For checks that have to answer quickly, even when the server is unreachable.
+pub fn get_info_with_timeout(uri: &http::Uri, options: &ConnectionOptions, timeout: Duration) -> Result<LightdInfo, String> {
+    let mut rt = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
 
-async fn get_block_range<F : 'static + std::marker::Send>(uri: &http::Uri, start_height: u64, end_height: u64, pool: ThreadPool, c: F) 
-    -> Result<(), Box<dyn std::error::Error>> 
-where F : Fn(&[u8], u64) {
-    let mut client = get_client(uri).await?;
+    let options = ConnectionOptions {
+        retry_policy: RetryPolicy { connect_timeout: timeout, ..options.retry_policy.clone() },
+        ..options.clone()
+    };
+
+    match rt.block_on(tokio::time::timeout(timeout, get_lightd_info(uri, &options))) {
+        Ok(r)  => r.map_err(|e| e.to_string()),
+        Err(_) => Err(format!("No answer from {} within {:?}", uri, timeout)),
+    }
+}
+
+
+async fn open_block_range(uri: &http::Uri, options: &ConnectionOptions, start_height: u64, end_height: u64)
+    -> Result<Streaming<CompactBlock>, Box<dyn std::error::Error>> {
+    let mut client = get_client(uri, options).await?;
 
     let bs = BlockId{ height: start_height, hash: vec!()};
     let be = BlockId{ height: end_height,   hash: vec!()};
 
     let request = Request::new(BlockRange{ start: Some(bs), end: Some(be) });
 
-    // Channel where the blocks are sent. A None signifies end of all blocks
-    let (tx, rx) = channel::<Option<CompactBlock>>();
+    Ok(client.get_block_range(request).await?.into_inner())
+}
+
+async fn get_block_range<F : 'static + std::marker::Send>(uri: &http::Uri, options: &ConnectionOptions, start_height: u64, end_height: u64, max_blocks_in_memory: usize, rate_limit: Option<u64>, pool: ThreadPool, c: F) 
+    -> Result<(), Box<dyn std::error::Error>> 
+where F : Fn(&[u8], u64) {
+    // Only opening the stream is retried. If it breaks halfway, the blocks that were already
+    // scanned can't be taken back, so the sync has to retry from where it was.
+    let mut response = with_retry(&options.retry_policy, "get_block_range",
+                        || open_block_range(uri, options, start_height, end_height)).await?;
+
+    // Channel where the blocks are sent. A None signifies end of all blocks.
+    // The channel is bounded, so if scanning falls behind, the download waits for it instead of
+    // buffering the whole range in memory.
+    let (tx, rx) = sync_channel::<Option<CompactBlock>>(std::cmp::max(1, max_blocks_in_memory));
 
     // Channel that the processor signals it is done, so the method can return
     let (ftx, frx) = channel();
@@ -89,8 +405,21 @@ where F : Fn(&[u8], u64) {
         ftx.send(Ok(())).unwrap();
     });
 
-    let mut response = client.get_block_range(request).await?.into_inner();
-    while let Some(block) = response.message().await? {
+    // Throttle by waiting between blocks. Only this stream is paused: the connection keeps
+    // answering the server's pings while it waits, and no wait is longer than MAX_THROTTLE_WAIT.
+    let mut limiter = rate_limit.map(RateLimiter::new);
+
+    // A stalled stream fails once no block arrives within the request timeout
+    while let Some(block) = with_timeout(options.retry_policy.request_timeout, "get_block_range", response.message()).await? {
+        if let Some(limiter) = limiter.as_mut() {
+            use prost::Message;
+
+            let wait = limiter.take(block.encoded_len() as u64);
+            if wait > Duration::from_secs(0) {
+                tokio::time::delay_for(wait).await;
+            }
+        }
+
         tx.send(Some(block)).unwrap();
     }
     tx.send(None).unwrap();
@@ -101,7 +430,9 @@ where F : Fn(&[u8], u64) {
     Ok(())
 }
 
-pub fn fetch_blocks<F : 'static + std::marker::Send>(uri: &http::Uri, start_height: u64, end_height: u64, pool: ThreadPool, c: F) -> Result<(), String>
+/// Download the blocks from `start_height` to `end_height` and call `c` with each of them. If `rate_limit`
+/// is set, the download is throttled to about that many bytes per second.
+pub fn fetch_blocks<F : 'static + std::marker::Send>(uri: &http::Uri, options: &ConnectionOptions, start_height: u64, end_height: u64, max_blocks_in_memory: usize, rate_limit: Option<u64>, pool: ThreadPool, c: F) -> Result<(), String>
     where F : Fn(&[u8], u64)  {
     
     let mut rt = match tokio::runtime::Runtime::new() {
@@ -109,17 +440,15 @@ pub fn fetch_blocks<F : 'static + std::marker::Send>(uri: &http::Uri, start_heig
         Err(e) => {
             let es = format!("Error creating runtime {:?}", e);
             error!("{}", es);
-            eprintln!("{}", e);
             return Err(es);
         }
     };
 
-    match rt.block_on(get_block_range(uri, start_height, end_height, pool, c)) {
+    match rt.block_on(get_block_range(uri, options, start_height, end_height, max_blocks_in_memory, rate_limit, pool, c)) {
         Ok(o) => Ok(o),
         Err(e) => {
             let e = format!("Error fetching blocks {:?}", e);
             error!("{}", e);
-            eprintln!("{}", e);
             Err(e)
         }
     }
@@ -127,20 +456,26 @@ pub fn fetch_blocks<F : 'static + std::marker::Send>(uri: &http::Uri, start_heig
 
 
 // get_address_txids GRPC call
-async fn get_address_txids<F : 'static + std::marker::Send>(uri: &http::Uri, address: String, 
-        start_height: u64, end_height: u64, c: F) -> Result<(), Box<dyn std::error::Error>>
-    where F : Fn(&[u8], u64) {
-
-    let mut client = get_client(uri).await?;
+async fn open_address_txids(uri: &http::Uri, options: &ConnectionOptions, address: String,
+        start_height: u64, end_height: u64) -> Result<Streaming<RawTransaction>, Box<dyn std::error::Error>> {
+    let mut client = get_client(uri, options).await?;
     let start = Some(BlockId{ height: start_height, hash: vec!()});
     let end   = Some(BlockId{ height: end_height,   hash: vec!()});
 
     let request = Request::new(TransparentAddressBlockFilter{ address, range: Some(BlockRange{start, end}) });
 
     let maybe_response = client.get_address_txids(request).await?;
-    let mut response = maybe_response.into_inner();
+    Ok(maybe_response.into_inner())
+}
 
-    while let Some(tx) = response.message().await? {
+async fn get_address_txids<F : 'static + std::marker::Send>(uri: &http::Uri, options: &ConnectionOptions, address: String, 
+        start_height: u64, end_height: u64, c: F) -> Result<(), Box<dyn std::error::Error>>
+    where F : Fn(&[u8], u64) {
+
+    let mut response = with_retry(&options.retry_policy, "get_address_txids",
+                        || open_address_txids(uri, options, address.clone(), start_height, end_height)).await?;
+
+    while let Some(tx) = with_timeout(options.retry_policy.request_timeout, "get_address_txids", response.message()).await? {
         c(&tx.data, tx.height);
     }
 
@@ -148,7 +483,7 @@ async fn get_address_txids<F : 'static + std::marker::Send>(uri: &http::Uri, add
 }
 
 
-pub fn fetch_transparent_txids<F: 'static + std::marker::Send>(uri: &http::Uri, address: String, 
+pub fn fetch_transparent_txids<F: 'static + std::marker::Send>(uri: &http::Uri, options: &ConnectionOptions, address: String, 
         start_height: u64, end_height: u64, c: F) -> Result<(), String>
     where F : Fn(&[u8], u64) {
     
@@ -157,17 +492,15 @@ pub fn fetch_transparent_txids<F: 'static + std::marker::Send>(uri: &http::Uri,
         Err(e) => {
             let e = format!("Error creating runtime {:?}", e);
             error!("{}", e);
-            eprintln!("{}", e);
             return Err(e);
         }
     };
 
-    match rt.block_on(get_address_txids(uri, address.clone(), start_height, end_height, c)) {
+    match rt.block_on(get_address_txids(uri, options, address.clone(), start_height, end_height, c)) {
         Ok(o) => Ok(o),
         Err(e) => {
             let e = format!("Error with get_address_txids runtime {:?}", e);
             error!("{}", e);
-            eprintln!("{}", e);
             Err(e)
         }
     }
@@ -175,9 +508,9 @@ pub fn fetch_transparent_txids<F: 'static + std::marker::Send>(uri: &http::Uri,
 
 
 // get_transaction GRPC call
-async fn get_transaction(uri: &http::Uri, txid: TxId) 
+async fn get_transaction(uri: &http::Uri, options: &ConnectionOptions, txid: TxId) 
     -> Result<RawTransaction, Box<dyn std::error::Error>> {
-    let mut client = get_client(uri).await?;
+    let mut client = get_client(uri, options).await?;
     let request = Request::new(TxFilter { block: None, index: 0, hash: txid.0.to_vec() });
 
     let response = client.get_transaction(request).await?;
@@ -185,35 +518,36 @@ async fn get_transaction(uri: &http::Uri, txid: TxId)
     Ok(response.into_inner())
 }
 
-pub fn fetch_full_tx(uri: &http::Uri, txid: TxId) -> Result<Vec<u8>, String> {
+pub fn fetch_full_tx(uri: &http::Uri, options: &ConnectionOptions, txid: TxId) -> Result<Vec<u8>, String> {
     let mut rt = match tokio::runtime::Runtime::new() {
         Ok(r) => r,
         Err(e) => {
             let errstr = format!("Error creating runtime {}", e.to_string());
             error!("{}", errstr);
-            eprintln!("{}", errstr);
             return Err(errstr);
         }
     };
 
-    match rt.block_on(get_transaction(uri, txid)) {
+    match rt.block_on(with_retry(&options.retry_policy, "get_transaction", || get_transaction(uri, options, txid.clone()))) {
         Ok(rawtx) => Ok(rawtx.data.to_vec()),
         Err(e) => {
             let errstr = format!("Error in get_transaction runtime {}", e.to_string());
             error!("{}", errstr);
-            eprintln!("{}", errstr);
             Err(errstr)
         }
     }    
 }
 
-// send_transaction GRPC call
-async fn send_transaction(uri: &http::Uri, tx_bytes: Box<[u8]>) -> Result<String, Box<dyn std::error::Error>> {
-    let mut client = get_client(uri).await?;
+// send_transaction GRPC call. Only connecting is retried: once the transaction has been written, a
+// timeout or an Unknown status doesn't say whether the server accepted it, so sending it again could
+// broadcast it twice.
+async fn send_transaction(uri: &http::Uri, options: &ConnectionOptions, tx_bytes: Box<[u8]>) -> Result<String, Box<dyn std::error::Error>> {
+    let mut client = with_retry(&options.retry_policy, "connect",
+                        || get_client(uri, options)).await?;
 
     let request = Request::new(RawTransaction {data: tx_bytes.to_vec(), height: 0});
 
-    let response = client.send_transaction(request).await?;
+    let response = with_timeout(options.retry_policy.request_timeout, "send_transaction", client.send_transaction(request)).await?;
 
     let sendresponse = response.into_inner();
     if sendresponse.error_code == 0 {
@@ -228,15 +562,37 @@ async fn send_transaction(uri: &http::Uri, tx_bytes: Box<[u8]>) -> Result<String
     }
 }
 
-pub fn broadcast_raw_tx(uri: &http::Uri, tx_bytes: Box<[u8]>) -> Result<String, String> {
+pub fn broadcast_raw_tx(uri: &http::Uri, options: &ConnectionOptions, tx_bytes: Box<[u8]>) -> Result<String, String> {
     let mut rt = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
 
-    rt.block_on(send_transaction(uri, tx_bytes)).map_err( |e| e.to_string())
+    rt.block_on(send_transaction(uri, options, tx_bytes))
+        .map_err( |e| e.to_string())
+}
+
+// get_block GRPC call
+async fn get_block(uri: &http::Uri, options: &ConnectionOptions, height: u64) -> Result<CompactBlock, Box<dyn std::error::Error>> {
+    let mut client = get_client(uri, options).await?;
+
+    let request = Request::new(BlockId { height, hash: vec![] });
+
+    let response = client.get_block(request).await?;
+
+    Ok(response.into_inner())
+}
+
+pub fn fetch_block(uri: &http::Uri, options: &ConnectionOptions, height: u64) -> Result<CompactBlock, String> {
+    let mut rt = tokio::runtime::Runtime::new().map_err(|e| e.to_string())?;
+
+    rt.block_on(with_retry(&options.retry_policy, "get_block", || get_block(uri, options, height))).map_err(|e| {
+        let errstr = format!("Error getting block {}: {}", height, e.to_string());
+        error!("{}", errstr);
+        errstr
+    })
 }
 
 // get_latest_block GRPC call
-async fn get_latest_block(uri: &http::Uri) -> Result<BlockId, Box<dyn std::error::Error>> {
-    let mut client = get_client(uri).await?;
+async fn get_latest_block(uri: &http::Uri, options: &ConnectionOptions) -> Result<BlockId, Box<dyn std::error::Error>> {
+    let mut client = get_client(uri, options).await?;
 
     let request = Request::new(ChainSpec {});
 
@@ -245,19 +601,19 @@ async fn get_latest_block(uri: &http::Uri) -> Result<BlockId, Box<dyn std::error
     Ok(response.into_inner())
 }
 
-pub fn fetch_latest_block(uri: &http::Uri) -> Result<BlockId, String> {
+pub fn fetch_latest_block(uri: &http::Uri, options: &ConnectionOptions) -> Result<BlockId, String> {
     let mut rt = match tokio::runtime::Runtime::new() {
         Ok(r) => r,
         Err(e) => {
             let errstr = format!("Error creating runtime {}", e.to_string());
-            eprintln!("{}", errstr);
+            error!("{}", errstr);
             return Err(errstr);
         }
     };
 
-    rt.block_on(get_latest_block(uri)).map_err(|e| {
+    rt.block_on(with_retry(&options.retry_policy, "get_latest_block", || get_latest_block(uri, options))).map_err(|e| {
         let errstr = format!("Error getting latest block {}", e.to_string());
-        eprintln!("{}", errstr);
+        error!("{}", errstr);
         errstr
     })
 }
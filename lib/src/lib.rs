@@ -23,3 +23,9 @@ pub const ANCHOR_OFFSET: u32 = 0;
 pub mod grpc_client {
     tonic::include_proto!("cash.z.wallet.sdk.rpc");
 }
+
+// The same messages with the server stubs, for the mock server. See build.rs
+#[cfg(test)]
+pub mod grpc_server {
+    include!(concat!(env!("OUT_DIR"), "/server/cash.z.wallet.sdk.rpc.rs"));
+}
@@ -1,3 +1,6 @@
+use std::path::Path;
+
+use log::{info, warn};
 use serde_derive::Deserialize;
 
 #[derive(Deserialize, Clone)]
@@ -21,36 +24,50 @@ pub struct CheckPoint {
 }
 
 
-pub fn get_closest_checkpoint(chain_name: &str, coin_type: u32, height: u64) ->  Option<(u64, String, String)> {
+// The checkpoints that were current when this version was built. Used when there is no local
+// checkpoints file and the published list can't be downloaded.
+const EMBEDDED_CHECKPOINTS: &str = include_str!("../../coin-checkpoint.json");
 
-    let mut checkpoints: Vec<(u64, String, String)> = Vec::new();
+const CHECKPOINTS_URL: &str = "https://raw.githubusercontent.com/Qortal/piratewallet-light-cli/master/lib/coin-checkpoint.json";
 
-    let cps = match reqwest::blocking::get("https://raw.githubusercontent.com/Qortal/piratewallet-light-cli/master/coin-checkpoint.json") {
-        Ok(s) => {
-            match s.json::<CoinList>() {
-                Ok(j) => Some(j),
-                Err(_) => None
+/// Find the highest checkpoint at or below `height`. The checkpoints are read from `local_file`
+/// if it exists, otherwise from the published list, falling back to the list built into the crate.
+pub fn get_closest_checkpoint(chain_name: &str, coin_type: u32, height: u64, local_file: Option<&Path>) ->  Option<(u64, String, String)> {
+    let local = local_file
+        .filter(|path| path.exists())
+        .and_then(|path| match std::fs::read_to_string(path) {
+            Ok(s)  => Some(s),
+            Err(e) => {
+                warn!("Couldn't read checkpoints from {}: {}", path.display(), e);
+                None
             }
-        },
-        Err(_) => None
+        })
+        .and_then(|s| serde_json::from_str::<CoinList>(&s).map_err(|e| warn!("Bad checkpoints file: {}", e)).ok());
+
+    let cps = match local {
+        Some(cps) => cps,
+        None      => match reqwest::blocking::get(CHECKPOINTS_URL).and_then(|r| r.json::<CoinList>()) {
+            Ok(cps) => cps,
+            Err(e)  => {
+                info!("Couldn't download checkpoints, using the built-in ones: {}", e);
+                embedded_checkpoints()
+            }
+        }
     };
 
-    match cps {
-        Some(s) => {
-            for coin in 0..s.coin_list.len() {
-                if s.coin_list[coin].coin_type == coin_type && s.coin_list[coin].chain_name == chain_name {
-                    for points in 0..s.coin_list[coin].check_points.len() {
-                        checkpoints.push((s.coin_list[coin].check_points[points].height
-                                        , s.coin_list[coin].check_points[points].hash.clone()
-                                        , s.coin_list[coin].check_points[points].sapling_tree.clone()))
-                    }
-                }
-            }
-        },
-        None => {}
-    }
+    find_checkpoint(height, checkpoints_for(&cps, chain_name, coin_type))
+}
+
+fn embedded_checkpoints() -> CoinList {
+    serde_json::from_str(EMBEDDED_CHECKPOINTS).unwrap()
+}
 
-    find_checkpoint(height, checkpoints)
+// The (height, hash, sapling tree) of every checkpoint of the coin
+fn checkpoints_for(cps: &CoinList, chain_name: &str, coin_type: u32) -> Vec<(u64, String, String)> {
+    cps.coin_list.iter()
+        .filter(|coin| coin.coin_type == coin_type && coin.chain_name == chain_name)
+        .flat_map(|coin| coin.check_points.iter().map(|cp| (cp.height, cp.hash.clone(), cp.sapling_tree.clone())))
+        .collect()
 }
 
 fn find_checkpoint(height: u64, chkpts: Vec<(u64, String, String)>) -> Option<(u64, String, String)> {
@@ -99,15 +116,26 @@ pub mod tests {
 
     #[test]
     fn test_checkpoints() {
-        assert_eq!(get_test_checkpoint(500000), None);
-        assert_eq!(get_test_checkpoint(600000).unwrap().0, 600000);
-        assert_eq!(get_test_checkpoint(625000).unwrap().0, 600000);
-        assert_eq!(get_test_checkpoint(650000).unwrap().0, 650000);
-        assert_eq!(get_test_checkpoint(655000).unwrap().0, 650000);
-
-        assert_eq!(get_main_checkpoint(500000), None);
-        assert_eq!(get_main_checkpoint(610000).unwrap().0, 610000);
-        assert_eq!(get_main_checkpoint(625000).unwrap().0, 610000);
+        let main = checkpoints_for(&embedded_checkpoints(), "main", 141);
+        assert!(!main.is_empty());
+        assert!(checkpoints_for(&embedded_checkpoints(), "test", 141).is_empty());
+
+        assert_eq!(find_checkpoint(199999, main.clone()), None);
+        assert_eq!(find_checkpoint(200000, main.clone()).unwrap().0, 200000);
+        assert_eq!(find_checkpoint(250000, main.clone()).unwrap().0, 200000);
+        assert_eq!(find_checkpoint(300000, main.clone()).unwrap().0, 300000);
+    }
+
+    #[test]
+    fn test_local_checkpoints_file() {
+        let tmp = tempdir::TempDir::new("checkpoints").unwrap();
+        let path = tmp.path().join("checkpoints.json");
+        std::fs::write(&path, r#"{"coin_list": [{"coin_name": "Pirate", "coin_type": 141, "chain_name": "test",
+            "check_points": [{"height": 1000, "hash": "00ff", "sapling_tree": "000000"}]}]}"#).unwrap();
+
+        assert_eq!(get_closest_checkpoint("test", 141, 1500, Some(&path)),
+                   Some((1000, "00ff".to_string(), "000000".to_string())));
+        assert_eq!(get_closest_checkpoint("test", 141, 999, Some(&path)), None);
     }
 
 }
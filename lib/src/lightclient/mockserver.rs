@@ -0,0 +1,381 @@
+use std::cmp::max;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use ff::{Field, PrimeField};
+use pairing::bls12_381::Bls12;
+use rand::rngs::OsRng;
+use tokio::sync::mpsc;
+use tonic::{Request, Response, Status};
+use tempdir::TempDir;
+use tonic::transport::Server;
+use zcash_primitives::{
+    jubjub::fs::Fs,
+    note_encryption::{Memo, SaplingNoteEncryption},
+    primitives::Note,
+    transaction::{Transaction, TransactionData, TxId, components::{Amount, amount::DEFAULT_FEE}},
+    zip32::ExtendedFullViewingKey,
+    JUBJUB,
+};
+
+use crate::grpc_server::{BlockId, BlockRange, ChainSpec, CompactBlock, CompactOutput, CompactSpend, CompactTx,
+                         Empty, LightdInfo, RawTransaction, SendResponse, TransparentAddressBlockFilter, TxFilter};
+use crate::grpc_server::compact_tx_streamer_server::{CompactTxStreamer, CompactTxStreamerServer};
+use super::{LightClient, LightClientConfig};
+use crate::grpcconnector::{ConnectionOptions, RetryPolicy};
+
+pub const MOCK_CHAIN_NAME: &str = "test";
+pub const MOCK_BRANCH_ID: &str = "76b809bb";
+
+/// The fee that sends pay unless the test sets another one
+pub fn default_fee() -> u64 {
+    DEFAULT_FEE.try_into().unwrap()
+}
+
+struct MockChain {
+    start_height  : u64,
+    blocks        : Vec<CompactBlock>,
+    txs           : HashMap<Vec<u8>, RawTransaction>,  // Full txs that were mined, by txid
+    mempool       : Vec<Vec<u8>>,                      // Txs that were broadcast, but not mined yet
+    fork          : u8,                                // Bumped on every reorg, so replaced blocks get new hashes
+    fail_next_send: bool,                              // See `fail_next_send_after_accepting`
+}
+
+/// A lightwalletd that serves an in-memory chain, so tests can sync and send without a real server.
+/// Blocks are only added when the test asks for them, so the chain is the same on every run.
+///
+/// Transparent txs are not tracked, so address txid lookups always come back empty.
+#[derive(Clone)]
+pub struct MockLightServer {
+    chain: Arc<RwLock<MockChain>>,
+}
+
+impl MockLightServer {
+    /// A new chain whose first block will be at `start_height`. This is also the sapling activation
+    /// height of the chain, so wallets start scanning from it.
+    pub fn new(start_height: u64) -> Self {
+        assert!(start_height > 0, "The chain has to start after height 0");
+
+        MockLightServer {
+            chain: Arc::new(RwLock::new(MockChain {
+                start_height,
+                blocks : vec![],
+                txs    : HashMap::new(),
+                mempool: vec![],
+                fork   : 0,
+                fail_next_send: false,
+            }))
+        }
+    }
+
+    /// A config that points to this server. The server has to be started first.
+    pub fn config(&self, server: http::Uri, data_dir: Option<String>) -> LightClientConfig {
+        LightClientConfig {
+            server,
+            sapling_activation_height   : self.chain.read().unwrap().start_height,
+            consensus_branch_id         : MOCK_BRANCH_ID.to_string(),
+            connection                  : ConnectionOptions { retry_policy: RetryPolicy::no_retries(), ..ConnectionOptions::default() },
+            ..LightClientConfig::create_unconnected(MOCK_CHAIN_NAME.to_string(), data_dir)
+        }
+    }
+
+    /// Serve the chain on a free local port, and return the server's URI
+    pub fn start(&self) -> http::Uri {
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let addr: SocketAddr = ([127, 0, 0, 1], port).into();
+
+        let service = CompactTxStreamerServer::new(self.clone());
+        std::thread::spawn(move || {
+            let mut rt = tokio::runtime::Runtime::new().unwrap();
+            rt.block_on(Server::builder().add_service(service).serve(addr)).unwrap();
+        });
+
+        // Wait for the server to start accepting connections
+        for _ in 0..100 {
+            if TcpStream::connect(addr).is_ok() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+
+        format!("http://127.0.0.1:{}", port).parse().unwrap()
+    }
+
+    /// Start serving the chain, and create a client for `seed_phrase` whose birthday is the start of
+    /// the chain. The wallet is in a new temp dir, which is deleted when it is dropped, so keep it
+    /// for as long as the client is used.
+    pub fn client(&self, seed_phrase: &str) -> (LightClient, TempDir) {
+        let uri = self.start();
+
+        let tmp = TempDir::new("lctest").unwrap();
+        let config = self.config(uri, tmp.path().to_str().map(|s| s.to_string()));
+        let birthday = self.chain.read().unwrap().start_height;
+
+        (LightClient::new_from_phrase(seed_phrase.to_string(), &config, birthday, false).unwrap(), tmp)
+    }
+
+    pub fn latest_height(&self) -> u64 {
+        let chain = self.chain.read().unwrap();
+        chain.start_height + chain.blocks.len() as u64 - 1
+    }
+
+    /// Make the next broadcast fail with an Unknown status after the tx was already put in the
+    /// mempool, like a connection that drops before the server's response arrives
+    pub fn fail_next_send_after_accepting(&self) {
+        self.chain.write().unwrap().fail_next_send = true;
+    }
+
+    /// Raw txs that were broadcast to this server and haven't been mined yet
+    pub fn mempool(&self) -> Vec<Vec<u8>> {
+        self.chain.read().unwrap().mempool.clone()
+    }
+
+    // Block hashes only need to be unique, so derive them from the height and the fork
+    fn block_hash(height: u64, fork: u8) -> Vec<u8> {
+        let mut hash = vec![0u8; 32];
+        hash[..8].copy_from_slice(&height.to_le_bytes());
+        hash[30] = fork;
+        hash[31] = 0xff;
+
+        hash
+    }
+
+    fn tip_hash(chain: &MockChain) -> Vec<u8> {
+        chain.blocks.last().map(|b| b.hash.clone()).unwrap_or(Self::block_hash(chain.start_height - 1, 0))
+    }
+
+    fn compact_tx(tx: &Transaction) -> CompactTx {
+        let spends = tx.shielded_spends.iter().map(|s| {
+            CompactSpend { nf: s.nullifier.to_vec() }
+        }).collect::<Vec<CompactSpend>>();
+
+        let outputs = tx.shielded_outputs.iter().map(|o| {
+            let mut epk = vec![];
+            o.ephemeral_key.write(&mut epk).unwrap();
+
+            CompactOutput {
+                cmu       : o.cmu.to_repr().0.to_vec(),
+                epk,
+                ciphertext: o.enc_ciphertext[0..52].to_vec(),
+            }
+        }).collect::<Vec<CompactOutput>>();
+
+        CompactTx {
+            index: 0,
+            hash : tx.txid().0.to_vec(),
+            fee  : 0,
+            spends,
+            outputs,
+        }
+    }
+
+    // Add a block containing the given txs to the tip of the chain, and return its height
+    fn mine(&self, mut vtx: Vec<CompactTx>) -> u64 {
+        let mut chain = self.chain.write().unwrap();
+        let height = chain.start_height + chain.blocks.len() as u64;
+
+        for (i, ctx) in vtx.iter_mut().enumerate() {
+            ctx.index = i as u64;
+        }
+
+        let prev_hash = Self::tip_hash(&chain);
+        chain.blocks.push(CompactBlock {
+            proto_version: 0,
+            height,
+            hash         : Self::block_hash(height, chain.fork),
+            prev_hash,
+            time         : height as u32,
+            header       : vec![],
+            vtx,
+        });
+
+        height
+    }
+
+    /// Add `count` empty blocks, and return the new tip height
+    pub fn add_empty_blocks(&self, count: u64) -> u64 {
+        for _ in 0..count {
+            self.mine(vec![]);
+        }
+
+        self.latest_height()
+    }
+
+    /// Replace the last `depth` blocks with as many empty blocks with different hashes, as if the
+    /// chain reorged to a fork of the same length. Txs in the replaced blocks are dropped.
+    pub fn reorg(&self, depth: u64) {
+        {
+            let mut chain = self.chain.write().unwrap();
+            let len = chain.blocks.len();
+            assert!(depth as usize <= len, "Can't reorg more blocks than the chain has");
+
+            chain.blocks.truncate(len - depth as usize);
+            chain.fork += 1;
+        }
+
+        self.add_empty_blocks(depth);
+    }
+
+    /// Add a block with a tx paying `value` to the default address of `extfvk`. There is no full tx
+    /// behind it, so fetching the tx returns an empty one. Returns the txid.
+    pub fn add_tx_paying(&self, extfvk: &ExtendedFullViewingKey, value: u64) -> TxId {
+        let mut rng = OsRng;
+
+        let to = extfvk.default_address().unwrap().1;
+        let note = Note {
+            g_d  : to.diversifier().g_d::<Bls12>(&JUBJUB).unwrap(),
+            pk_d : to.pk_d().clone(),
+            value: Amount::from_u64(value).unwrap().into(),
+            r    : Fs::random(&mut rng),
+        };
+        let encryptor = SaplingNoteEncryption::new(
+            extfvk.fvk.ovk,
+            note.clone(),
+            to.clone(),
+            Memo::default(),
+            &mut rng,
+        );
+
+        let mut epk = vec![];
+        encryptor.epk().write(&mut epk).unwrap();
+
+        // The txid only needs to be unique, so use the note commitment
+        let cmu = note.cm(&JUBJUB).to_repr().0.to_vec();
+        let mut txid = [0u8; 32];
+        txid.copy_from_slice(&cmu);
+
+        let ctx = CompactTx {
+            index  : 0,
+            hash   : txid.to_vec(),
+            fee    : 0,
+            spends : vec![],
+            outputs: vec![CompactOutput {
+                cmu,
+                epk,
+                ciphertext: encryptor.encrypt_note_plaintext()[..52].to_vec(),
+            }],
+        };
+        self.mine(vec![ctx]);
+
+        TxId(txid)
+    }
+
+    /// Mine everything in the mempool into a new block, and return the mined txids
+    pub fn mine_mempool(&self) -> Vec<TxId> {
+        let raw_txs = std::mem::replace(&mut self.chain.write().unwrap().mempool, vec![]);
+        let txs = raw_txs.iter()
+                    .map(|raw| Transaction::read(&raw[..]).unwrap())
+                    .collect::<Vec<Transaction>>();
+
+        let height = self.mine(txs.iter().map(|tx| Self::compact_tx(tx)).collect());
+
+        let mut chain = self.chain.write().unwrap();
+        for (tx, raw) in txs.iter().zip(raw_txs.into_iter()) {
+            chain.txs.insert(tx.txid().0.to_vec(), RawTransaction { data: raw, height });
+        }
+
+        txs.iter().map(|tx| tx.txid()).collect()
+    }
+}
+
+#[tonic::async_trait]
+impl CompactTxStreamer for MockLightServer {
+    async fn get_latest_block(&self, _request: Request<ChainSpec>) -> Result<Response<BlockId>, Status> {
+        let chain = self.chain.read().unwrap();
+        let height = chain.start_height + chain.blocks.len() as u64 - 1;
+
+        Ok(Response::new(BlockId { height, hash: Self::tip_hash(&chain) }))
+    }
+
+    async fn get_block(&self, request: Request<BlockId>) -> Result<Response<CompactBlock>, Status> {
+        let height = request.into_inner().height;
+
+        self.chain.read().unwrap().blocks.iter()
+            .find(|b| b.height == height)
+            .map(|b| Response::new(b.clone()))
+            .ok_or(Status::not_found(format!("No block at height {}", height)))
+    }
+
+    type GetBlockRangeStream = mpsc::Receiver<Result<CompactBlock, Status>>;
+
+    async fn get_block_range(&self, request: Request<BlockRange>) -> Result<Response<Self::GetBlockRangeStream>, Status> {
+        let range = request.into_inner();
+        let start = range.start.map(|b| b.height).unwrap_or(0);
+        let end   = range.end.map(|b| b.height).unwrap_or(0);
+
+        let blocks = self.chain.read().unwrap().blocks.iter()
+                        .filter(|b| b.height >= start && b.height <= end)
+                        .cloned()
+                        .collect::<Vec<CompactBlock>>();
+
+        let (mut tx, rx) = mpsc::channel(max(1, blocks.len()));
+        for block in blocks {
+            tx.try_send(Ok(block)).unwrap();
+        }
+
+        Ok(Response::new(rx))
+    }
+
+    async fn get_transaction(&self, request: Request<TxFilter>) -> Result<Response<RawTransaction>, Status> {
+        let hash = request.into_inner().hash;
+
+        let rawtx = match self.chain.read().unwrap().txs.get(&hash) {
+            Some(rawtx) => rawtx.clone(),
+            None => {
+                // Txs added with add_tx_paying only exist as compact txs
+                let mut txid = [0u8; 32];
+                txid.copy_from_slice(&hash);
+
+                let mut data = vec![];
+                Transaction { txid: TxId(txid), data: TransactionData::new() }.write(&mut data).unwrap();
+
+                RawTransaction { data, height: 0 }
+            }
+        };
+
+        Ok(Response::new(rawtx))
+    }
+
+    async fn send_transaction(&self, request: Request<RawTransaction>) -> Result<Response<SendResponse>, Status> {
+        let data = request.into_inner().data;
+
+        let txid = match Transaction::read(&data[..]) {
+            Ok(tx) => tx.txid(),
+            Err(e) => return Ok(Response::new(SendResponse { error_code: -1, error_message: e.to_string() })),
+        };
+        let mut chain = self.chain.write().unwrap();
+        chain.mempool.push(data);
+
+        if chain.fail_next_send {
+            chain.fail_next_send = false;
+            return Err(Status::unknown("Connection reset"));
+        }
+
+        Ok(Response::new(SendResponse { error_code: 0, error_message: format!("\"{}\"", txid) }))
+    }
+
+    type GetAddressTxidsStream = mpsc::Receiver<Result<RawTransaction, Status>>;
+
+    async fn get_address_txids(&self, _request: Request<TransparentAddressBlockFilter>) -> Result<Response<Self::GetAddressTxidsStream>, Status> {
+        let (_tx, rx) = mpsc::channel(1);
+
+        Ok(Response::new(rx))
+    }
+
+    async fn get_lightd_info(&self, _request: Request<Empty>) -> Result<Response<LightdInfo>, Status> {
+        let chain = self.chain.read().unwrap();
+
+        Ok(Response::new(LightdInfo {
+            version                  : "mock".to_string(),
+            vendor                   : "MockLightServer".to_string(),
+            taddr_support            : false,
+            chain_name               : MOCK_CHAIN_NAME.to_string(),
+            sapling_activation_height: chain.start_height,
+            consensus_branch_id      : MOCK_BRANCH_ID.to_string(),
+            block_height             : chain.start_height + chain.blocks.len() as u64 - 1,
+        }))
+    }
+}
@@ -0,0 +1,436 @@
+//! Typed results returned by `LightClient`. The `do_*` methods that return JSON are thin
+//! wrappers that serialize these with `to_json()`.
+
+use std::fmt;
+
+use json::{object, JsonValue};
+
+use crate::lightwallet::{BuiltOutput, WATCH_ONLY_ERROR};
+
+/// The balance of one z address
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZAddressBalance {
+    pub address             : String,
+    pub key_address         : Option<String>, // The default address of the key this address belongs to
+    pub zbalance            : u64,
+    pub verified_zbalance   : u64,
+    pub spendable_zbalance  : u64,
+    pub unverified_zbalance : u64,
+}
+
+/// The balance of one z key, which is the total of all its diversified addresses
+#[derive(Clone, Debug, PartialEq)]
+pub struct ZKeyBalance {
+    pub key_address         : String,
+    pub addresses           : Vec<String>,
+    pub zbalance            : u64,
+    pub verified_zbalance   : u64,
+    pub spendable_zbalance  : u64,
+    pub unverified_zbalance : u64,
+}
+
+/// The balance of one t address
+#[derive(Clone, Debug, PartialEq)]
+pub struct TAddressBalance {
+    pub address : String,
+    pub balance : u64,
+}
+
+/// The balance of an account, in total and per address
+#[derive(Clone, Debug, PartialEq)]
+pub struct Balance {
+    pub zbalance            : u64,
+    pub verified_zbalance   : u64,
+    pub spendable_zbalance  : u64,
+    pub unverified_zbalance : u64,
+    pub tbalance            : u64,
+    pub z_addresses         : Vec<ZAddressBalance>,
+    pub t_addresses         : Vec<TAddressBalance>,
+}
+
+impl Balance {
+    /// The z balance per key. Notes received at any of a key's addresses can be spent from all of
+    /// them, so this is what each of those addresses can actually spend.
+    pub fn z_keys(&self) -> Vec<ZKeyBalance> {
+        let mut keys: Vec<ZKeyBalance> = vec![];
+
+        for z in self.z_addresses.iter() {
+            let key_address = z.key_address.clone().unwrap_or(z.address.clone());
+
+            let pos = match keys.iter().position(|k| k.key_address == key_address) {
+                Some(pos) => pos,
+                None      => {
+                    keys.push(ZKeyBalance {
+                        key_address,
+                        addresses:           vec![],
+                        zbalance:            0,
+                        verified_zbalance:   0,
+                        spendable_zbalance:  0,
+                        unverified_zbalance: 0,
+                    });
+                    keys.len() - 1
+                }
+            };
+
+            let k = &mut keys[pos];
+            k.addresses.push(z.address.clone());
+            k.zbalance            += z.zbalance;
+            k.verified_zbalance   += z.verified_zbalance;
+            k.spendable_zbalance  += z.spendable_zbalance;
+            k.unverified_zbalance += z.unverified_zbalance;
+        }
+
+        keys
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        let z_addresses = self.z_addresses.iter().map(|z| object!{
+            "address"             => z.address.clone(),
+            "key_address"         => z.key_address.clone(),
+            "zbalance"            => z.zbalance,
+            "verified_zbalance"   => z.verified_zbalance,
+            "spendable_zbalance"  => z.spendable_zbalance,
+            "unverified_zbalance" => z.unverified_zbalance,
+        }).collect::<Vec<JsonValue>>();
+
+        let z_keys = self.z_keys().iter().map(|k| object!{
+            "key_address"         => k.key_address.clone(),
+            "addresses"           => k.addresses.clone(),
+            "zbalance"            => k.zbalance,
+            "verified_zbalance"   => k.verified_zbalance,
+            "spendable_zbalance"  => k.spendable_zbalance,
+            "unverified_zbalance" => k.unverified_zbalance,
+        }).collect::<Vec<JsonValue>>();
+
+        let t_addresses = self.t_addresses.iter().map(|t| object!{
+            "address" => t.address.clone(),
+            "balance" => t.balance,
+        }).collect::<Vec<JsonValue>>();
+
+        object!{
+            "zbalance"            => self.zbalance,
+            "verified_zbalance"   => self.verified_zbalance,
+            "spendable_zbalance"  => self.spendable_zbalance,
+            "unverified_zbalance" => self.unverified_zbalance,
+            "tbalance"            => self.tbalance,
+            "z_addresses"         => z_addresses,
+            "z_keys"              => z_keys,
+            "t_addresses"         => t_addresses,
+        }
+    }
+}
+
+/// Whether a note or utxo is unspent, spent in a transaction that is not yet mined, or spent
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpendStatus {
+    Unspent,
+    Pending,
+    Spent,
+}
+
+/// A sapling note received by the wallet
+#[derive(Clone, Debug, PartialEq)]
+pub struct NoteInfo {
+    pub created_in_block  : i32,
+    pub datetime          : u64,
+    pub created_in_txid   : String,
+    pub outpoint          : Option<String>,
+    pub output_index      : Option<u64>,
+    pub value             : u64,
+    pub is_change         : bool,
+    pub address           : Option<String>,
+    pub spendable         : bool,
+    pub spent             : Option<String>,
+    pub spent_at_height   : Option<i32>,
+    pub unconfirmed_spent : Option<String>,
+}
+
+impl NoteInfo {
+    pub fn status(&self) -> SpendStatus {
+        spend_status(&self.spent, &self.unconfirmed_spent)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "created_in_block"   => self.created_in_block,
+            "datetime"           => self.datetime,
+            "created_in_txid"    => self.created_in_txid.clone(),
+            "outpoint"           => self.outpoint.clone(),
+            "value"              => self.value,
+            "is_change"          => self.is_change,
+            "address"            => self.address.clone(),
+            "spendable"          => self.spendable,
+            "spent"              => self.spent.clone(),
+            "spent_at_height"    => self.spent_at_height.map(|h| format!("{}", h)),
+            "unconfirmed_spent"  => self.unconfirmed_spent.clone(),
+        }
+    }
+}
+
+/// A transparent output received by the wallet
+#[derive(Clone, Debug, PartialEq)]
+pub struct UtxoInfo {
+    pub created_in_block  : i32,
+    pub datetime          : u64,
+    pub created_in_txid   : String,
+    pub output_index      : u64,
+    pub value             : u64,
+    pub scriptkey         : String,
+    pub address           : String,
+    pub spent             : Option<String>,
+    pub unconfirmed_spent : Option<String>,
+}
+
+impl UtxoInfo {
+    pub fn status(&self) -> SpendStatus {
+        spend_status(&self.spent, &self.unconfirmed_spent)
+    }
+
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "created_in_block"   => self.created_in_block,
+            "datetime"           => self.datetime,
+            "created_in_txid"    => self.created_in_txid.clone(),
+            "value"              => self.value,
+            "scriptkey"          => self.scriptkey.clone(),
+            "is_change"          => false, // TODO: Identify notes as change if we send change to taddrs
+            "address"            => self.address.clone(),
+            "spent"              => self.spent.clone(),
+            "unconfirmed_spent"  => self.unconfirmed_spent.clone(),
+        }
+    }
+}
+
+fn spend_status(spent: &Option<String>, unconfirmed_spent: &Option<String>) -> SpendStatus {
+    if spent.is_some() {
+        SpendStatus::Spent
+    } else if unconfirmed_spent.is_some() {
+        SpendStatus::Pending
+    } else {
+        SpendStatus::Unspent
+    }
+}
+
+/// One output of a transaction
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxOutput {
+    pub address    : Option<String>,
+    pub value      : i64,
+    pub memo       : Option<String>,
+    pub memo_bytes : Option<Vec<u8>>, // None for transparent outputs
+}
+
+impl TxOutput {
+    pub fn to_json(&self, include_memo_hex: bool) -> JsonValue {
+        let mut o = object!{
+            "address" => self.address.clone(),
+            "value"   => self.value,
+            "memo"    => self.memo.clone(),
+        };
+
+        if include_memo_hex {
+            o.insert("memohex", self.memo_bytes.as_ref().map(|m| hex::encode(m))).unwrap();
+            o.insert("memobase64", self.memo_bytes.as_ref().map(|m| base64::encode(m))).unwrap();
+        }
+
+        o
+    }
+}
+
+/// What `LightClient::do_list_transactions` lists. The default is every transaction, without the
+/// raw memos or txs, as a plain list.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ListOptions {
+    pub include_memo_hex : bool,           // Also give every memo as hex ('memohex') and base64 ('memobase64')
+    pub include_raw      : bool,           // Give the serialized tx as hex ('rawtx'), null if the wallet doesn't have it
+    pub offset           : Option<usize>,  // With an offset or limit, the list is paged, see `do_list_transactions`
+    pub limit            : Option<usize>,
+    pub min_height       : Option<u64>,    // Only the transactions in this (inclusive) block range
+    pub max_height       : Option<u64>,
+}
+
+/// A transaction in the wallet, either mined or still in the mempool
+#[derive(Clone, Debug, PartialEq)]
+pub struct TxSummary {
+    pub block_height    : i32,
+    pub datetime        : u64,
+    pub txid            : String,
+    pub confirmations   : i32,
+    pub unconfirmed     : bool,
+    pub amount          : i64,
+    pub fee             : Option<u64>, // None if the wallet didn't fund all of the tx's inputs, so it can't tell
+    pub incoming        : Vec<TxOutput>,
+    pub incoming_change : Vec<TxOutput>,
+    pub outgoing        : Vec<TxOutput>,
+    pub outgoing_change : Vec<TxOutput>,
+    pub outgoing_memo   : Option<String>, // The memo on the outgoing outputs, if they all have the same one
+    pub raw_tx          : Option<Vec<u8>>, // The serialized tx, if the wallet has it
+}
+
+impl TxSummary {
+    pub fn to_json(&self, include_memo_hex: bool) -> JsonValue {
+        let list = |outputs: &Vec<TxOutput>| outputs.iter()
+            .map(|o| o.to_json(include_memo_hex))
+            .collect::<Vec<JsonValue>>();
+
+        let mut j = object!{
+            "block_height"  => self.block_height,
+            "datetime"      => self.datetime,
+            "txid"          => self.txid.clone(),
+            "confirmations" => self.confirmations,
+            "unconfirmed"   => self.unconfirmed,
+            "amount"        => self.amount,
+            "fee"           => self.fee,
+            "outgoing_memo" => self.outgoing_memo.clone(),
+        };
+
+        // Mempool transactions only know what we sent
+        if !self.unconfirmed {
+            j["incoming_metadata"]        = JsonValue::Array(list(&self.incoming));
+            j["incoming_metadata_change"] = JsonValue::Array(list(&self.incoming_change));
+        }
+        j["outgoing_metadata"] = JsonValue::Array(list(&self.outgoing));
+        if !self.unconfirmed {
+            j["outgoing_metadata_change"] = JsonValue::Array(list(&self.outgoing_change));
+        }
+
+        j
+    }
+}
+
+/// What a long running operation is doing
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperationKind {
+    Sync,
+    Rescan,
+    Send,
+}
+
+impl OperationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationKind::Sync   => "sync",
+            OperationKind::Rescan => "rescan",
+            OperationKind::Send   => "send",
+        }
+    }
+}
+
+/// Where a long running operation is at. A cancelled operation is `Cancelling` until it gets to a
+/// point where it can stop safely.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OperationStatus {
+    Running,
+    Cancelling,
+    Finished,
+    Cancelled,
+    Failed,
+}
+
+impl OperationStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationStatus::Running    => "running",
+            OperationStatus::Cancelling => "cancelling",
+            OperationStatus::Finished   => "finished",
+            OperationStatus::Cancelled  => "cancelled",
+            OperationStatus::Failed     => "failed",
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        match self {
+            OperationStatus::Running | OperationStatus::Cancelling => false,
+            _ => true,
+        }
+    }
+}
+
+/// A sync, rescan or send, as tracked by the client's operation registry
+#[derive(Clone, Debug, PartialEq)]
+pub struct Operation {
+    pub id       : u64,
+    pub kind     : OperationKind,
+    pub status   : OperationStatus,
+    pub started  : u64,         // Unix time
+    pub finished : Option<u64>, // Unix time, once the operation is done
+    pub error    : Option<String>,
+}
+
+impl Operation {
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "id"       => self.id,
+            "kind"     => self.kind.as_str(),
+            "status"   => self.status.as_str(),
+            "started"  => self.started,
+            "finished" => self.finished,
+            "error"    => self.error.clone(),
+        }
+    }
+}
+
+/// The result of a successful send
+#[derive(Clone, Debug, PartialEq)]
+pub struct SendResult {
+    pub txid    : String,
+    pub change  : Option<(String, u64)>, // (address, value) of the change, if there was any
+    pub outputs : Vec<BuiltOutput>,      // Every output in the transaction, including the change
+}
+
+impl SendResult {
+    pub fn to_json(&self) -> JsonValue {
+        object!{
+            "txid"    => self.txid.clone(),
+            "change"  => match &self.change {
+                Some((address, value)) => object!{
+                    "address" => address.clone(),
+                    "value"   => *value,
+                },
+                None => JsonValue::Null,
+            },
+            "outputs" => outputs_json(&self.outputs),
+        }
+    }
+}
+
+pub(crate) fn outputs_json(outputs: &[BuiltOutput]) -> JsonValue {
+    JsonValue::Array(outputs.iter().map(|o| object!{
+        "address"       => o.address.clone(),
+        "amount"        => o.value,
+        "memo_included" => o.memo_included,
+        "is_change"     => o.is_change,
+    }).collect())
+}
+
+/// Why a send failed
+#[derive(Clone, Debug, PartialEq)]
+pub enum SendError {
+    WatchOnly,
+    Locked,
+    Rejected(String), // Refused by the client's send limits before anything was built
+    Failed(String),   // Building the transaction failed, or it couldn't be sent
+    BroadcastFailed { txid: String, error: String },  // Broadcasting failed, but the server may still have accepted the transaction
+    MissingParams(String), // The sapling params weren't embedded or found. Has the directory they were expected in
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::WatchOnly   => write!(f, "{}", WATCH_ONLY_ERROR),
+            SendError::Locked      => write!(f, "Wallet is locked"),
+            SendError::Rejected(e) => write!(f, "{}", e),
+            SendError::Failed(e)   => write!(f, "{}", e),
+            SendError::BroadcastFailed { txid, error } => write!(f, "Broadcasting transaction {} failed: {}", txid, error),
+            SendError::MissingParams(dir) => write!(f, "The sapling params are missing. Put sapling-spend.params and sapling-output.params in {}", dir),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+impl From<SendError> for String {
+    fn from(e: SendError) -> String {
+        e.to_string()
+    }
+}
@@ -1,4 +1,4 @@
-use std::time::{SystemTime, Duration};
+use std::time::{SystemTime, Duration, Instant};
 use std::io::{self, Read, Write};
 use std::cmp;
 use std::collections::{HashMap, HashSet};
@@ -8,13 +8,15 @@ use std::io::{Error, ErrorKind};
 use threadpool::ThreadPool;
 use std::sync::mpsc::{channel};
 
-use rand::{Rng, rngs::OsRng};
+use rand::{Rng, RngCore, CryptoRng, rngs::OsRng};
+#[cfg(feature = "testing")]
+use rand::{SeedableRng, rngs::StdRng};
 use subtle::{ConditionallySelectable, ConstantTimeEq, CtOption};
 use log::{info, warn, error};
 
 use protobuf::parse_from_bytes;
 
-use libflate::gzip::{Decoder};
+use libflate::gzip::{Decoder, Encoder};
 use secp256k1::SecretKey;
 use bip39::{Mnemonic, Language};
 
@@ -25,31 +27,36 @@ use sha2::{Sha256, Digest};
 use sodiumoxide::crypto::secretbox;
 
 use zcash_client_backend::{
-    encoding::{encode_payment_address, encode_extended_spending_key, encode_extended_full_viewing_key, decode_extended_spending_key, decode_extended_full_viewing_key},
+    encoding::{encode_payment_address, decode_payment_address, encode_extended_spending_key, encode_extended_full_viewing_key, decode_extended_spending_key, decode_extended_full_viewing_key},
     proto::compact_formats::{CompactBlock, CompactOutput},
     wallet::{WalletShieldedOutput, WalletShieldedSpend}
 };
 
 use zcash_primitives::{
-    jubjub::fs::Fs,
+    jubjub::{edwards, PrimeOrder, fs::{Fs, FsRepr}},
+    util::hash_to_scalar,
     block::BlockHash,
-    serialize::{Vector},
+    serialize::{Vector, Optional},
     transaction::{
         builder::{Builder},
-        components::{Amount, OutPoint, TxOut}, //components::amount::DEFAULT_FEE,
+        components::{Amount, OutPoint, TxOut, amount::DEFAULT_FEE},
         TxId, Transaction,
     },
     sapling::Node,
-    merkle_tree::{CommitmentTree, IncrementalWitness},
+    merkle_tree::{CommitmentTree, IncrementalWitness, Hashable},
     legacy::{Script, TransparentAddress},
     note_encryption::{Memo, try_sapling_note_decryption, try_sapling_output_recovery, try_sapling_compact_note_decryption},
-    zip32::{ExtendedFullViewingKey, ExtendedSpendingKey, ChildIndex},
+    zip32::{ExtendedFullViewingKey, ExtendedSpendingKey, ChildIndex, DiversifierIndex},
+    keys::OutgoingViewingKey,
     JUBJUB,
     primitives::{PaymentAddress},
 };
 
 use crate::lightclient::{LightClientConfig};
 
+// A note decrypted by trial decryption: (note, recipient, index of the ivk that decrypted it)
+type DecryptedOutput = (zcash_primitives::primitives::Note<Bls12>, PaymentAddress<Bls12>, usize);
+
 mod data;
 mod extended_key;
 mod utils;
@@ -57,7 +64,7 @@ mod address;
 mod prover;
 pub mod walletzkey;
 
-use data::{BlockData, WalletTx, Utxo, SaplingNoteData, SpendableNote, OutgoingTxMetadata};
+use data::{BlockData, WalletTx, Utxo, SaplingNoteData, SpendableNote, ExternalSpendNote, OutgoingTxMetadata};
 use extended_key::{KeyIndex, ExtendedPrivKey};
 use walletzkey::{WalletZKey, WalletZKeyType, WalletDiversifiers};
 
@@ -65,10 +72,202 @@ pub const MAX_REORG: usize = 100;
 
 pub const GAP_RULE_UNUSED_ADDRESSES: usize = 0;
 
+/// Version byte at the start of messages made by `encrypt_message`
+pub const ENCRYPTED_MESSAGE_VERSION: u8 = 1;
+
+/// Names the scheme of the viewing key proofs that `sign_message` makes for z-addresses. Proofs are
+/// this, a ':' and the hex signature
+pub const MESSAGE_SIGNATURE_SCHEME: &str = "sapling-ivk-v1";
+
+/// Prefixed to messages signed by t-addresses, the same as the full node's `signmessage`
+pub const TRANSPARENT_MESSAGE_MAGIC: &str = "Komodo Signed Message:\n";
+
+/// Error returned when trying to spend from a watch-only wallet
+pub const WATCH_ONLY_ERROR: &str = "WatchOnly: This wallet only has viewing keys and can't spend";
+
+/// Maximum size of a single memo, in bytes
+pub const MAX_MEMO_BYTES: usize = 512;
+
+/// The kind of key that was pasted into an import
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportKeyType {
+    SpendingKey,
+    ViewingKey,
+}
+
+/// The order in which spendable notes are picked to cover the amount being sent
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NoteSelectionStrategy {
+    /// Spend as few notes as possible. If a single note covers the amount, the smallest such
+    /// note is spent, otherwise the largest notes are spent first.
+    FewestInputs,
+    /// Spend the oldest notes first, consolidating them
+    OldestFirst,
+    /// Spend the largest notes first
+    LargestFirst,
+}
+
+/// The randomness used to build transactions. This is always the OS RNG, except in tests built
+/// with the `testing` feature, which can fix a seed with `LightWallet::set_tx_rng_seed`
+enum TxRng {
+    Os(OsRng),
+    #[cfg(feature = "testing")]
+    Seeded(StdRng),
+}
+
+impl RngCore for TxRng {
+    fn next_u32(&mut self) -> u32 {
+        match self {
+            TxRng::Os(rng)     => rng.next_u32(),
+            #[cfg(feature = "testing")]
+            TxRng::Seeded(rng) => rng.next_u32(),
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        match self {
+            TxRng::Os(rng)     => rng.next_u64(),
+            #[cfg(feature = "testing")]
+            TxRng::Seeded(rng) => rng.next_u64(),
+        }
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        match self {
+            TxRng::Os(rng)     => rng.fill_bytes(dest),
+            #[cfg(feature = "testing")]
+            TxRng::Seeded(rng) => rng.fill_bytes(dest),
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        match self {
+            TxRng::Os(rng)     => rng.try_fill_bytes(dest),
+            #[cfg(feature = "testing")]
+            TxRng::Seeded(rng) => rng.try_fill_bytes(dest),
+        }
+    }
+}
+
+impl CryptoRng for TxRng {}
+
+impl Default for NoteSelectionStrategy {
+    fn default() -> Self {
+        NoteSelectionStrategy::LargestFirst
+    }
+}
+
+impl NoteSelectionStrategy {
+    pub fn from_str(s: &str) -> Option<NoteSelectionStrategy> {
+        match s {
+            "fewestinputs" => Some(NoteSelectionStrategy::FewestInputs),
+            "oldestfirst"  => Some(NoteSelectionStrategy::OldestFirst),
+            "largestfirst" => Some(NoteSelectionStrategy::LargestFirst),
+            _              => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NoteSelectionStrategy::FewestInputs => "fewestinputs",
+            NoteSelectionStrategy::OldestFirst  => "oldestfirst",
+            NoteSelectionStrategy::LargestFirst => "largestfirst",
+        }
+    }
+}
+
+/// Identifies a sapling note by the txid that created it and its index in that tx's
+/// shielded outputs. Written as "<txid>:<output index>".
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NoteOutpoint {
+    pub txid: TxId,
+    pub output_index: u64,
+}
+
+impl NoteOutpoint {
+    pub fn from_str(s: &str) -> Result<NoteOutpoint, String> {
+        let mut parts = s.trim().splitn(2, ':');
+
+        let txid_hex = parts.next().unwrap_or("");
+        let output_index = parts.next()
+            .ok_or(format!("Invalid note outpoint '{}', expected <txid>:<output index>", s))?
+            .parse::<u64>()
+            .map_err(|e| format!("Invalid output index in note outpoint '{}': {}", s, e))?;
+
+        let txid = LightWallet::txid_from_str(txid_hex)
+            .map_err(|e| format!("Invalid txid in note outpoint '{}': {}", s, e))?;
+
+        Ok(NoteOutpoint { txid, output_index })
+    }
+}
+
+impl std::fmt::Display for NoteOutpoint {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.txid, self.output_index)
+    }
+}
+
+/// How a send picks the notes it spends and where its change goes. The default is an ordinary
+/// send: notes are picked with the client's default strategy, and the change goes to the address
+/// of the first note spent.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SendOptions {
+    pub strategy         : Option<NoteSelectionStrategy>,  // None is the client's default strategy
+    pub from_notes       : Option<Vec<NoteOutpoint>>,      // Spend exactly these notes, instead of picking them
+    pub confirm_large    : bool,   // Allow a send over the max send limit, see `LightClient::set_max_send`
+    pub change_to_source : bool,   // Send the change of a z-address send back to `from`, not to the first note's address
+    pub min_note_value   : Option<u64>,  // Notes worth less are dust, picked last. None is the client's dust threshold
+    pub verified_utxos_only : bool,  // Only spend utxos with as many confirmations as notes need, see `get_verified_utxos`
+}
+
+// State of the timer that locks the wallet after it has been unlocked for a while
+#[derive(Default)]
+struct AutoLock {
+    timeout: Option<Duration>,
+    deadline: Option<Instant>,
+    generation: u64,           // Changes whenever the timer is (re)started or cancelled
+}
+
+// The seed and keys of a wallet and its accounts encrypted with a new password, before they replace
+// the old ones. See `LightWallet::change_password`
+struct Reencrypted {
+    enc_seed: Vec<u8>,
+    nonce: Vec<u8>,
+    enc_passphrase_seed: Option<(Vec<u8>, Vec<u8>)>,
+    zkeys: Vec<WalletZKey>,
+    accounts: Vec<Reencrypted>,
+}
+
+// The start of a wallet file that was written with `LightWallet::write_compressed`. An uncompressed
+// file starts with its version instead, whose high bytes are always 0, so the two can't be confused.
+const COMPRESSED_WALLET_MAGIC: &[u8; 8] = b"PWLTGZ01";
+
 fn now() -> f64 {
     SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap().as_secs() as f64
 }
 
+/// A signed transaction, along with the notes and utxos it spends
+pub struct BuiltTransaction {
+    pub tx: Transaction,
+    pub raw_tx: Vec<u8>,
+    pub notes: Vec<SpendableNote>,
+    pub tinputs: Vec<Utxo>,
+    pub height: u32,
+    pub total_value: u64,
+    pub fee: u64,
+    pub change: Option<(String, u64)>,  // Address the change went to, and its value
+    pub outputs: Vec<BuiltOutput>,
+}
+
+/// An output of a transaction built by the wallet
+#[derive(Debug, Clone, PartialEq)]
+pub struct BuiltOutput {
+    pub address: String,
+    pub value: u64,
+    pub memo_included: bool,
+    pub is_change: bool,
+}
+
 /// Sha256(Sha256(value))
 pub fn double_sha256(payload: &[u8]) -> Vec<u8> {
     let h1 = Sha256::digest(&payload);
@@ -114,6 +313,14 @@ pub struct LightWallet {
 
     seed: [u8; 32],    // Seed phrase for this wallet. If wallet is locked, this is 0
 
+    // The 64 byte BIP-39 seed, if the seed phrase was used with a passphrase. The passphrase itself is
+    // never stored. None if there is no passphrase, or if the wallet is locked. Added in v18
+    passphrase_seed: Option<Vec<u8>>,
+
+    // If encrypted, the (nonce, encrypted passphrase seed), so that the passphrase seed is never
+    // written in the clear. Added in v18
+    enc_passphrase_seed: Option<(Vec<u8>, Vec<u8>)>,
+
     // List of keys, actually in this wallet. This is a combination of HD keys derived from the seed,
     // viewing keys and imported spending keys.
     pub zkeys: Arc<RwLock<Vec<WalletZKey>>>,
@@ -135,15 +342,60 @@ pub struct LightWallet {
     // will start from here.
     birthday: u64,
 
+    // The block height at which this wallet's seed was first created. Unlike the
+    // birthday, this is not lowered when older keys are imported. Added in v9
+    creation_height: u64,
+
+    // A watch-only wallet has no seed and only viewing keys. It can never spend, or have
+    // spending keys imported into it. Added in v10
+    watch_only: bool,
+
+    // Additional accounts in this wallet file, each with its own seed and keys. This wallet is
+    // account 0, and the accounts here are 1 onwards. Each account is a whole wallet, with its own
+    // copy of the blocks and commitment trees, and scans every block itself, so memory and scan time
+    // grow with the number of accounts. Only balances, addresses, new addresses, sends and key info
+    // work on other accounts; everything else only covers account 0. Added in v11
+    accounts: Arc<RwLock<Vec<LightWallet>>>,
+
+    // Keys were imported with a birthday below the last scanned height, and their blocks haven't
+    // been scanned yet. The next sync rescans from this height. Added in v12
+    pending_scan_height: Arc<RwLock<Option<u64>>>,
+
+    // The memos the wallet attached to the outputs of its own sends, as (address, value, memo),
+    // recorded when they were sent. They fill in outgoing metadata that came back without its memo,
+    // are kept across rescans, and are pruned when the send expires unmined. Added in v13
+    pub sent_memos: Arc<RwLock<HashMap<TxId, Vec<(String, u64, String)>>>>,
+
+    // The fee used when a send doesn't give one. None is DEFAULT_FEE. Added in v15
+    default_fee: Arc<RwLock<Option<u64>>>,
+
+    // Blocks scanned per second, averaged over the previous syncs. None until a sync was long
+    // enough to measure. Added in v16
+    sync_speed: Arc<RwLock<Option<f64>>>,
+
+    // The memo attached to sends to z-addresses that don't give one. None sends no memo. Added in v17
+    default_memo: Arc<RwLock<Option<String>>>,
+
     // Non-serialized fields
     config: LightClientConfig,
 
     pub total_scan_duration: Arc<RwLock<Vec<Duration>>>,
+
+    // Non-serialized. Locks the wallet when it expires, see `start_auto_lock_timer`
+    auto_lock: Arc<RwLock<AutoLock>>,
+
+    // Non-serialized. The version of the file this wallet was read from. Writing always uses
+    // the current version, so an older file is upgraded on the next save
+    file_version: u64,
+
+    // Non-serialized. If set, transactions are built from this seed instead of the OS RNG
+    #[cfg(feature = "testing")]
+    tx_rng_seed: Arc<RwLock<Option<[u8; 32]>>>,
 }
 
 impl LightWallet {
     pub fn serialized_version() -> u64 {
-        return 8;
+        return 18;
     }
 
     fn get_taddr_from_bip39seed(config: &LightClientConfig, bip39_seed: &[u8], pos: u32) -> SecretKey {
@@ -188,7 +440,67 @@ impl LightWallet {
         }
     }
 
+    pub fn is_transparent_address(addr: &String, config: &LightClientConfig) -> bool {
+        match address::RecipientAddress::from_str(addr,
+                config.hrp_sapling_address(),
+                config.base58_pubkey_address(),
+                config.base58_script_address()) {
+            Some(address::RecipientAddress::Transparent(_)) => true,
+            _ => false,
+        }
+    }
+
+    /// Work out whether `key` is a sapling spending key or viewing key for this network, checking its
+    /// prefix and checksum. Anything else gets an error that says what the key looks like instead.
+    pub fn classify_import_key(key: &str, config: &LightClientConfig) -> Result<ImportKeyType, String> {
+        use zcash_client_backend::constants::{mainnet, testnet, regtest};
+
+        // The bech32 prefix is everything before the last '1'
+        let hrp = key.rfind('1').map(|i| &key[..i]).unwrap_or("");
+
+        if hrp == config.hrp_sapling_private_key() {
+            return match decode_extended_spending_key(config.hrp_sapling_private_key(), key) {
+                Ok(Some(_)) => Ok(ImportKeyType::SpendingKey),
+                Ok(None)    => Err("Not a valid Pirate sapling spending key".to_string()),
+                Err(e)      => Err(format!("Not a valid Pirate sapling spending key: {}", e)),
+            };
+        }
+
+        if hrp == config.hrp_sapling_viewing_key() {
+            return match decode_extended_full_viewing_key(config.hrp_sapling_viewing_key(), key) {
+                Ok(Some(_)) => Ok(ImportKeyType::ViewingKey),
+                Ok(None)    => Err("Not a valid Pirate sapling viewing key".to_string()),
+                Err(e)      => Err(format!("Not a valid Pirate sapling viewing key: {}", e)),
+            };
+        }
+
+        let other_spending_hrps = [mainnet::HRP_SAPLING_EXTENDED_SPENDING_KEY, testnet::HRP_SAPLING_EXTENDED_SPENDING_KEY,
+                                   regtest::HRP_SAPLING_EXTENDED_SPENDING_KEY];
+        let other_viewing_hrps  = [mainnet::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY, testnet::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY,
+                                   regtest::HRP_SAPLING_EXTENDED_FULL_VIEWING_KEY];
+
+        if other_spending_hrps.contains(&hrp) {
+            Err(format!("This is a spending key for a different network, this wallet needs keys starting with '{}'", config.hrp_sapling_private_key()))
+        } else if other_viewing_hrps.contains(&hrp) {
+            Err(format!("This is a viewing key for a different network, this wallet needs keys starting with '{}'", config.hrp_sapling_viewing_key()))
+        } else if LightWallet::is_shielded_address(&key.to_string(), config) {
+            Err("This is a z-address, not a key. Import the address's spending key or viewing key instead".to_string())
+        } else if LightWallet::is_transparent_address(&key.to_string(), config) {
+            Err("This is a t-address, not a key. Only sapling spending keys and viewing keys can be imported".to_string())
+        } else {
+            Err(format!("'{}' was not recognized as either a spending key or a viewing key because it didn't start with either '{}' or '{}'",
+                key, config.hrp_sapling_private_key(), config.hrp_sapling_viewing_key()))
+        }
+    }
+
     pub fn new(seed_phrase: Option<String>, config: &LightClientConfig, latest_block: u64) -> io::Result<Self> {
+        LightWallet::new_with_passphrase(seed_phrase, None, config, latest_block)
+    }
+
+    /// Create a wallet whose keys are derived from the seed phrase and a BIP-39 passphrase (the "25th word").
+    /// The same seed phrase with a different passphrase gives an entirely different wallet, and the
+    /// passphrase is needed along with the seed phrase to restore it. An empty passphrase is the same as none.
+    pub fn new_with_passphrase(seed_phrase: Option<String>, passphrase: Option<String>, config: &LightClientConfig, latest_block: u64) -> io::Result<Self> {
         // This is the source entropy that corresponds to the 24-word seed phrase
         let mut seed_bytes = [0u8; 32];
 
@@ -211,7 +523,8 @@ impl LightWallet {
 
         // The seed bytes is the raw entropy. To pass it to HD wallet generation,
         // we need to get the 64 byte bip39 entropy
-        let bip39_seed = bip39::Seed::new(&Mnemonic::from_entropy(&seed_bytes, Language::English).unwrap(), "");
+        let passphrase = passphrase.unwrap_or_default();
+        let bip39_seed = bip39::Seed::new(&Mnemonic::from_entropy(&seed_bytes, Language::English).unwrap(), &passphrase);
 
         // Derive only the first sk and address
         // let tpk = LightWallet::get_taddr_from_bip39seed(&config, &bip39_seed.as_bytes(), 0);
@@ -229,6 +542,8 @@ impl LightWallet {
             enc_seed:    [0u8; 48],
             nonce:       vec![],
             seed:        seed_bytes,
+            passphrase_seed: if passphrase.is_empty() { None } else { Some(bip39_seed.as_bytes().to_vec()) },
+            enc_passphrase_seed: None,
             zkeys:       Arc::new(RwLock::new(vec![WalletZKey::new_hdkey(hdkey_num, extsk)])),
             zaddresses:  Arc::new(RwLock::new(vec![])),
             tkeys:       Arc::new(RwLock::new(vec![])),
@@ -238,7 +553,19 @@ impl LightWallet {
             mempool_txs: Arc::new(RwLock::new(HashMap::new())),
             config:      config.clone(),
             birthday:    latest_block,
-            total_scan_duration: Arc::new(RwLock::new(vec![Duration::new(0, 0)]))
+            creation_height: latest_block,
+            watch_only:  false,
+            total_scan_duration: Arc::new(RwLock::new(vec![Duration::new(0, 0)])),
+            accounts:    Arc::new(RwLock::new(vec![])),
+            pending_scan_height: Arc::new(RwLock::new(None)),
+            sent_memos: Arc::new(RwLock::new(HashMap::new())),
+            default_fee: Arc::new(RwLock::new(None)),
+            sync_speed:  Arc::new(RwLock::new(None)),
+            default_memo: Arc::new(RwLock::new(None)),
+            auto_lock:   Arc::new(RwLock::new(AutoLock::default())),
+            file_version: LightWallet::serialized_version(),
+            #[cfg(feature = "testing")]
+            tx_rng_seed: Arc::new(RwLock::new(None)),
         };
 
         // If restoring from seed, make sure we are creating 5 addresses for users
@@ -252,24 +579,96 @@ impl LightWallet {
         Ok(lw)
     }
 
-    pub fn read<R: Read>(mut inp: R, config: &LightClientConfig) -> io::Result<Self> {
+    /// Create a watch-only wallet, which has no seed and only the given viewing key
+    pub fn new_watch_only(viewing_key: String, config: &LightClientConfig, birthday: u64) -> io::Result<Self> {
+        let extfvk = match decode_extended_full_viewing_key(config.hrp_sapling_viewing_key(), &viewing_key) {
+            Ok(Some(k)) => k,
+            Ok(None) => return Err(io::Error::new(ErrorKind::InvalidData, "Couldn't decode viewing key")),
+            Err(e) => {
+                let e = format!("Error decoding viewing key: {}", e);
+                error!("{}", e);
+                return Err(io::Error::new(ErrorKind::InvalidData, e));
+            }
+        };
+
+        Ok(LightWallet {
+            encrypted:   false,
+            unlocked:    true,
+            enc_seed:    [0u8; 48],
+            nonce:       vec![],
+            seed:        [0u8; 32],
+            passphrase_seed: None,
+            enc_passphrase_seed: None,
+            zkeys:       Arc::new(RwLock::new(vec![WalletZKey::new_imported_viewkey(extfvk)])),
+            zaddresses:  Arc::new(RwLock::new(vec![])),
+            tkeys:       Arc::new(RwLock::new(vec![])),
+            taddresses:  Arc::new(RwLock::new(vec![])),
+            blocks:      Arc::new(RwLock::new(vec![])),
+            txs:         Arc::new(RwLock::new(HashMap::new())),
+            mempool_txs: Arc::new(RwLock::new(HashMap::new())),
+            config:      config.clone(),
+            birthday,
+            creation_height: birthday,
+            watch_only:  true,
+            total_scan_duration: Arc::new(RwLock::new(vec![Duration::new(0, 0)])),
+            accounts:    Arc::new(RwLock::new(vec![])),
+            pending_scan_height: Arc::new(RwLock::new(None)),
+            sent_memos: Arc::new(RwLock::new(HashMap::new())),
+            default_fee: Arc::new(RwLock::new(None)),
+            sync_speed:  Arc::new(RwLock::new(None)),
+            default_memo: Arc::new(RwLock::new(None)),
+            auto_lock:   Arc::new(RwLock::new(AutoLock::default())),
+            file_version: LightWallet::serialized_version(),
+            #[cfg(feature = "testing")]
+            tx_rng_seed: Arc::new(RwLock::new(None)),
+        })
+    }
+
+    /// Read a wallet written by this or any older version. Every wallet file starts with its
+    /// version, and older files are read with the format of their version and then migrated, see
+    /// `migrate`. Files from a newer version are refused.
+    pub fn read<R: Read>(inp: R, config: &LightClientConfig) -> io::Result<Self> {
+        let mut inp = LightWallet::uncompressed_reader(inp)?;
         let version = inp.read_u64::<LittleEndian>()?;
         if version > LightWallet::serialized_version() {
-            let e = format!("Don't know how to read wallet version {}. Do you have the latest version?", version);
+            let e = format!("This wallet file is version {}, but this version of the wallet can only read up to version {}. Please upgrade to the latest version.",
+                            version, LightWallet::serialized_version());
             error!("{}", e);
             return Err(io::Error::new(ErrorKind::InvalidData, e));
         }
 
-        println!("Reading wallet version {}", version);
         info!("Reading wallet version {}", version);
 
+        let lw = LightWallet::read_version(version, inp, config)
+            .map_err(|e| io::Error::new(e.kind(), format!("Couldn't read wallet file (version {}): {}", version, e)))?;
+        lw.migrate(version);
+
+        Ok(lw)
+    }
+
+    /// A reader of the wallet serialized in `inp`, which decompresses it if it was written with
+    /// `write_compressed`. Files are told apart by their first 8 bytes, so either kind can be read.
+    pub fn uncompressed_reader<'a, R: Read + 'a>(mut inp: R) -> io::Result<Box<dyn Read + 'a>> {
+        let mut header = [0u8; 8];
+        inp.read_exact(&mut header)?;
+
+        if &header == COMPRESSED_WALLET_MAGIC {
+            info!("Reading compressed wallet");
+            Ok(Box::new(Decoder::new(inp)?))
+        } else {
+            Ok(Box::new(io::Cursor::new(header).chain(inp)))
+        }
+    }
+
+    // Read the rest of a wallet file, in the format of the given version
+    fn read_version<R: Read>(version: u64, inp: R, config: &LightClientConfig) -> io::Result<Self> {
         // At version 5, we're writing the rest of the file as a compressed stream (gzip)
         let mut reader: Box<dyn Read> = if version != 5 {
             info!("Reading direct");
             Box::new(inp)
         } else {
             info!("Reading libflat");
-            Box::new(Decoder::new(inp).unwrap())
+            Box::new(Decoder::new(inp)?)
         };
 
         let encrypted = if version >= 4 {
@@ -367,7 +766,7 @@ impl LightWallet {
             let mut txid_bytes = [0u8; 32];
             r.read_exact(&mut txid_bytes)?;
 
-            Ok((TxId{0: txid_bytes}, WalletTx::read(r).unwrap()))
+            Ok((TxId{0: txid_bytes}, WalletTx::read(r)?))
         })?;
         let txs = txs_tuples.into_iter().collect::<HashMap<TxId, WalletTx>>();
 
@@ -380,14 +779,95 @@ impl LightWallet {
 
         let birthday = reader.read_u64::<LittleEndian>()?;
 
+        let creation_height = if version >= 9 {
+            reader.read_u64::<LittleEndian>()?
+        } else {
+            birthday
+        };
+
+        let watch_only = if version >= 10 {
+            reader.read_u8()? > 0
+        } else {
+            false
+        };
+
+        // Accounts are read as trait objects, so the reader type doesn't nest for every account
+        let accounts = if version >= 11 {
+            Vector::read(&mut reader, |r| LightWallet::read(r as &mut dyn Read, config))?
+        } else {
+            vec![]
+        };
+
+        let pending_scan_height = if version >= 12 {
+            Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?
+        } else {
+            None
+        };
+
+        let sent_memos = if version >= 13 {
+            Vector::read(&mut reader, |r| {
+                let mut txid_bytes = [0u8; 32];
+                r.read_exact(&mut txid_bytes)?;
+
+                let outputs = Vector::read(r, |r| {
+                    let address = utils::read_string(&mut *r)?;
+                    let value = r.read_u64::<LittleEndian>()?;
+                    Ok((address, value, utils::read_string(r)?))
+                })?;
+
+                Ok((TxId{0: txid_bytes}, outputs))
+            })?.into_iter().collect::<HashMap<TxId, Vec<(String, u64, String)>>>()
+        } else {
+            HashMap::new()
+        };
+
+        // Diversified addresses made with `add_diversified_zaddr`. Addresses that received notes are
+        // also rebuilt from the notes when the wallet is loaded
+        let zaddresses = if version >= 14 {
+            Vector::read(&mut reader, |r| WalletDiversifiers::read(r, config))?
+        } else {
+            vec![]
+        };
+
+        let default_fee = if version >= 15 {
+            Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?
+        } else {
+            None
+        };
+
+        let sync_speed = if version >= 16 {
+            Optional::read(&mut reader, |r| r.read_f64::<LittleEndian>())?
+        } else {
+            None
+        };
+
+        let default_memo = if version >= 17 {
+            Optional::read(&mut reader, |r| utils::read_string(r))?
+        } else {
+            None
+        };
+
+        let (passphrase_seed, enc_passphrase_seed) = if version >= 18 {
+            (Optional::read(&mut reader, |r| Vector::read(r, |r| r.read_u8()))?,
+             Optional::read(&mut reader, |r| {
+                let nonce = Vector::read(&mut *r, |r| r.read_u8())?;
+                let cipher = Vector::read(r, |r| r.read_u8())?;
+                Ok((nonce, cipher))
+             })?)
+        } else {
+            (None, None)
+        };
+
         let lw = LightWallet{
             encrypted:   encrypted,
             unlocked:    !encrypted, // When reading from disk, if wallet is encrypted, it starts off locked.
             enc_seed:    enc_seed,
             nonce:       nonce,
             seed:        seed_bytes,
+            passphrase_seed,
+            enc_passphrase_seed,
             zkeys:       Arc::new(RwLock::new(zkeys)),
-            zaddresses:  Arc::new(RwLock::new(vec![])),
+            zaddresses:  Arc::new(RwLock::new(zaddresses)),
             tkeys:       Arc::new(RwLock::new(vec![])),
             taddresses:  Arc::new(RwLock::new(vec![])),
             blocks:      Arc::new(RwLock::new(blocks)),
@@ -395,23 +875,65 @@ impl LightWallet {
             mempool_txs: Arc::new(RwLock::new(HashMap::new())),
             config:      config.clone(),
             birthday,
+            creation_height,
+            watch_only,
             total_scan_duration: Arc::new(RwLock::new(vec![Duration::new(0, 0)])),
+            accounts:    Arc::new(RwLock::new(accounts)),
+            pending_scan_height: Arc::new(RwLock::new(pending_scan_height)),
+            sent_memos:  Arc::new(RwLock::new(sent_memos)),
+            default_fee: Arc::new(RwLock::new(default_fee)),
+            sync_speed:  Arc::new(RwLock::new(sync_speed)),
+            default_memo: Arc::new(RwLock::new(default_memo)),
+            auto_lock:   Arc::new(RwLock::new(AutoLock::default())),
+            file_version: version,
+            #[cfg(feature = "testing")]
+            tx_rng_seed: Arc::new(RwLock::new(None)),
         };
 
+        Ok(lw)
+    }
+
+    // Bring a wallet read from an older file up to date, for the things the file format alone
+    // doesn't cover. The upgraded wallet is written in the current format on the next save.
+    fn migrate(&self, from_version: u64) {
+        if from_version == LightWallet::serialized_version() {
+            return;
+        }
+
+        info!("Migrating wallet from version {} to {}", from_version, LightWallet::serialized_version());
+
         // Do a one-time fix of the spent_at_height for older wallets
-        if version <= 7 {
-            lw.fix_spent_at_height();
+        if from_version <= 7 {
+            self.fix_spent_at_height();
         }
+    }
 
-        Ok(lw)
+    /// The version of the file the wallet was read from. It is older than `serialized_version()`
+    /// until the upgraded wallet is saved.
+    pub fn file_version(&self) -> u64 {
+        self.file_version
     }
 
-    pub fn write<W: Write>(&self, mut writer: W) -> io::Result<()> {
+    pub fn write<W: Write>(&self, writer: W) -> io::Result<()> {
         if self.encrypted && self.unlocked {
             return Err(Error::new(ErrorKind::InvalidInput,
                         format!("Cannot write while wallet is unlocked while encrypted.")));
         }
 
+        self.write_as_locked(writer)
+    }
+
+    /// Write the wallet as it would be written once it's locked, without locking it. An unlocked
+    /// encrypted wallet is written without its seed and secret keys, and stays unlocked. Any other
+    /// wallet is written the same as by `write`.
+    pub fn write_snapshot<W: Write>(&self, writer: W) -> io::Result<()> {
+        self.write_as_locked(writer)
+    }
+
+    fn write_as_locked<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        // The secrets that `lock` would empty are left out
+        let strip_secrets = self.encrypted && self.unlocked;
+
         // Write the version
         writer.write_u64::<LittleEndian>(LightWallet::serialized_version())?;
 
@@ -425,18 +947,25 @@ impl LightWallet {
         Vector::write(&mut writer, &self.nonce, |w, b| w.write_u8(*b))?;
 
         // Write the seed
-        writer.write_all(&self.seed)?;
+        writer.write_all(if strip_secrets { &[0u8; 32] } else { &self.seed })?;
 
         // Flush after writing the seed, so in case of a disaster, we can still recover the seed.
         writer.flush()?;
 
         // Write all the wallet's keys
         Vector::write(&mut writer, &self.zkeys.read().unwrap(),
-             |w, zk| zk.write(w)
+             |w, zk| if strip_secrets {
+                 let mut locked = zk.clone();
+                 locked.lock()?;
+                 locked.write(w)
+             } else {
+                 zk.write(w)
+             }
         )?;
 
         // Write the transparent private keys
-        Vector::write(&mut writer, &self.tkeys.read().unwrap(),
+        let tkeys = if strip_secrets { vec![] } else { self.tkeys.read().unwrap().clone() };
+        Vector::write(&mut writer, &tkeys,
             |w, pk| w.write_all(&pk[..])
         )?;
 
@@ -464,7 +993,63 @@ impl LightWallet {
 
         // While writing the birthday, get it from the fn so we recalculate it properly
         // in case of rescans etc...
-        writer.write_u64::<LittleEndian>(self.get_birthday())
+        writer.write_u64::<LittleEndian>(self.get_birthday())?;
+
+        writer.write_u64::<LittleEndian>(self.creation_height)?;
+
+        writer.write_u8(if self.watch_only {1} else {0})?;
+
+        Vector::write(&mut writer, &self.accounts.read().unwrap(), |w, a| if strip_secrets {
+            a.write_as_locked(w as &mut dyn Write)
+        } else {
+            a.write(w as &mut dyn Write)
+        })?;
+
+        Optional::write(&mut writer, &*self.pending_scan_height.read().unwrap(), |w, h| w.write_u64::<LittleEndian>(*h))?;
+
+        // Sorted, like the txs, so that wallets are deterministically saved
+        let sent_memos = self.sent_memos.read().unwrap();
+        let mut memos = sent_memos.iter().collect::<Vec<(&TxId, &Vec<(String, u64, String)>)>>();
+        memos.sort_by(|a, b| a.0.partial_cmp(b.0).unwrap());
+
+        Vector::write(&mut writer, &memos, |w, (txid, outputs)| {
+            w.write_all(&txid.0)?;
+            Vector::write(w, outputs, |w, (address, value, memo)| {
+                utils::write_string(&mut *w, address)?;
+                w.write_u64::<LittleEndian>(*value)?;
+                utils::write_string(w, memo)
+            })
+        })?;
+
+        Vector::write(&mut writer, &self.zaddresses.read().unwrap(), |w, d| d.write(w))?;
+
+        Optional::write(&mut writer, &*self.default_fee.read().unwrap(), |w, f| w.write_u64::<LittleEndian>(*f))?;
+
+        Optional::write(&mut writer, &*self.sync_speed.read().unwrap(), |w, s| w.write_f64::<LittleEndian>(*s))?;
+
+        Optional::write(&mut writer, &*self.default_memo.read().unwrap(), |w, m| utils::write_string(w, m))?;
+
+        // If the wallet is encrypted, it is locked here, so only the encrypted passphrase seed is written
+        let passphrase_seed = if strip_secrets { None } else { self.passphrase_seed.clone() };
+        Optional::write(&mut writer, &passphrase_seed, |w, s| Vector::write(w, s, |w, b| w.write_u8(*b)))?;
+        Optional::write(&mut writer, &self.enc_passphrase_seed, |w, (nonce, cipher)| {
+            Vector::write(&mut *w, nonce, |w, b| w.write_u8(*b))?;
+            Vector::write(w, cipher, |w, b| w.write_u8(*b))
+        })
+    }
+
+    /// Write the wallet like `write`, compressed with gzip after a magic header, which `read` detects.
+    /// The order is serialize, encrypt, then compress: the wallet is serialized exactly as `write`
+    /// does, so the seed and keys of an encrypted wallet are already encrypted, and only then is the
+    /// result compressed. Compression never sees the secrets of an encrypted wallet in the clear.
+    pub fn write_compressed<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        writer.write_all(COMPRESSED_WALLET_MAGIC)?;
+
+        let mut encoder = Encoder::new(writer)?;
+        self.write(&mut encoder)?;
+        encoder.finish().into_result()?;
+
+        Ok(())
     }
 
     pub fn note_address(hrp: &str, note: &SaplingNoteData) -> Option<String> {
@@ -482,6 +1067,11 @@ impl LightWallet {
         }
     }
 
+    /// The height at which this wallet's seed was created (or the birthday it was restored with)
+    pub fn get_creation_height(&self) -> u64 {
+        self.creation_height
+    }
+
     // Get the first block that this wallet has a tx in. This is often used as the wallet's "birthday"
     // If there are no Txns, then the actual birthday (which is recorder at wallet creation) is returned
     // If no birthday was recorded, return the sapling activation height
@@ -496,6 +1086,19 @@ impl LightWallet {
             .unwrap_or(&cmp::max(self.birthday, self.config.sapling_activation_height))
     }
 
+    /// The encoded full viewing key of a z address in the wallet, including diversified addresses.
+    /// Viewing keys are not encrypted, so this works while the wallet is locked.
+    pub fn get_z_viewing_key(&self, address: &str) -> Option<String> {
+        let extfvk = self.zkeys.read().unwrap().iter()
+            .find(|k| encode_payment_address(self.config.hrp_sapling_address(), &k.zaddress) == address)
+            .map(|k| k.extfvk.clone())
+            .or_else(|| self.zaddresses.read().unwrap().iter()
+                .find(|d| d.zaddress == address)
+                .map(|d| d.extfvk.clone()))?;
+
+        Some(encode_extended_full_viewing_key(self.config.hrp_sapling_viewing_key(), &extfvk))
+    }
+
     // Get all z-address private keys. Returns a Vector of (address, privatekey, viewkey)
     pub fn get_z_private_keys(&self) -> Vec<(String, String, String)> {
         //Collect Default Addresses
@@ -551,6 +1154,10 @@ impl LightWallet {
     /// at the next position and add it to the wallet.
     /// NOTE: This does NOT rescan
     pub fn add_zaddr(&self) -> String {
+        if self.watch_only {
+            return "Error: Can't add addresses to a watch-only wallet".to_string();
+        }
+
         if !self.unlocked {
             return "Error: Can't add key while wallet is locked".to_string();
         }
@@ -562,10 +1169,10 @@ impl LightWallet {
             .map_or(0, |zk| zk.hdkey_num.unwrap() + 1);
 
 
-        let bip39_seed = bip39::Seed::new(&Mnemonic::from_entropy(&self.seed, Language::English).unwrap(), "");
+        let bip39_seed = self.bip39_seed();
 
         let (extsk, _, _) =
-            LightWallet::get_zaddr_from_bip39seed(&self.config, &bip39_seed.as_bytes(), pos);
+            LightWallet::get_zaddr_from_bip39seed(&self.config, &bip39_seed, pos);
 
         // let zaddr = encode_payment_address(self.config.hrp_sapling_address(), &address);
         let newkey = WalletZKey::new_hdkey(pos, extsk);
@@ -574,18 +1181,79 @@ impl LightWallet {
         encode_payment_address(self.config.hrp_sapling_address(), &newkey.zaddress)
     }
 
+    /// Adds a new diversified address of the key that `address` belongs to. The new address is the
+    /// key's first diversified address that isn't in the wallet yet, so a key always gives the same
+    /// addresses in the same order. It receives to the same key, so it shares that key's balance.
+    /// NOTE: This does NOT rescan, since the key's notes are already being scanned for
+    pub fn add_diversified_zaddr(&self, address: &str) -> Result<String, String> {
+        let extfvk = self.zaddress_extfvk(address)
+            .ok_or(format!("{} is not a z address in this wallet", address))?;
+
+        let existing = self.get_all_zaddresses();
+        let exhausted = || "No more diversified addresses for this key".to_string();
+
+        let mut index = DiversifierIndex::new();
+        loop {
+            let (found, pa) = extfvk.address(index).map_err(|_| exhausted())?;
+
+            let zaddress = encode_payment_address(self.config.hrp_sapling_address(), &pa);
+            if !existing.contains(&zaddress) {
+                self.zaddresses.write().unwrap().push(WalletDiversifiers {
+                    extfvk,
+                    diversifier: pa.diversifier().clone(),
+                    zaddress:    zaddress.clone(),
+                });
+
+                return Ok(zaddress);
+            }
+
+            // Keep looking after the diversifier that was just used
+            index = found;
+            index.increment().map_err(|_| exhausted())?;
+        }
+    }
+
+    /// The viewing key of the wallet's key that a z address belongs to. Every diversified address
+    /// of a key belongs to it, whether or not the wallet has seen that address before.
+    pub fn zaddress_extfvk(&self, address: &str) -> Option<ExtendedFullViewingKey> {
+        let pa = match decode_payment_address(self.config.hrp_sapling_address(), address) {
+            Ok(Some(pa)) => pa,
+            _            => return None,
+        };
+
+        self.zkeys.read().unwrap().iter()
+            .find(|zk| zk.extfvk.fvk.vk.to_payment_address(pa.diversifier().clone(), &JUBJUB)
+                        .map(|a| encode_payment_address(self.config.hrp_sapling_address(), &a) == address)
+                        .unwrap_or(false))
+            .map(|zk| zk.extfvk.clone())
+    }
+
+    /// The default address of the key that a z address belongs to. All the diversified addresses
+    /// of a key have the same key address.
+    pub fn zaddress_key_address(&self, address: &str) -> Option<String> {
+        let extfvk = self.zaddress_extfvk(address)?;
+
+        self.zkeys.read().unwrap().iter()
+            .find(|zk| zk.extfvk == extfvk)
+            .map(|zk| encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress))
+    }
+
     /// Add a new t address to the wallet. This will derive a new address from the seed
     /// at the next position.
     /// NOTE: This will not rescan the wallet
     pub fn add_taddr(&self) -> String {
+        if self.watch_only {
+            return "Error: Can't add addresses to a watch-only wallet".to_string();
+        }
+
         if !self.unlocked {
             return "Error: Can't add key while wallet is locked".to_string();
         }
 
         let pos = self.tkeys.read().unwrap().len() as u32;
-        let bip39_seed = bip39::Seed::new(&Mnemonic::from_entropy(&self.seed, Language::English).unwrap(), "");
+        let bip39_seed = self.bip39_seed();
 
-        let sk = LightWallet::get_taddr_from_bip39seed(&self.config, &bip39_seed.as_bytes(), pos);
+        let sk = LightWallet::get_taddr_from_bip39seed(&self.config, &bip39_seed, pos);
         let address = self.address_from_sk(&sk);
 
         self.tkeys.write().unwrap().push(sk);
@@ -594,46 +1262,202 @@ impl LightWallet {
         address
     }
 
-    // Add a new imported spending key to the wallet
-    /// NOTE: This will not rescan the wallet
-    pub fn add_imported_sk(&mut self, sk: String, birthday: u64) -> String {
-        if self.encrypted {
-            return "Error: Can't import spending key while wallet is encrypted".to_string();
+    /// The HD derivation index of one of the wallet's addresses. Imported and diversified addresses
+    /// aren't derived from the seed at an index, so they have none.
+    pub fn address_hd_index(&self, address: &str) -> Option<u32> {
+        // All t addresses are derived from the seed, in order
+        if let Some(pos) = self.taddresses.read().unwrap().iter().position(|a| a == address) {
+            return Some(pos as u32);
         }
 
-        // First, try to interpret the key
-        let extsk = match decode_extended_spending_key(self.config.hrp_sapling_private_key(), &sk) {
-            Ok(Some(k)) => k,
-            Ok(None) => return format!("Error: Couldn't decode spending key"),
-            Err(e) => return format!("Error importing spending key: {}", e)
-        };
-
-        // Make sure the key doesn't already exist
-        if self.zkeys.read().unwrap().iter().find(|&wk| wk.extsk.is_some() && wk.extsk.as_ref().unwrap() == &extsk.clone()).is_some() {
-            return "Error: Key already exists".to_string();
-        }
+        self.zkeys.read().unwrap().iter()
+            .find(|zk| encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress) == address)
+            .and_then(|zk| zk.hdkey_num)
+    }
 
-        let extfvk = ExtendedFullViewingKey::from(&extsk);
-        let zaddress = {
-            let mut zkeys = self.zkeys.write().unwrap();
-            let maybe_existing_zkey = zkeys.iter_mut().find(|wk| wk.extfvk == extfvk);
+    /// For each z key: its default address, the kind of key, its ZIP-32 derivation path if it was
+    /// derived from the seed, and its ZIP-32 fingerprint
+    pub fn get_zkey_info(&self) -> Vec<(String, &'static str, Option<String>, [u8; 32])> {
+        let coin_type = self.config.get_coin_type();
+
+        self.zkeys.read().unwrap().iter()
+            .map(|zk| {
+                let key_type = match zk.keytype {
+                    WalletZKeyType::HdKey               => "hd",
+                    WalletZKeyType::ImportedSpendingKey => "imported_spending_key",
+                    WalletZKeyType::ImportedViewKey     => "imported_viewing_key",
+                };
 
-            // If the viewing key exists, and is now being upgraded to the spending key, replace it in-place
-            if maybe_existing_zkey.is_some() {
-                let mut existing_zkey = maybe_existing_zkey.unwrap();
-                existing_zkey.extsk = Some(extsk);
-                existing_zkey.keytype = WalletZKeyType::ImportedSpendingKey;
-                existing_zkey.zaddress.clone()
-            } else {
-                let newkey = WalletZKey::new_imported_sk(extsk);
-                zkeys.push(newkey.clone());
-                newkey.zaddress
-            }
-        };
+                (encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress),
+                 key_type, zk.derivation_path(coin_type), zk.fingerprint())
+            })
+            .collect()
+    }
 
-        // Adjust wallet birthday
-        if birthday < self.birthday {
-            self.birthday = if birthday < self.config.sapling_activation_height {self.config.sapling_activation_height} else {birthday};
+    /// Re-derive every address of the wallet from the seed or from its imported key, as
+    /// (address, source, HD index, problem). The source is "hd", "imported_spending_key",
+    /// "imported_viewing_key", "diversified" or "hd_transparent", and the problem is None if the
+    /// address is what its key derives. This only reads the keys, it doesn't rescan anything.
+    pub fn verify_addresses(&self) -> Result<Vec<(String, &'static str, Option<u32>, Option<String>)>, String> {
+        if !self.unlocked {
+            return Err("Can't verify addresses while wallet is locked".to_string());
+        }
+
+        let hrp = self.config.hrp_sapling_address();
+        let bip39_seed = self.bip39_seed();
+        let zkeys = self.zkeys.read().unwrap();
+
+        let mut results = zkeys.iter().map(|zk| {
+            let address = encode_payment_address(hrp, &zk.zaddress);
+
+            // Every key's address is the default address of its viewing key
+            let address_problem = match zk.extfvk.default_address() {
+                Ok((_, pa)) if encode_payment_address(hrp, &pa) == address => None,
+                _ => Some("Isn't the default address of its viewing key".to_string()),
+            };
+
+            match zk.keytype {
+                WalletZKeyType::HdKey => {
+                    let pos = zk.hdkey_num.unwrap();
+                    let (_, extfvk, _) = LightWallet::get_zaddr_from_bip39seed(&self.config, &bip39_seed, pos);
+                    let problem = if extfvk != zk.extfvk {
+                        Some(format!("Doesn't derive from the seed at index {}", pos))
+                    } else {
+                        address_problem
+                    };
+                    (address, "hd", Some(pos), problem)
+                },
+                WalletZKeyType::ImportedSpendingKey => {
+                    let problem = match &zk.extsk {
+                        Some(extsk) if ExtendedFullViewingKey::from(extsk) != zk.extfvk =>
+                            Some("The spending key doesn't match the address".to_string()),
+                        _ => address_problem,
+                    };
+                    (address, "imported_spending_key", None, problem)
+                },
+                WalletZKeyType::ImportedViewKey => (address, "imported_viewing_key", None, address_problem),
+            }
+        }).collect::<Vec<_>>();
+
+        results.extend(self.zaddresses.read().unwrap().iter().map(|d| {
+            let derived = d.extfvk.fvk.vk.to_payment_address(d.diversifier, &JUBJUB)
+                .map(|pa| encode_payment_address(hrp, &pa));
+            let problem = if !zkeys.iter().any(|zk| zk.extfvk == d.extfvk) {
+                Some("Its key isn't in the wallet".to_string())
+            } else if derived.as_ref() != Some(&d.zaddress) {
+                Some("Doesn't derive from its key's diversifier".to_string())
+            } else {
+                None
+            };
+            (d.zaddress.clone(), "diversified", None, problem)
+        }));
+
+        results.extend(self.taddresses.read().unwrap().iter().enumerate().map(|(pos, address)| {
+            let sk = LightWallet::get_taddr_from_bip39seed(&self.config, &bip39_seed, pos as u32);
+            let problem = if &self.address_from_sk(&sk) != address {
+                Some(format!("Doesn't derive from the seed at index {}", pos))
+            } else {
+                None
+            };
+            (address.clone(), "hd_transparent", Some(pos as u32), problem)
+        }));
+
+        Ok(results)
+    }
+
+    /// For each address that was ever used, the height at which it first received funds and the last
+    /// height at which it received or spent them. Only mined transactions count, so addresses that
+    /// never received anything are not in the map.
+    pub fn address_activity_heights(&self) -> HashMap<String, (i32, i32)> {
+        let txs = self.txs.read().unwrap();
+        let spent_height = |txid: Option<TxId>| txid.and_then(|txid| txs.get(&txid).map(|wtx| wtx.block));
+
+        let mut activity: HashMap<String, (i32, i32)> = HashMap::new();
+        let mut record = |address: String, received: i32, spent: Option<i32>| {
+            let last = cmp::max(received, spent.unwrap_or(received));
+            let heights = activity.entry(address).or_insert((received, last));
+            heights.0 = cmp::min(heights.0, received);
+            heights.1 = cmp::max(heights.1, last);
+        };
+
+        for wtx in txs.values() {
+            for nd in wtx.notes.iter() {
+                if let Some(address) = LightWallet::note_address(self.config.hrp_sapling_address(), nd) {
+                    record(address, wtx.block, nd.spent_at_height.or(spent_height(nd.spent)));
+                }
+            }
+
+            for utxo in wtx.utxos.iter() {
+                record(utxo.address.clone(), wtx.block, spent_height(utxo.spent));
+            }
+        }
+
+        activity
+    }
+
+    /// Derive the z and t addresses at HD index `pos` from the seed, without adding them to the wallet.
+    /// The same seed always gives the same addresses, so this can be used to rebuild the address set.
+    pub fn addresses_at_index(&self, pos: u32) -> Result<(String, String), String> {
+        if self.watch_only {
+            return Err("Can't derive addresses in a watch-only wallet".to_string());
+        }
+
+        if !self.unlocked {
+            return Err("Can't derive addresses while wallet is locked".to_string());
+        }
+
+        let bip39_seed = self.bip39_seed();
+
+        let (_, _, zaddress) = LightWallet::get_zaddr_from_bip39seed(&self.config, &bip39_seed, pos);
+        let sk = LightWallet::get_taddr_from_bip39seed(&self.config, &bip39_seed, pos);
+
+        Ok((encode_payment_address(self.config.hrp_sapling_address(), &zaddress), self.address_from_sk(&sk)))
+    }
+
+    // Add a new imported spending key to the wallet
+    /// NOTE: This will not rescan the wallet
+    pub fn add_imported_sk(&mut self, sk: String, birthday: u64) -> String {
+        if self.watch_only {
+            return "Error: Can't import spending key into a watch-only wallet".to_string();
+        }
+
+        if self.encrypted {
+            return "Error: Can't import spending key while wallet is encrypted".to_string();
+        }
+
+        // First, try to interpret the key
+        let extsk = match decode_extended_spending_key(self.config.hrp_sapling_private_key(), &sk) {
+            Ok(Some(k)) => k,
+            Ok(None) => return format!("Error: Couldn't decode spending key"),
+            Err(e) => return format!("Error importing spending key: {}", e)
+        };
+
+        // Make sure the key doesn't already exist
+        if self.zkeys.read().unwrap().iter().find(|&wk| wk.extsk.is_some() && wk.extsk.as_ref().unwrap() == &extsk.clone()).is_some() {
+            return "Error: Key already exists".to_string();
+        }
+
+        let extfvk = ExtendedFullViewingKey::from(&extsk);
+        let zaddress = {
+            let mut zkeys = self.zkeys.write().unwrap();
+            let maybe_existing_zkey = zkeys.iter_mut().find(|wk| wk.extfvk == extfvk);
+
+            // If the viewing key exists, and is now being upgraded to the spending key, replace it in-place
+            if maybe_existing_zkey.is_some() {
+                let mut existing_zkey = maybe_existing_zkey.unwrap();
+                existing_zkey.extsk = Some(extsk);
+                existing_zkey.keytype = WalletZKeyType::ImportedSpendingKey;
+                existing_zkey.zaddress.clone()
+            } else {
+                let newkey = WalletZKey::new_imported_sk(extsk);
+                zkeys.push(newkey.clone());
+                newkey.zaddress
+            }
+        };
+
+        // Adjust wallet birthday
+        if birthday < self.birthday {
+            self.birthday = if birthday < self.config.sapling_activation_height {self.config.sapling_activation_height} else {birthday};
         }
 
         encode_payment_address(self.config.hrp_sapling_address(), &zaddress)
@@ -673,12 +1497,20 @@ impl LightWallet {
     /// After this, the wallet's initial state will need to be set
     /// and the wallet will need to be rescanned
     pub fn clear_blocks(&self) {
+        for account in self.accounts.read().unwrap().iter() {
+            account.clear_blocks();
+        }
+
         self.blocks.write().unwrap().clear();
         self.txs.write().unwrap().clear();
         self.mempool_txs.write().unwrap().clear();
     }
 
     pub fn set_initial_block(&self, height: i32, hash: &str, sapling_tree: &str) -> bool {
+        for account in self.accounts.read().unwrap().iter() {
+            account.set_initial_block(height, hash, sapling_tree);
+        }
+
         let mut blocks = self.blocks.write().unwrap();
         if !blocks.is_empty() {
             return false;
@@ -691,7 +1523,7 @@ impl LightWallet {
                 BlockHash::from_slice(&r)
             },
             Err(e) => {
-                eprintln!("{}", e);
+                error!("{}", e);
                 return false;
             }
         };
@@ -699,7 +1531,7 @@ impl LightWallet {
         let sapling_tree = match hex::decode(sapling_tree) {
             Ok(tree) => tree,
             Err(e) => {
-                eprintln!("{}", e);
+                error!("{}", e);
                 return false;
             }
         };
@@ -731,6 +1563,61 @@ impl LightWallet {
         Ok((block.height, hex::encode(blockhash), hex::encode(write_buf)))
     }
 
+    /// The root of the sapling commitment tree at the last scanned block, hex encoded in the
+    /// byte order it is serialized in transactions (the anchor).
+    pub fn get_sapling_tree_root(&self) -> Result<String, String> {
+        let blocks = self.blocks.read().unwrap();
+
+        let block = match blocks.last() {
+            Some(block) => block,
+            None => return Err("Couldn't get a block height!".to_string())
+        };
+
+        let mut root = vec![];
+        block.tree.root().write(&mut root).map_err(|e| format!("Error writing commitment tree root {}", e))?;
+
+        Ok(hex::encode(root))
+    }
+
+    /// The root of the latest witness of every note, with the note's value, sorted by value
+    #[cfg(test)]
+    pub(crate) fn note_witness_roots(&self) -> Vec<(u64, Node)> {
+        let mut roots = self.txs.read().unwrap().values()
+            .flat_map(|wtx| wtx.notes.iter().map(|nd| (nd.note.value, nd.witnesses.last().unwrap().root())))
+            .collect::<Vec<_>>();
+        roots.sort_by_key(|(value, _)| *value);
+
+        roots
+    }
+
+    /// Problems with the wallet's notes, as (txid, index of the note in the tx, problem). Unspent
+    /// notes need a witness that is up to date with the last scanned block to be spent.
+    pub fn check_note_witnesses(&self) -> Vec<(TxId, usize, String)> {
+        let last_height = self.last_scanned_height();
+        let tree_root = self.blocks.read().unwrap().last().map(|block| block.tree.root());
+
+        let mut problems = vec![];
+        for wtx in self.txs.read().unwrap().values() {
+            for (i, nd) in wtx.notes.iter().enumerate() {
+                let problem = if wtx.block > last_height {
+                    format!("The transaction is at height {}, after the last scanned block {}", wtx.block, last_height)
+                } else if nd.spent.is_some() {
+                    continue;
+                } else if nd.witnesses.is_empty() {
+                    "The unspent note has no witness".to_string()
+                } else if tree_root.is_some() && nd.witnesses.last().map(|w| w.root()) != tree_root {
+                    format!("The note's witness doesn't match the commitment tree at block {}", last_height)
+                } else {
+                    continue;
+                };
+
+                problems.push((wtx.txid, i, problem));
+            }
+        }
+
+        problems
+    }
+
     pub fn last_scanned_height(&self) -> i32 {
         self.blocks.read().unwrap()
             .last()
@@ -738,6 +1625,173 @@ impl LightWallet {
             .unwrap_or(self.config.sapling_activation_height as i32 - 1)
     }
 
+    /// Ask the next sync to rescan from `height`, so the blocks of a newly imported key are scanned
+    /// without a full rescan right away. Earlier heights win over later ones.
+    pub fn schedule_scan_from(&self, height: u64) {
+        let height = cmp::max(height, self.config.sapling_activation_height);
+
+        let mut pending = self.pending_scan_height.write().unwrap();
+        *pending = Some(pending.map(|h| cmp::min(h, height)).unwrap_or(height));
+    }
+
+    pub fn pending_scan_height(&self) -> Option<u64> {
+        *self.pending_scan_height.read().unwrap()
+    }
+
+    /// The fee used by sends that don't give one. This is DEFAULT_FEE unless it was changed
+    pub fn default_fee(&self) -> u64 {
+        use std::convert::TryInto;
+
+        self.default_fee.read().unwrap().unwrap_or(DEFAULT_FEE.try_into().unwrap())
+    }
+
+    /// Whether the default fee was changed with `set_default_fee`
+    pub fn has_custom_default_fee(&self) -> bool {
+        self.default_fee.read().unwrap().is_some()
+    }
+
+    /// Set the fee used by sends that don't give one. `None` goes back to DEFAULT_FEE.
+    pub fn set_default_fee(&self, fee: Option<u64>) {
+        *self.default_fee.write().unwrap() = fee;
+    }
+
+    /// The memo attached to sends to z-addresses that don't give a memo of their own, if there is one
+    pub fn default_memo(&self) -> Option<String> {
+        self.default_memo.read().unwrap().clone()
+    }
+
+    /// Set the memo attached to sends that don't give one. `None` sends no memo.
+    pub fn set_default_memo(&self, memo: Option<String>) {
+        *self.default_memo.write().unwrap() = memo;
+    }
+
+    /// Blocks scanned per second, averaged over the previous syncs. None if no sync was measured yet
+    pub fn sync_speed(&self) -> Option<f64> {
+        *self.sync_speed.read().unwrap()
+    }
+
+    /// Add the speed of a finished sync to the average. Recent syncs weigh more, so the average
+    /// follows a change of device or server.
+    pub fn record_sync_speed(&self, blocks_per_second: f64) {
+        if !blocks_per_second.is_finite() || blocks_per_second <= 0.0 {
+            return;
+        }
+
+        let mut speed = self.sync_speed.write().unwrap();
+        *speed = Some(match *speed {
+            Some(avg) => avg * 0.7 + blocks_per_second * 0.3,
+            None      => blocks_per_second,
+        });
+    }
+
+    /// The outgoing metadata of a tx as (address, value, memo), with the memos the wallet attached
+    /// when it sent the tx filled in where they're missing
+    pub fn outgoing_with_sent_memos(&self, wtx: &WalletTx) -> Vec<(String, u64, Memo)> {
+        let sent_memos = self.sent_memos.read().unwrap();
+        let mut unused = sent_memos.get(&wtx.txid).map(|outputs| outputs.iter().collect::<Vec<_>>()).unwrap_or(vec![]);
+
+        wtx.outgoing_metadata.iter().map(|om| {
+            let mut memo = om.memo.clone();
+            if memo == Memo::default() {
+                // Each sent memo fills in one output, so chunks sent to the same address don't repeat
+                if let Some(i) = unused.iter().position(|(address, value, _)| address == &om.address && *value == om.value) {
+                    if let Ok(m) = utils::interpret_memo_string(&unused.remove(i).2) {
+                        memo = m;
+                    }
+                }
+            }
+
+            (om.address.clone(), om.value, memo)
+        }).collect()
+    }
+
+    /// For each tx that spent some of the wallet's funds: how many of the wallet's notes and utxos
+    /// it spent, and the total value of those utxos. Unconfirmed spends count as well.
+    pub fn spends_by_tx(&self) -> HashMap<TxId, (usize, usize, u64)> {
+        let mut spends: HashMap<TxId, (usize, usize, u64)> = HashMap::new();
+
+        for wtx in self.txs.read().unwrap().values() {
+            for nd in wtx.notes.iter() {
+                if let Some(txid) = nd.spent.as_ref().or(nd.unconfirmed_spent.as_ref()) {
+                    spends.entry(txid.clone()).or_default().0 += 1;
+                }
+            }
+
+            for utxo in wtx.utxos.iter() {
+                if let Some(txid) = utxo.spent.as_ref().or(utxo.unconfirmed_spent.as_ref()) {
+                    let entry = spends.entry(txid.clone()).or_default();
+                    entry.1 += 1;
+                    entry.2 += utxo.value;
+                }
+            }
+        }
+
+        spends
+    }
+
+    /// The fee the serialized tx `raw_tx` paid, if the wallet can tell. A tx only has the values of its
+    /// outputs and its shielded value balance, not the values of its transparent inputs, so the wallet
+    /// has to have funded every one of its inputs. `spends` is from `spends_by_tx`.
+    pub fn fee_paid(spends: &HashMap<TxId, (usize, usize, u64)>, raw_tx: &[u8]) -> Option<u64> {
+        let tx = Transaction::read(raw_tx).ok()?;
+        let (notes_spent, utxos_spent, transparent_in) = spends.get(&tx.txid())?;
+
+        // Some of the inputs aren't the wallet's
+        if *notes_spent != tx.shielded_spends.len() || *utxos_spent != tx.vin.len() {
+            return None;
+        }
+
+        let transparent_out: i64 = tx.vout.iter().map(|o| i64::from(o.value)).sum();
+        let fee = *transparent_in as i64 + i64::from(tx.value_balance) - transparent_out;
+
+        if fee < 0 { None } else { Some(fee as u64) }
+    }
+
+    /// The fee of a wallet tx, from its serialized tx if the wallet has it, or else from what the
+    /// wallet recorded about it, as for txs from before WalletTx v6.
+    pub fn tx_fee(spends: &HashMap<TxId, (usize, usize, u64)>, wtx: &WalletTx) -> Option<u64> {
+        match &wtx.raw_tx {
+            Some(raw) => LightWallet::fee_paid(spends, raw),
+            None      => LightWallet::fee_from_metadata(wtx),
+        }
+    }
+
+    /// What the wallet spent in a tx, less what came back to the wallet and what it sent. This is
+    /// only right for the wallet's own sends, where it funded every input, so it needs the full tx
+    /// to have been scanned for what was sent, and is None if the result would be negative.
+    fn fee_from_metadata(wtx: &WalletTx) -> Option<u64> {
+        let spent = wtx.total_shielded_value_spent + wtx.total_transparent_value_spent;
+        if spent == 0 || !wtx.full_tx_scanned {
+            return None;
+        }
+
+        let received = wtx.notes.iter().map(|nd| nd.note.value).sum::<u64>()
+                        + wtx.utxos.iter().map(|u| u.value).sum::<u64>();
+        let sent = wtx.outgoing_metadata.iter().map(|om| om.value).sum::<u64>();
+
+        spent.checked_sub(received + sent)
+    }
+
+    /// Clear the scheduled scan, returning the height it was scheduled from
+    pub fn take_pending_scan_height(&self) -> Option<u64> {
+        self.pending_scan_height.write().unwrap().take()
+    }
+
+    /// The hash of the stored block at `height`, if the wallet still has it
+    pub fn block_hash_at(&self, height: i32) -> Option<BlockHash> {
+        self.blocks.read().unwrap().iter()
+            .find(|block| block.height == height)
+            .map(|block| block.hash)
+    }
+
+    /// The height of the oldest block the wallet still keeps. Only the last `MAX_REORG` blocks
+    /// are kept, so the wallet can't be rolled back to before this height.
+    pub fn earliest_stored_height(&self) -> Option<i32> {
+        self.blocks.read().unwrap()
+            .first()
+            .map(|block| block.height)
+    }
+
     /// Determines the target height for a transaction, and the offset from which to
     /// select anchors, based on the current synchronised block chain.
     fn get_target_height_and_anchor_offset(&self) -> Option<(u32, usize)> {
@@ -762,6 +1816,76 @@ impl LightWallet {
         }
     }
 
+    /// The notes to the given address that can be spent right now, with their values. Notes
+    /// without a known output index (from older wallets that haven't been rescanned) are skipped.
+    /// Pick the notes of `from`'s key that pay for `target_value`, largest first, for a spend that
+    /// is signed and proven outside the wallet. Unlike `build_transaction`, this doesn't need the
+    /// spending key, so it works for watch-only wallets. Returns the target height of the spend, the
+    /// height of the anchor that the notes' witnesses are at, and the notes.
+    pub fn select_notes_for_external_spend(&self, from: &str, target_value: u64) -> Result<(u32, u32, Vec<ExternalSpendNote>), String> {
+        let (height, anchor_offset) = self.get_target_height_and_anchor_offset()
+            .ok_or("Cannot send funds before scanning any blocks".to_string())?;
+        let from_extfvk = self.zaddress_extfvk(from)
+            .ok_or(format!("{} is not a z-address of this wallet", from))?;
+
+        let mut candidates = self.txs.read().unwrap().iter()
+            .flat_map(|(txid, wtx)| wtx.notes.iter().map(move |nd| (*txid, nd)))
+            .filter(|(_, nd)| nd.extfvk == from_extfvk && nd.spent.is_none() && nd.unconfirmed_spent.is_none())
+            .filter(|(_, nd)| nd.output_index.is_some() && nd.witnesses.len() >= anchor_offset + 1)
+            .map(|(txid, nd)| ExternalSpendNote {
+                txid,
+                output_index: nd.output_index.unwrap(),
+                diversifier: nd.diversifier,
+                note: nd.note.clone(),
+                nullifier: nd.nullifier,
+                witness: nd.witnesses[nd.witnesses.len() - anchor_offset - 1].clone(),
+                extfvk: nd.extfvk.clone(),
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by(|a, b| b.note.value.cmp(&a.note.value));
+
+        let mut selected = vec![];
+        let mut selected_value = 0;
+        for note in candidates {
+            if selected_value >= target_value {
+                break;
+            }
+            selected_value += note.note.value;
+            selected.push(note);
+        }
+
+        if selected_value < target_value {
+            return Err(format!(
+                "Insufficient verified funds (have {}, need {}). NOTE: funds need {} confirmations before they can be spent.",
+                selected_value, target_value, self.config.anchor_offset + 1));
+        }
+
+        Ok((height, height - 1 - anchor_offset as u32, selected))
+    }
+
+    pub fn get_spendable_notes(&self, address: &str) -> Vec<(NoteOutpoint, u64)> {
+        let anchor_offset = match self.get_target_height_and_anchor_offset() {
+            Some((_, anchor_offset)) => anchor_offset,
+            None => return vec![],
+        };
+
+        let zkeys = self.zkeys.read().unwrap();
+        self.txs.read().unwrap().iter()
+            .flat_map(|(txid, wtx)| wtx.notes.iter().map(move |nd| (*txid, nd)))
+            .filter(|(_, nd)| LightWallet::note_address(self.config.hrp_sapling_address(), nd).as_deref() == Some(address))
+            .filter_map(|(txid, nd)| {
+                let extsk = zkeys.iter()
+                    .find(|zk| zk.extfvk == nd.extfvk)
+                    .and_then(|zk| zk.extsk.clone());
+
+                match (nd.output_index, SpendableNote::from(txid, nd, anchor_offset, &extsk)) {
+                    (Some(output_index), Some(_)) => Some((NoteOutpoint { txid, output_index }, nd.note.value)),
+                    _ => None
+                }
+            })
+            .collect()
+    }
+
     /// Get the height of the anchor block
     pub fn get_anchor_height(&self) -> u32 {
         match self.get_target_height_and_anchor_offset() {
@@ -770,6 +1894,262 @@ impl LightWallet {
         }
     }
 
+    /// Encrypt a message to a z-address, the same way a memo is encrypted in a note. Nothing is sent
+    /// to the chain. The result is version byte, epk, cmu and the encrypted note, and only the owner
+    /// of the address can decrypt it with `decrypt_message`.
+    pub fn encrypt_message(&self, to: &str, message: &str) -> Result<Vec<u8>, String> {
+        use ff::{Field, PrimeField};
+        use zcash_primitives::{note_encryption::SaplingNoteEncryption, primitives::Note};
+
+        let to = match address::RecipientAddress::from_str(to,
+                        self.config.hrp_sapling_address(),
+                        self.config.base58_pubkey_address(),
+                        self.config.base58_script_address()) {
+            Some(address::RecipientAddress::Shielded(to)) => to,
+            _ => return Err(format!("{} is not a z-address", to)),
+        };
+
+        let memo = utils::interpret_memo_string(&message.to_string())?;
+
+        // A 0 value note, that only exists to carry the memo. The ovk is random, because the
+        // message doesn't need to be recoverable by the sender.
+        let mut rng = OsRng;
+        let note = Note {
+            g_d  : to.diversifier().g_d::<Bls12>(&JUBJUB).unwrap(),
+            pk_d : to.pk_d().clone(),
+            value: 0,
+            r    : Fs::random(&mut rng),
+        };
+        let encryptor = SaplingNoteEncryption::new(OutgoingViewingKey(rng.gen()), note.clone(), to, memo, &mut rng);
+
+        let mut message = vec![ENCRYPTED_MESSAGE_VERSION];
+        encryptor.epk().write(&mut message).map_err(|e| e.to_string())?;
+        message.extend_from_slice(&note.cm(&JUBJUB).to_repr().0);
+        message.extend_from_slice(&encryptor.encrypt_note_plaintext());
+
+        Ok(message)
+    }
+
+    /// Decrypt a message made by `encrypt_message` with any of this wallet's keys. Returns the
+    /// address it was sent to and the memo.
+    pub fn decrypt_message(&self, message: &[u8]) -> Result<(String, Memo), String> {
+        const ENC_CIPHERTEXT_SIZE: usize = 580;
+
+        if message.len() != 1 + 32 + 32 + ENC_CIPHERTEXT_SIZE {
+            return Err(format!("Encrypted message should be {} bytes, found {}", 1 + 32 + 32 + ENC_CIPHERTEXT_SIZE, message.len()));
+        }
+        if message[0] != ENCRYPTED_MESSAGE_VERSION {
+            return Err(format!("Unknown encrypted message version {}", message[0]));
+        }
+
+        // The epk and cmu are laid out like in a compact output, so parse them the same way
+        let mut output = CompactOutput::new();
+        output.set_epk(message[1..33].to_vec());
+        output.set_cmu(message[33..65].to_vec());
+        let epk = output.epk().map_err(|_| "Invalid epk in encrypted message".to_string())?;
+        let cmu = output.cmu().map_err(|_| "Invalid cmu in encrypted message".to_string())?;
+
+        let ivks: Vec<_> = self.zkeys.read().unwrap().iter()
+            .map(|zk| zk.extfvk.fvk.vk.ivk())
+            .collect();
+
+        ivks.iter()
+            .find_map(|ivk| try_sapling_note_decryption(ivk, &epk, &cmu, &message[65..]))
+            .map(|(_note, to, memo)| (encode_payment_address(self.config.hrp_sapling_address(), &to), memo))
+            .ok_or("This wallet has no key that can decrypt the message".to_string())
+    }
+
+    // The diversified base point of a z-address's pk_d
+    fn message_base_point(&self, pa: &PaymentAddress<Bls12>) -> Result<edwards::Point<Bls12, PrimeOrder>, String> {
+        pa.diversifier().g_d::<Bls12>(&JUBJUB).ok_or(format!("Invalid diversifier in {}",
+            encode_payment_address(self.config.hrp_sapling_address(), pa)))
+    }
+
+    fn parse_message_address(&self, address: &str) -> Result<address::RecipientAddress, String> {
+        address::RecipientAddress::from_str(address,
+                self.config.hrp_sapling_address(),
+                self.config.base58_pubkey_address(),
+                self.config.base58_script_address())
+            .ok_or(format!("Invalid address {}", address))
+    }
+
+    // The Schnorr challenge, which binds the signature to the address and the message
+    fn message_challenge(r: &[u8], pa: &PaymentAddress<Bls12>, message: &str) -> Fs {
+        let mut a = r.to_vec();
+        a.extend_from_slice(&pa.diversifier().0);
+        pa.pk_d().write(&mut a).unwrap();
+
+        hash_to_scalar::<Bls12>(b"PirateMessageSig", &a, message.as_bytes())
+    }
+
+    // The hash a t-address signs: the double sha256 of the magic and the message, each prefixed
+    // with its compact size
+    fn transparent_message_hash(message: &str) -> secp256k1::Message {
+        let mut payload = vec![];
+        for part in [TRANSPARENT_MESSAGE_MAGIC.as_bytes(), message.as_bytes()].iter() {
+            match part.len() {
+                n if n < 0xfd    => payload.push(n as u8),
+                n if n <= 0xffff => { payload.push(0xfd); payload.write_u16::<LittleEndian>(n as u16).unwrap(); },
+                n                => { payload.push(0xfe); payload.write_u32::<LittleEndian>(n as u32).unwrap(); },
+            }
+            payload.extend_from_slice(part);
+        }
+
+        secp256k1::Message::from_slice(&double_sha256(&payload)).unwrap()
+    }
+
+    /// Sign a message to prove that this wallet holds `address`, which has to be one of the wallet's
+    /// addresses.
+    ///
+    /// For a t-address, this is the same base64 signature the full node's `signmessage` makes, made
+    /// with the address's private key. It proves that the signer can spend from the address.
+    ///
+    /// A z-address's spend authority can't be checked from the address alone, so for a z-address this
+    /// is a viewing key proof instead: a Schnorr signature by the address's incoming viewing key (ivk),
+    /// with the address's diversified base g_d as the generator, so the address's pk_d = ivk * g_d is
+    /// the public key. The viewing key is enough to make it, so a watch-only wallet can make it too,
+    /// and it proves that the signer can see the address's funds, not that they can spend them.
+    pub fn sign_message(&self, address: &str, message: &str) -> Result<String, String> {
+        match self.parse_message_address(address)? {
+            address::RecipientAddress::Transparent(_) => self.sign_transparent_message(address, message),
+            address::RecipientAddress::Shielded(pa)   => self.prove_viewing_key(&pa, address, message),
+        }
+    }
+
+    fn sign_transparent_message(&self, address: &str, message: &str) -> Result<String, String> {
+        if !self.is_unlocked_for_spending() {
+            return Err("Wallet is locked".to_string());
+        }
+
+        let sk = self.tkeys.read().unwrap().iter()
+            .find(|sk| self.address_from_sk(sk) == address)
+            .cloned()
+            .ok_or(format!("{} is not an address in this wallet", address))?;
+
+        let secp = secp256k1::Secp256k1::new();
+        let (recid, sig) = secp.sign_recoverable(&Self::transparent_message_hash(message), &sk).serialize_compact();
+
+        // The header byte says which key to recover, and that it is compressed
+        let mut signature = vec![27 + 4 + recid.to_i32() as u8];
+        signature.extend_from_slice(&sig);
+
+        Ok(base64::encode(&signature))
+    }
+
+    fn prove_viewing_key(&self, pa: &PaymentAddress<Bls12>, address: &str, message: &str) -> Result<String, String> {
+        use ff::{Field, PrimeField};
+
+        let g_d = self.message_base_point(pa)?;
+
+        let ivk = self.zkeys.read().unwrap().iter()
+            .find(|zk| zk.extfvk.fvk.vk.to_payment_address(pa.diversifier().clone(), &JUBJUB)
+                        .map(|a| encode_payment_address(self.config.hrp_sapling_address(), &a) == address)
+                        .unwrap_or(false))
+            .map(|zk| zk.extfvk.fvk.vk.ivk())
+            .ok_or(format!("{} is not an address in this wallet", address))?;
+
+        let r = Fs::random(&mut OsRng);
+        let mut r_bytes = vec![];
+        g_d.mul(r, &JUBJUB).write(&mut r_bytes).map_err(|e| e.to_string())?;
+
+        let c = Self::message_challenge(&r_bytes, pa, message);
+        let s = r + c * ivk;
+
+        let mut signature = r_bytes;
+        signature.extend_from_slice(&s.to_repr().0);
+
+        Ok(format!("{}:{}", MESSAGE_SIGNATURE_SCHEME, hex::encode(signature)))
+    }
+
+    /// Check a signature made by `sign_message`. Returns an error if the signature or address can't
+    /// be parsed, and false if the signature doesn't match the address and message.
+    pub fn verify_message(&self, address: &str, message: &str, signature: &str) -> Result<bool, String> {
+        match self.parse_message_address(address)? {
+            address::RecipientAddress::Transparent(_) => self.verify_transparent_message(address, message, signature),
+            address::RecipientAddress::Shielded(pa)   => self.verify_viewing_key_proof(&pa, message, signature),
+        }
+    }
+
+    fn verify_transparent_message(&self, address: &str, message: &str, signature: &str) -> Result<bool, String> {
+        use secp256k1::recovery::{RecoverableSignature, RecoveryId};
+
+        let sig = base64::decode(signature.trim()).map_err(|e| format!("Couldn't decode signature base64: {}", e))?;
+        if sig.len() != 65 {
+            return Err(format!("Signature should be 65 bytes, found {}", sig.len()));
+        }
+        if sig[0] < 27 || sig[0] > 34 {
+            return Err(format!("Unknown signature header {}", sig[0]));
+        }
+
+        let compressed = sig[0] >= 31;
+        let recid = RecoveryId::from_i32(((sig[0] - 27) & 3) as i32).map_err(|e| e.to_string())?;
+        let recoverable = match RecoverableSignature::from_compact(&sig[1..], recid) {
+            Ok(s)  => s,
+            Err(_) => return Ok(false),
+        };
+
+        let secp = secp256k1::Secp256k1::new();
+        let pk = match secp.recover(&Self::transparent_message_hash(message), &recoverable) {
+            Ok(pk) => pk,
+            Err(_) => return Ok(false),
+        };
+
+        let mut hash160 = ripemd160::Ripemd160::new();
+        if compressed {
+            hash160.input(Sha256::digest(&pk.serialize()[..]));
+        } else {
+            hash160.input(Sha256::digest(&pk.serialize_uncompressed()[..]));
+        }
+
+        Ok(hash160.result().to_base58check(&self.config.base58_pubkey_address(), &[]) == address)
+    }
+
+    fn verify_viewing_key_proof(&self, pa: &PaymentAddress<Bls12>, message: &str, signature: &str) -> Result<bool, String> {
+        use ff::PrimeField;
+        use std::convert::TryInto;
+
+        let g_d = self.message_base_point(pa)?;
+
+        let sig_hex = match signature.trim().splitn(2, ':').collect::<Vec<_>>()[..] {
+            [scheme, sig_hex] if scheme == MESSAGE_SIGNATURE_SCHEME => sig_hex,
+            [scheme, _] => return Err(format!("Unknown signature scheme '{}'", scheme)),
+            _ => return Err(format!("Signature should start with '{}:'", MESSAGE_SIGNATURE_SCHEME)),
+        };
+
+        let sig = hex::decode(sig_hex).map_err(|e| format!("Couldn't decode signature hex: {}", e))?;
+        if sig.len() != 64 {
+            return Err(format!("Signature should be 64 bytes, found {}", sig.len()));
+        }
+
+        let r_point = match edwards::Point::<Bls12, _>::read(&sig[..32], &JUBJUB).ok().and_then(|p| p.as_prime_order(&JUBJUB)) {
+            Some(p) => p,
+            None    => return Ok(false),
+        };
+        let s = match Fs::from_repr(FsRepr(sig[32..].try_into().unwrap())) {
+            Some(s) => s,
+            None    => return Ok(false),
+        };
+
+        let c = Self::message_challenge(&sig[..32], pa, message);
+
+        // s * g_d == R + c * pk_d
+        Ok(g_d.mul(s, &JUBJUB) == r_point.add(&pa.pk_d().mul(c, &JUBJUB), &JUBJUB))
+    }
+
+    /// Parse a txid in the byte-reversed hex it is displayed in
+    pub fn txid_from_str(txid_hex: &str) -> Result<TxId, String> {
+        let mut txid_bytes = hex::decode(txid_hex.trim()).map_err(|e| e.to_string())?;
+        if txid_bytes.len() != 32 {
+            return Err(format!("Expected 32 bytes, found {}", txid_bytes.len()));
+        }
+        txid_bytes.reverse();
+
+        let mut txid = TxId([0u8; 32]);
+        txid.0.copy_from_slice(&txid_bytes);
+
+        Ok(txid)
+    }
+
     pub fn memo_str(memo: &Option<Memo>) -> Option<String> {
         match memo {
             Some(memo) => {
@@ -782,6 +2162,102 @@ impl LightWallet {
         }
     }
 
+    /// Decode a memo given in `encoding` ("utf8", "hex" or "base64") into the memo string that
+    /// sends take. Binary memos are returned as "0x" followed by the hex of the bytes.
+    pub fn decode_memo(memo: &str, encoding: &str) -> Result<String, String> {
+        let bytes = match encoding {
+            "utf8"   => return Ok(memo.to_string()),
+            "hex"    => {
+                let hex_str = if memo.to_lowercase().starts_with("0x") { &memo[2..] } else { memo };
+                hex::decode(hex_str).map_err(|e| format!("Couldn't decode hex memo: {}", e))?
+            },
+            "base64" => base64::decode(memo).map_err(|e| format!("Couldn't decode base64 memo: {}", e))?,
+            _        => return Err(format!("Unknown memo encoding '{}'. Use 'utf8', 'hex' or 'base64'", encoding)),
+        };
+
+        if bytes.len() > MAX_MEMO_BYTES {
+            return Err(format!("Memo is {} bytes, but the maximum is {} bytes", bytes.len(), MAX_MEMO_BYTES));
+        }
+
+        Ok(format!("0x{}", hex::encode(bytes)))
+    }
+
+    /// Split a memo that is too long for a single output into chunks that each fit in a memo.
+    /// Every chunk starts with a "[index/total]" header, where index is 1-based, followed by the
+    /// next piece of the original memo (split on character boundaries), e.g. "[1/3]...", "[2/3]...".
+    /// Memos that already fit are returned unchanged, as a single chunk.
+    pub fn split_memo(memo: &str) -> Vec<String> {
+        if memo.as_bytes().len() <= MAX_MEMO_BYTES {
+            return vec![memo.to_string()];
+        }
+
+        // The header gets longer as the number of chunks grows, so size the chunks for the
+        // widest header with the given number of digits, and retry with more digits if needed.
+        let mut digits = 1;
+        loop {
+            let payload_len = MAX_MEMO_BYTES - (3 + 2 * digits);
+
+            let mut chunks = vec![];
+            let mut current = String::new();
+            for c in memo.chars() {
+                if current.len() + c.len_utf8() > payload_len {
+                    chunks.push(std::mem::replace(&mut current, String::new()));
+                }
+                current.push(c);
+            }
+            if !current.is_empty() {
+                chunks.push(current);
+            }
+
+            if chunks.len() < 10usize.pow(digits as u32) {
+                let total = chunks.len();
+                return chunks.into_iter().enumerate()
+                    .map(|(i, c)| format!("[{}/{}]{}", i + 1, total, c))
+                    .collect();
+            }
+
+            digits += 1;
+        }
+    }
+
+    /// Parse the "[index/total]" header of a memo chunk created by `split_memo`, returning
+    /// the index, total and the remaining contents of the chunk.
+    pub fn parse_memo_chunk(memo: &str) -> Option<(usize, usize, String)> {
+        if !memo.starts_with('[') {
+            return None;
+        }
+
+        let end = memo.find(']')?;
+        let mut parts = memo[1..end].splitn(2, '/');
+        let index = parts.next()?.parse::<usize>().ok()?;
+        let total = parts.next()?.parse::<usize>().ok()?;
+
+        // A single chunk is never created, so don't treat "[1/1]" as one
+        if total < 2 || index == 0 || index > total {
+            return None;
+        }
+
+        Some((index, total, memo[end + 1..].to_string()))
+    }
+
+    /// Reassemble a complete set of memo chunks (in any order) into the original memo.
+    /// Returns None if any chunk is missing, duplicated or not a chunk at all.
+    pub fn join_memo_chunks(memos: &[String]) -> Option<String> {
+        let mut chunks = memos.iter()
+            .map(|m| LightWallet::parse_memo_chunk(m))
+            .collect::<Option<Vec<_>>>()?;
+
+        chunks.sort_by_key(|(index, _, _)| *index);
+
+        let complete = chunks.iter().enumerate()
+            .all(|(i, (index, total, _))| *index == i + 1 && *total == chunks.len());
+        if !complete {
+            return None;
+        }
+
+        Some(chunks.into_iter().map(|(_, _, c)| c).collect::<Vec<_>>().join(""))
+    }
+
     pub fn get_all_zaddresses(&self) -> Vec<String> {
         let mut zaddrs: Vec<String> = self.zkeys.read().unwrap().iter().map( |zk| {
             encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress)
@@ -829,8 +2305,22 @@ impl LightWallet {
         }
     }
 
+    /// Was this wallet's seed phrase used with a BIP-39 passphrase? If so, the passphrase is needed
+    /// along with the seed phrase to restore the wallet.
+    pub fn has_passphrase(&self) -> bool {
+        self.passphrase_seed.is_some() || self.enc_passphrase_seed.is_some()
+    }
+
+    // The 64 byte BIP-39 seed that the HD keys are derived from. Only valid while the wallet is unlocked
+    fn bip39_seed(&self) -> Vec<u8> {
+        match &self.passphrase_seed {
+            Some(s) => s.clone(),
+            None => bip39::Seed::new(&Mnemonic::from_entropy(&self.seed, Language::English).unwrap(), "").as_bytes().to_vec()
+        }
+    }
+
     pub fn get_seed_phrase(&self) -> String {
-        if !self.unlocked {
+        if !self.unlocked || self.watch_only {
             return "".to_string();
         }
 
@@ -840,6 +2330,11 @@ impl LightWallet {
     }
 
     pub fn encrypt(&mut self, passwd: String) -> io::Result<()> {
+        // A watch-only wallet has nothing to encrypt
+        if self.watch_only {
+            return Ok(());
+        }
+
         if self.encrypted {
             return Err(io::Error::new(ErrorKind::AlreadyExists, "Wallet is already encrypted"));
         }
@@ -853,11 +2348,22 @@ impl LightWallet {
         self.enc_seed.copy_from_slice(&cipher);
         self.nonce = nonce.as_ref().to_vec();
 
+        // The passphrase seed gets its own nonce, since it is encrypted with the same key
+        self.enc_passphrase_seed = self.passphrase_seed.as_ref().map(|s| {
+            let nonce = secretbox::gen_nonce();
+            (nonce.as_ref().to_vec(), secretbox::seal(s, &nonce, &key))
+        });
+
         // Encrypt the individual keys
         self.zkeys.write().unwrap().iter_mut()
             .map(|k| k.encrypt(&key))
             .collect::<io::Result<Vec<()>>>()?;
 
+        // Every account is encrypted with the same password
+        for account in self.accounts.write().unwrap().iter_mut() {
+            account.encrypt(passwd.clone())?;
+        }
+
         self.encrypted = true;
         self.lock()?;
 
@@ -865,6 +2371,10 @@ impl LightWallet {
     }
 
     pub fn lock(&mut self) -> io::Result<()> {
+        if self.watch_only {
+            return Ok(());
+        }
+
         if !self.encrypted {
             return Err(io::Error::new(ErrorKind::AlreadyExists, "Wallet is not encrypted"));
         }
@@ -875,6 +2385,7 @@ impl LightWallet {
 
         // Empty the seed and the secret keys
         self.seed.copy_from_slice(&[0u8; 32]);
+        self.passphrase_seed = None;
         self.tkeys = Arc::new(RwLock::new(vec![]));
 
         // Remove all the private key from the zkeys
@@ -882,12 +2393,22 @@ impl LightWallet {
             zk.lock()
         }).collect::<io::Result<Vec<_>>>()?;
 
+        // Accounts that were encrypted with the wallet are already locked
+        for account in self.accounts.write().unwrap().iter_mut().filter(|a| a.unlocked) {
+            account.lock()?;
+        }
+
         self.unlocked = false;
+        self.cancel_auto_lock();
 
         Ok(())
     }
 
     pub fn unlock(&mut self, passwd: String) -> io::Result<()> {
+        if self.watch_only {
+            return Ok(());
+        }
+
         if !self.encrypted {
             return Err(Error::new(ErrorKind::AlreadyExists, "Wallet is not encrypted"));
         }
@@ -908,9 +2429,20 @@ impl LightWallet {
         // Now that we have the seed, we'll generate the extsks and tkeys, and verify the fvks and addresses
         // respectively match
 
+        let passphrase_seed = match &self.enc_passphrase_seed {
+            Some((nonce, cipher)) => match secretbox::open(cipher, &secretbox::Nonce::from_slice(nonce).unwrap(), &key) {
+                Ok(s) => Some(s),
+                Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Decryption failed. Is your password correct?"))
+            },
+            None => None
+        };
+
         // The seed bytes is the raw entropy. To pass it to HD wallet generation,
-        // we need to get the 64 byte bip39 entropy
-        let bip39_seed = bip39::Seed::new(&Mnemonic::from_entropy(&seed, Language::English).unwrap(), "");
+        // we need to get the 64 byte bip39 entropy, which also depends on the passphrase if there is one
+        let bip39_seed = match &passphrase_seed {
+            Some(s) => s.clone(),
+            None => bip39::Seed::new(&Mnemonic::from_entropy(&seed, Language::English).unwrap(), "").as_bytes().to_vec()
+        };
 
         // Transparent keys
         let tkeys = vec![];
@@ -928,50 +2460,276 @@ impl LightWallet {
 
         // Go over the zkeys, and add the spending keys again
         self.zkeys.write().unwrap().iter_mut().map(|zk| {
-            zk.unlock(&self.config, bip39_seed.as_bytes(), &key)
+            zk.unlock(&self.config, &bip39_seed, &key)
         }).collect::<io::Result<Vec<()>>>()?;
 
+        for account in self.accounts.write().unwrap().iter_mut().filter(|a| !a.unlocked) {
+            account.unlock(passwd.clone())?;
+        }
+
         // Everything checks out, so we'll update our wallet with the decrypted values
         self.tkeys = Arc::new(RwLock::new(tkeys));
         self.seed.copy_from_slice(&seed);
+        self.passphrase_seed = passphrase_seed;
 
         self.encrypted = true;
         self.unlocked = true;
 
-        Ok(())
+        Ok(())
+    }
+
+    // Removing encryption means unlocking it and setting the self.encrypted = false,
+    // permanantly removing the encryption
+    pub fn remove_encryption(&mut self, passwd: String) -> io::Result<()> {
+        if self.watch_only {
+            return Ok(());
+        }
+
+        if !self.encrypted {
+            return Err(Error::new(ErrorKind::AlreadyExists, "Wallet is not encrypted"));
+        }
+
+        // Unlock the wallet if it's locked
+        if !self.unlocked {
+            self.unlock(passwd.clone())?;
+        }
+
+        for account in self.accounts.write().unwrap().iter_mut() {
+            account.remove_encryption(passwd.clone())?;
+        }
+
+        // Remove encryption from individual zkeys
+        self.zkeys.write().unwrap().iter_mut().map(|zk| {
+            zk.remove_encryption()
+        }).collect::<io::Result<Vec<()>>>()?;
+
+        // Permanantly remove the encryption
+        self.encrypted = false;
+        self.nonce = vec![];
+        self.enc_seed.copy_from_slice(&[0u8; 48]);
+        self.enc_passphrase_seed = None;
+
+        Ok(())
+    }
+
+    /// Change the password of an encrypted wallet, and of its accounts, from `old_passwd` to
+    /// `new_passwd`. The seed and keys are re-encrypted in memory, so the wallet is never decrypted,
+    /// and it stays locked or unlocked as it was. If `old_passwd` is wrong, nothing is changed.
+    pub fn change_password(&mut self, old_passwd: String, new_passwd: String) -> io::Result<()> {
+        // Re-encrypt everything first, so that a wrong password anywhere leaves the wallet as it was
+        let reencrypted = self.reencrypt(&old_passwd, &new_passwd)?;
+        self.apply_reencrypted(reencrypted);
+
+        Ok(())
+    }
+
+    fn reencrypt(&self, old_passwd: &str, new_passwd: &str) -> io::Result<Reencrypted> {
+        if !self.encrypted {
+            return Err(Error::new(ErrorKind::InvalidInput, "Wallet is not encrypted"));
+        }
+
+        let old_key = secretbox::Key::from_slice(&double_sha256(old_passwd.as_bytes())).unwrap();
+        let new_key = secretbox::Key::from_slice(&double_sha256(new_passwd.as_bytes())).unwrap();
+
+        let seed = match secretbox::open(&self.enc_seed, &secretbox::Nonce::from_slice(&self.nonce).unwrap(), &old_key) {
+            Ok(s) => s,
+            Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Decryption failed. Is your password correct?"))
+        };
+
+        let nonce = secretbox::gen_nonce();
+        let enc_seed = secretbox::seal(&seed, &nonce, &new_key);
+
+        let enc_passphrase_seed = match &self.enc_passphrase_seed {
+            Some((nonce, cipher)) => match secretbox::open(cipher, &secretbox::Nonce::from_slice(nonce).unwrap(), &old_key) {
+                Ok(s) => {
+                    let nonce = secretbox::gen_nonce();
+                    Some((nonce.as_ref().to_vec(), secretbox::seal(&s, &nonce, &new_key)))
+                },
+                Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Decryption failed. Is your password correct?"))
+            },
+            None => None
+        };
+
+        let zkeys = self.zkeys.read().unwrap().iter()
+            .map(|zk| zk.reencrypt(&old_key, &new_key))
+            .collect::<io::Result<Vec<WalletZKey>>>()?;
+
+        let accounts = self.accounts.read().unwrap().iter()
+            .map(|account| account.reencrypt(old_passwd, new_passwd))
+            .collect::<io::Result<Vec<Reencrypted>>>()?;
+
+        Ok(Reencrypted { enc_seed, nonce: nonce.as_ref().to_vec(), enc_passphrase_seed, zkeys, accounts })
+    }
+
+    fn apply_reencrypted(&mut self, reencrypted: Reencrypted) {
+        self.enc_seed.copy_from_slice(&reencrypted.enc_seed);
+        self.nonce = reencrypted.nonce;
+        self.enc_passphrase_seed = reencrypted.enc_passphrase_seed;
+        *self.zkeys.write().unwrap() = reencrypted.zkeys;
+
+        for (account, r) in self.accounts.write().unwrap().iter_mut().zip(reencrypted.accounts) {
+            account.apply_reencrypted(r);
+        }
+    }
+
+    pub fn is_encrypted(&self) -> bool {
+        return self.encrypted;
+    }
+
+    pub fn is_unlocked_for_spending(&self) -> bool {
+        return self.unlocked && !self.watch_only;
+    }
+
+    pub fn is_watch_only(&self) -> bool {
+        return self.watch_only;
+    }
+
+    /// The number of accounts in this wallet file. This wallet itself is account 0.
+    pub fn num_accounts(&self) -> usize {
+        self.accounts.read().unwrap().len() + 1
+    }
+
+    /// Run `f` on the wallet for `account`, or return None if there is no such account.
+    pub fn with_account<T, F: FnOnce(&LightWallet) -> T>(&self, account: usize, f: F) -> Option<T> {
+        if account == 0 {
+            return Some(f(self));
+        }
+
+        self.accounts.read().unwrap().get(account - 1).map(f)
+    }
+
+    /// The account that has the given z or t address, if any
+    pub fn account_of_address(&self, address: &str) -> Option<usize> {
+        let has_address = |w: &LightWallet| {
+            w.get_all_zaddresses().iter().any(|a| a == address) ||
+                w.taddresses.read().unwrap().iter().any(|a| a == address)
+        };
+
+        (0..self.num_accounts()).find(|i| self.with_account(*i, &has_address).unwrap_or(false))
+    }
+
+    /// Add a new account with a freshly generated seed, and return its account number. The account
+    /// starts at the wallet's current block, so it is scanned along with the rest of the wallet from here on.
+    /// It gets its own copy of the wallet's blocks, see `accounts`.
+    pub fn add_account(&self) -> io::Result<usize> {
+        if self.watch_only {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "Can't add accounts to a watch-only wallet"));
+        }
+
+        if self.encrypted {
+            return Err(io::Error::new(ErrorKind::PermissionDenied, "Remove the wallet's encryption before adding an account"));
+        }
+
+        let account = LightWallet::new(None, &self.config, self.last_scanned_height() as u64)?;
+
+        // Start from the same commitment tree state as this wallet
+        {
+            let mut blocks = account.blocks.write().unwrap();
+            for block in self.blocks.read().unwrap().iter() {
+                let mut bytes = vec![];
+                block.write(&mut bytes)?;
+                blocks.push(BlockData::read(&bytes[..])?);
+            }
+        }
+
+        let mut accounts = self.accounts.write().unwrap();
+        accounts.push(account);
+
+        Ok(accounts.len())
     }
 
-    // Removing encryption means unlocking it and setting the self.encrypted = false,
-    // permanantly removing the encryption
-    pub fn remove_encryption(&mut self, passwd: String) -> io::Result<()> {
-        if !self.encrypted {
-            return Err(Error::new(ErrorKind::AlreadyExists, "Wallet is not encrypted"));
+    /// The txids of this wallet and all its accounts whose full tx hasn't been fetched and scanned yet
+    pub fn get_txids_to_fetch(&self) -> Vec<(TxId, i32)> {
+        let mut txids = self.txs.read().unwrap().values()
+                            .filter(|wtx| wtx.full_tx_scanned == false)
+                            .map(|wtx| (wtx.txid.clone(), wtx.block))
+                            .collect::<Vec<(TxId, i32)>>();
+
+        for account in self.accounts.read().unwrap().iter() {
+            txids.extend(account.get_txids_to_fetch());
         }
 
-        // Unlock the wallet if it's locked
-        if !self.unlocked {
-            self.unlock(passwd)?;
+        txids
+    }
+
+    /// The t addresses of this wallet and all its accounts
+    pub fn get_all_taddresses_in_accounts(&self) -> Vec<String> {
+        let mut addresses = self.taddresses.read().unwrap().clone();
+
+        for account in self.accounts.read().unwrap().iter() {
+            addresses.extend(account.get_all_taddresses_in_accounts());
         }
 
-        // Remove encryption from individual zkeys
-        self.zkeys.write().unwrap().iter_mut().map(|zk| {
-            zk.remove_encryption()
-        }).collect::<io::Result<Vec<()>>>()?;
+        addresses
+    }
 
-        // Permanantly remove the encryption
-        self.encrypted = false;
-        self.nonce = vec![];
-        self.enc_seed.copy_from_slice(&[0u8; 48]);
+    /// Lock the wallet once `timeout` has passed without a spend. Returns the timer's generation,
+    /// which has to be passed to `start_auto_lock_timer` to actually run the timer.
+    pub fn set_auto_lock(&self, timeout: Duration) -> u64 {
+        let mut auto_lock = self.auto_lock.write().unwrap();
+        auto_lock.timeout = Some(timeout);
+        auto_lock.deadline = Some(Instant::now() + timeout);
+        auto_lock.generation += 1;
 
-        Ok(())
+        auto_lock.generation
     }
 
-    pub fn is_encrypted(&self) -> bool {
-        return self.encrypted;
+    /// Stop the auto-lock timer, if it is running
+    pub fn cancel_auto_lock(&self) {
+        let mut auto_lock = self.auto_lock.write().unwrap();
+        auto_lock.timeout = None;
+        auto_lock.deadline = None;
+        auto_lock.generation += 1;
     }
 
-    pub fn is_unlocked_for_spending(&self) -> bool {
-        return self.unlocked;
+    /// Restart the auto-lock countdown. Called whenever the wallet spends.
+    pub fn reset_auto_lock(&self) {
+        let mut auto_lock = self.auto_lock.write().unwrap();
+        if let Some(timeout) = auto_lock.timeout {
+            auto_lock.deadline = Some(Instant::now() + timeout);
+        }
+    }
+
+    /// Time left until the wallet is automatically locked, if the auto-lock timer is running
+    pub fn auto_lock_remaining(&self) -> Option<Duration> {
+        self.auto_lock.read().unwrap().deadline.map(|d| d.saturating_duration_since(Instant::now()))
+    }
+
+    /// Run the auto-lock timer set by `set_auto_lock` in a background thread. The thread exits
+    /// when the wallet is locked, or when the timer is cancelled or replaced by a newer one.
+    pub fn start_auto_lock_timer(wallet: Arc<RwLock<LightWallet>>, generation: u64) {
+        std::thread::spawn(move || {
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+
+                let expired = {
+                    let w = wallet.read().unwrap();
+                    let auto_lock = w.auto_lock.read().unwrap();
+                    if auto_lock.generation != generation {
+                        return;
+                    }
+
+                    auto_lock.deadline.map_or(false, |d| Instant::now() >= d)
+                };
+
+                if expired {
+                    let mut w = wallet.write().unwrap();
+                    if w.auto_lock.read().unwrap().generation != generation {
+                        return;
+                    }
+
+                    // A spend might have restarted the countdown while we waited for the wallet
+                    if w.auto_lock_remaining() == Some(Duration::from_secs(0)) {
+                        match w.lock() {
+                            Ok(_)  => info!("Wallet was automatically locked"),
+                            Err(e) => warn!("Couldn't automatically lock wallet: {}", e),
+                        }
+                        w.cancel_auto_lock();
+                        return;
+                    }
+                }
+            }
+        });
     }
 
     pub fn zbalance(&self, addr: Option<String>) -> u64 {
@@ -1007,6 +2765,18 @@ impl LightWallet {
             .collect::<Vec<Utxo>>()
     }
 
+    /// Unspent utxos that have as many confirmations as notes need before they can be spent
+    pub fn get_verified_utxos(&self) -> Vec<Utxo> {
+        let anchor_height = match self.get_target_height_and_anchor_offset() {
+            Some((height, anchor_offset)) => height - anchor_offset as u32 - 1,
+            None => return vec![],
+        };
+
+        self.get_utxos().into_iter()
+            .filter(|utxo| utxo.unconfirmed_spent.is_none() && utxo.height as u32 <= anchor_height)
+            .collect()
+    }
+
     pub fn tbalance(&self, addr: Option<String>) -> u64 {
         self.get_utxos().iter()
             .filter(|utxo| {
@@ -1138,12 +2908,10 @@ impl LightWallet {
     }
 
     pub fn have_spending_key_for_zaddress(&self, address: &String) -> bool {
-        match self.zkeys.read().unwrap().iter()
-            .find(|zk| encode_payment_address(self.config.hrp_sapling_address(), &zk.zaddress) == *address)
-            {
-                None => false,
-                Some(zk) => zk.have_spending_key()
-            }
+        match self.zaddress_extfvk(address) {
+            None => false,
+            Some(extfvk) => self.have_spendingkey_for_extfvk(&extfvk)
+        }
     }
 
     fn add_toutput_to_wtx(&self, height: i32, timestamp: u64, txid: &TxId, vout: &TxOut, n: u64) {
@@ -1238,6 +3006,10 @@ impl LightWallet {
 
     // Scan the full Tx and update memos for incoming shielded transactions.
     pub fn scan_full_tx(&self, tx: &Transaction, height: i32, datetime: u64) {
+        for account in self.accounts.read().unwrap().iter() {
+            account.scan_full_tx(tx, height, datetime);
+        }
+
         let mut total_transparent_spend: u64 = 0;
 
         //Get Value Balance
@@ -1503,19 +3275,72 @@ impl LightWallet {
             }
         }
 
-        // Mark this Tx as scanned
+        // Mark this Tx as scanned, and keep the full tx if we didn't have it yet
         {
             let mut txs = self.txs.write().unwrap();
             match txs.get_mut(&tx.txid()) {
-                Some(wtx) => wtx.full_tx_scanned = true,
+                Some(wtx) => {
+                    wtx.full_tx_scanned = true;
+
+                    if wtx.raw_tx.is_none() {
+                        let mut raw_tx = vec![];
+                        match tx.write(&mut raw_tx) {
+                            Ok(_)  => wtx.raw_tx = Some(raw_tx),
+                            Err(e) => warn!("Couldn't serialize tx {}: {}", tx.txid(), e),
+                        }
+                    }
+                },
                 None => {},
             };
         }
     }
 
+    /// The outputs of `tx` that this wallet's keys can see, without adding anything to the wallet.
+    /// Returns (address, value, memo, outgoing) for the outputs paying our addresses, and for the
+    /// sapling outputs that one of our outgoing viewing keys recovers (outgoing is true for those).
+    pub fn decrypt_tx_outputs(&self, tx: &Transaction) -> Vec<(String, u64, Option<Memo>, bool)> {
+        let mut outputs = vec![];
+
+        let taddresses = self.taddresses.read().unwrap();
+        for vout in tx.vout.iter() {
+            match self.address_from_pubkeyhash(vout.script_pubkey.address()) {
+                Some(taddr) if taddresses.contains(&taddr) => outputs.push((taddr, vout.value.into(), None, false)),
+                _ => {}
+            }
+        }
+
+        let zkeys = self.zkeys.read().unwrap();
+        for output in tx.shielded_outputs.iter() {
+            let epk = match output.ephemeral_key.as_prime_order(&JUBJUB) {
+                Some(epk) => epk,
+                None => continue,
+            };
+
+            let incoming = zkeys.iter().find_map(|zk|
+                try_sapling_note_decryption(&zk.extfvk.fvk.vk.ivk(), &epk, &output.cmu, &output.enc_ciphertext));
+            if let Some((note, to, memo)) = incoming {
+                outputs.push((encode_payment_address(self.config.hrp_sapling_address(), &to), note.value, Some(memo), false));
+                continue;
+            }
+
+            let outgoing = zkeys.iter().find_map(|zk|
+                try_sapling_output_recovery(&zk.extfvk.fvk.ovk, &output.cv, &output.cmu, &epk,
+                                            &output.enc_ciphertext, &output.out_ciphertext));
+            if let Some((note, to, memo)) = outgoing {
+                outputs.push((encode_payment_address(self.config.hrp_sapling_address(), &to), note.value, Some(memo), true));
+            }
+        }
+
+        outputs
+    }
+
     // Invalidate all blocks including and after "at_height".
     // Returns the number of blocks invalidated
     pub fn invalidate_block(&self, at_height: i32) -> u64 {
+        for account in self.accounts.read().unwrap().iter() {
+            account.invalidate_block(at_height);
+        }
+
         let mut num_invalidated = 0;
 
         // First remove the blocks
@@ -1563,12 +3388,24 @@ impl LightWallet {
                         .for_each(|nd| {
                             if nd.spent.is_some() && txids_to_remove.contains(&nd.spent.unwrap()) {
                                 nd.spent = None;
+                                nd.spent_at_height = None;
                             }
 
-                            if nd.unconfirmed_spent.is_some() && txids_to_remove.contains(&nd.spent.unwrap()) {
+                            if nd.unconfirmed_spent.is_some() && txids_to_remove.contains(&nd.unconfirmed_spent.unwrap()) {
                                 nd.unconfirmed_spent = None;
                             }
-                        })
+                        });
+
+                    wtx.utxos.iter_mut()
+                        .for_each(|utxo| {
+                            if utxo.spent.is_some() && txids_to_remove.contains(&utxo.spent.unwrap()) {
+                                utxo.spent = None;
+                            }
+
+                            if utxo.unconfirmed_spent.is_some() && txids_to_remove.contains(&utxo.unconfirmed_spent.unwrap()) {
+                                utxo.unconfirmed_spent = None;
+                            }
+                        });
                 })
         }
 
@@ -1588,52 +3425,78 @@ impl LightWallet {
         num_invalidated as u64
     }
 
-    /// Scans a [`CompactOutput`] with a set of [`ExtendedFullViewingKey`]s.
+    /// Trial-decrypts every output in the block with every ivk, spreading the work over the pool.
     ///
-    /// Returns a [`WalletShieldedOutput`] and corresponding [`IncrementalWitness`] if this
-    /// output belongs to any of the given [`ExtendedFullViewingKey`]s.
+    /// Returns the outputs that could be decrypted, keyed by (tx index, output index) in the block.
+    /// If more than one ivk decrypts an output, the lowest account wins, so the result doesn't
+    /// depend on the order the jobs ran in.
+    fn trial_decrypt_block(
+        block: &CompactBlock,
+        ivks: &[Fs],
+        pool: &ThreadPool
+    ) -> HashMap<(usize, usize), DecryptedOutput> {
+        let (tx, rx) = channel();
+        let mut num_jobs = 0;
+
+        for (tx_index, ctx) in block.vtx.iter().enumerate() {
+            for (output_index, output) in ctx.outputs.iter().enumerate() {
+                let (cmu, epk) = match (output.cmu(), output.epk()) {
+                    (Ok(cmu), Ok(epk)) => (cmu, epk),
+                    _ => continue,
+                };
+
+                ivks.iter().enumerate().for_each(|(account, ivk)| {
+                    // Clone all values for passing to the closure
+                    let ivk = ivk.clone();
+                    let epk = epk.clone();
+                    let ct = output.ciphertext.clone();
+                    let tx = tx.clone();
+
+                    num_jobs += 1;
+                    pool.execute(move || {
+                        let m = try_sapling_compact_note_decryption(&ivk, &epk, &cmu, &ct)
+                                    .map(|(note, to)| (note, to, account));
+
+                        match tx.send(((tx_index, output_index), m)) {
+                            Ok(_) => {},
+                            Err(e) => error!("Send error {:?}", e)
+                        }
+                    });
+                });
+            }
+        }
+
+        let mut decrypted: HashMap<(usize, usize), DecryptedOutput> = HashMap::new();
+        for _i in 0..num_jobs {
+            let (key, m) = rx.recv().unwrap();
+
+            if let Some((note, to, account)) = m {
+                let keep_existing = decrypted.get(&key).map(|(_, _, a)| *a < account).unwrap_or(false);
+                if !keep_existing {
+                    decrypted.insert(key, (note, to, account));
+                }
+            }
+        }
+
+        decrypted
+    }
+
+    /// Adds a [`CompactOutput`] to the commitment tree, and returns a [`WalletShieldedOutput`]
+    /// with its new [`IncrementalWitness`] if the output was decrypted by `trial_decrypt_block`.
     ///
     /// The given [`CommitmentTree`] and existing [`IncrementalWitness`]es are incremented
     /// with this output's commitment.
     fn scan_output_internal(
         &self,
         (index, output): (usize, CompactOutput),
-        ivks: &[Fs],
+        decrypted: Option<DecryptedOutput>,
         tree: &mut CommitmentTree<Node>,
         existing_witnesses: &mut [&mut IncrementalWitness<Node>],
         block_witnesses: &mut [&mut IncrementalWitness<Node>],
         new_witnesses: &mut [&mut IncrementalWitness<Node>],
-        pool: &ThreadPool
     ) -> Option<WalletShieldedOutput> {
         let cmu = output.cmu().ok()?;
         let epk = output.epk().ok()?;
-        let ct = output.ciphertext;
-
-        let (tx, rx) = channel();
-        ivks.iter().enumerate().for_each(|(account, ivk)| {
-            // Clone all values for passing to the closure
-            let ivk = ivk.clone();
-            let epk = epk.clone();
-            let ct = ct.clone();
-            let tx = tx.clone();
-
-            pool.execute(move || {
-                let m = try_sapling_compact_note_decryption(&ivk, &epk, &cmu, &ct);
-                let r = match m {
-                    Some((note, to)) => {
-                        tx.send(Some(Some((note, to, account))))
-                    },
-                    None => {
-                        tx.send(Some(None))
-                    }
-                };
-
-                match r {
-                    Ok(_) => {},
-                    Err(e) => println!("Send error {:?}", e)
-                }
-            });
-        });
 
         // Increment tree and witnesses
         let node = Node::new(cmu.into());
@@ -1648,37 +3511,12 @@ impl LightWallet {
         }
         tree.append(node).unwrap();
 
-        // Collect all the RXs and fine if there was a valid result somewhere
-        let mut wsos =  vec![];
-        for _i in 0..ivks.len() {
-            let n = rx.recv().unwrap();
-            let epk = epk.clone();
-
-            let wso = match n {
-                None => panic!("Got a none!"),
-                Some(None) => None,
-                Some(Some((note, to, account))) => {
-                    // A note is marked as "change" if the account that received it
-                    // also spent notes in the same transaction. This will catch,
-                    // for instance:
-                    // - Change created by spending fractions of notes.
-                    // - Notes created by consolidation transactions.
-                    // - Notes sent from one account to itself.
-                    //let is_change = spent_from_accounts.contains(&account);
-
-                    Some(WalletShieldedOutput {
-                        index, cmu, epk, account, note, to, is_change: false,
-                        witness: IncrementalWitness::from_tree(tree),
-                    })
-                }
-            };
-            wsos.push(wso);
-        }
-
-        match wsos.into_iter().find(|wso| wso.is_some()) {
-            Some(Some(wso)) => Some(wso),
-            _ => None
-        }
+        // A note is marked as "change" later, if the account that received it
+        // also spent notes in the same transaction.
+        decrypted.map(|(note, to, account)| WalletShieldedOutput {
+            index, cmu, epk, account, note, to, is_change: false,
+            witness: IncrementalWitness::from_tree(tree),
+        })
     }
 
     /// Scans a [`CompactBlock`] with a set of [`ExtendedFullViewingKey`]s.
@@ -1700,7 +3538,11 @@ impl LightWallet {
         let mut wtxs: Vec<zcash_client_backend::wallet::WalletTx> = vec![];
         let ivks = extfvks.iter().map(|extfvk| extfvk.fvk.vk.ivk()).collect::<Vec<_>>();
 
-        for tx in block.vtx.into_iter() {
+        // Decrypt all the outputs in parallel first. The tree and witnesses are then updated
+        // in order on this thread, so the result is the same for any number of threads.
+        let mut decrypted = Self::trial_decrypt_block(&block, &ivks, pool);
+
+        for (tx_index, tx) in block.vtx.into_iter().enumerate() {
             let num_spends = tx.spends.len();
             let num_outputs = tx.outputs.len();
 
@@ -1761,7 +3603,7 @@ impl LightWallet {
                     .flatten()
                     .collect();
 
-                for to_scan in tx.outputs.into_iter().enumerate() {
+                for (output_index, output) in tx.outputs.into_iter().enumerate() {
                     // Grab mutable references to new witnesses from previous outputs
                     // in this transaction so that we can update them. Scoped so we
                     // don't hold mutable references to shielded_outputs for too long.
@@ -1771,13 +3613,12 @@ impl LightWallet {
                         .collect();
 
                     if let Some(output) = self.scan_output_internal(
-                        to_scan,
-                        &ivks,
+                        (output_index, output),
+                        decrypted.remove(&(tx_index, output_index)),
                         tree,
                         existing_witnesses,
                         &mut block_witnesses,
                         &mut new_witnesses,
-                        pool
                     ) {
                         shielded_outputs.push(output);
                     }
@@ -1815,8 +3656,24 @@ impl LightWallet {
         self.scan_block_with_pool(&block_bytes, &ThreadPool::new(1))
     }
 
-    // Scan a block. Will return an error with the block height that failed to scan
+    // Scan a block for this wallet and all its accounts, so the block is only downloaded once. Each
+    // account still parses and trial-decrypts it on its own, with its own keys and commitment tree.
+    // Will return an error with the block height that failed to scan
     pub fn scan_block_with_pool(&self, block_bytes: &[u8], pool: &ThreadPool) -> Result<Vec<TxId>, i32> {
+        let mut txids = self.scan_account_block(block_bytes, pool)?;
+
+        for account in self.accounts.read().unwrap().iter() {
+            for txid in account.scan_block_with_pool(block_bytes, pool)? {
+                if !txids.contains(&txid) {
+                    txids.push(txid);
+                }
+            }
+        }
+
+        Ok(txids)
+    }
+
+    fn scan_account_block(&self, block_bytes: &[u8], pool: &ThreadPool) -> Result<Vec<TxId>, i32> {
         let block: CompactBlock = match parse_from_bytes(block_bytes) {
             Ok(block) => block,
             Err(e) => {
@@ -2081,18 +3938,73 @@ impl LightWallet {
         });
     }
 
-    pub fn send_to_address<F> (
+    /// Build and sign a transaction, without broadcasting it or updating the wallet's
+    /// state. The selected notes and utxos are returned along with the transaction.
+    /// Build every transaction from `seed` instead of the OS RNG, so the same send from the same
+    /// wallet state gives the same bytes. The Groth16 proofs draw their own randomness, so while
+    /// a seed is set the proofs are replaced with placeholders and the transactions won't verify.
+    /// Only for tests.
+    #[cfg(feature = "testing")]
+    pub fn set_tx_rng_seed(&self, seed: Option<[u8; 32]>) {
+        *self.tx_rng_seed.write().unwrap() = seed;
+        for account in self.accounts.read().unwrap().iter() {
+            account.set_tx_rng_seed(seed);
+        }
+    }
+
+    #[cfg(feature = "testing")]
+    fn tx_rng(&self) -> TxRng {
+        match *self.tx_rng_seed.read().unwrap() {
+            Some(seed) => TxRng::Seeded(StdRng::from_seed(seed)),
+            None       => TxRng::Os(OsRng),
+        }
+    }
+
+    #[cfg(not(feature = "testing"))]
+    fn tx_rng(&self) -> TxRng {
+        TxRng::Os(OsRng)
+    }
+
+    /// The addresses a send spends from. `from` is a single address, or a comma separated list of
+    /// addresses whose notes and utxos are pooled for the send. The first one is the source address:
+    /// it is where the change goes with `change_to_source`, or when no notes are spent. Repeated
+    /// addresses are only counted once.
+    pub fn split_inputs(from: &str) -> Vec<&str> {
+        let mut inputs = vec![];
+        for address in from.split(',').map(|a| a.trim()).filter(|a| !a.is_empty()) {
+            if !inputs.contains(&address) {
+                inputs.push(address);
+            }
+        }
+
+        inputs
+    }
+
+    /// Build and sign a transaction sending `tos` from `from`, without broadcasting it. If
+    /// `change_to_source` is set, the change of a send from a z-address goes back to that address.
+    /// Otherwise it goes to the address of the first note spent, which can be another diversified
+    /// address of the same key. Notes worth less than `min_note_value` (dust) are only picked when the
+    /// other notes don't cover the send. Notes given in `from_notes` are always spent, dust or not.
+    /// A strategy or dust threshold that isn't set is the wallet's default (largest first, no dust).
+    pub fn build_transaction(
         &self,
         consensus_branch_id: u32,
         spend_params: &[u8],
         output_params: &[u8],
         from: &str,
-        tos: Vec<(&str, u64, Option<String>)>,
+        tos: &Vec<(&str, u64, Option<String>)>,
         fee: &u64,
-        broadcast_fn: F
-    ) -> Result<(String, Vec<u8>), String>
-        where F: Fn(Box<[u8]>) -> Result<String, String>
+        options: &SendOptions,
+    ) -> Result<BuiltTransaction, String>
     {
+        let strategy = options.strategy.unwrap_or_default();
+        let from_notes = options.from_notes.as_deref();
+        let min_note_value = options.min_note_value.unwrap_or(0);
+
+        if self.watch_only {
+            return Err(WATCH_ONLY_ERROR.to_string());
+        }
+
         if !self.unlocked {
             return Err("Cannot spend while wallet is locked".to_string());
         }
@@ -2102,8 +4014,24 @@ impl LightWallet {
             return Err("Need at least one destination address".to_string());
         }
 
+        // A send can pool the funds of several addresses, see `split_inputs`. They all have to be in
+        // this wallet, and the first one stands in for them where a single address is needed.
+        let inputs = LightWallet::split_inputs(from);
+        if inputs.is_empty() {
+            return Err("Need an address to send from".to_string());
+        }
+        if inputs.len() > 1 {
+            let taddresses = self.taddresses.read().unwrap();
+            if let Some(a) = inputs.iter().find(|a| self.zaddress_extfvk(a).is_none() && !taddresses.contains(&a.to_string())) {
+                let e = format!("Can't spend from {}, it is not an address of this wallet", a);
+                error!("{}", e);
+                return Err(e);
+            }
+        }
+        let from = inputs[0];
+
         let total_value = tos.iter().map(|to| to.1).sum::<u64>();
-        println!(
+        info!(
             "0: Creating transaction sending {} zatoshis to {} addresses",
             total_value, tos.len()
         );
@@ -2138,14 +4066,42 @@ impl LightWallet {
         };
 
         // Select notes to cover the target value
-        println!("{}: Selecting notes", now() - start_time);
+        info!("{}: Selecting notes", now() - start_time);
         let target_value = Amount::from_u64(total_value).unwrap() + Amount::from_u64(*fee).unwrap();
 
+        // If the notes to spend were given explicitly, make sure they all exist and are unspent
+        if let Some(outpoints) = from_notes {
+            let txs = self.txs.read().unwrap();
+            for outpoint in outpoints {
+                let nd = txs.get(&outpoint.txid)
+                    .and_then(|wtx| wtx.notes.iter().find(|nd| nd.output_index == Some(outpoint.output_index)));
+
+                match nd {
+                    None => {
+                        let e = format!("Unknown note {}", outpoint);
+                        error!("{}", e);
+                        return Err(e);
+                    },
+                    Some(nd) if nd.spent.is_some() || nd.unconfirmed_spent.is_some() => {
+                        let e = format!("Note {} is already spent", outpoint);
+                        error!("{}", e);
+                        return Err(e);
+                    },
+                    Some(_) => {}
+                }
+            }
+        }
+
+        // Notes received at any diversified address of the from addresses' keys can be spent
+        let from_extfvks = inputs.iter()
+            .filter_map(|a| self.zaddress_extfvk(a))
+            .collect::<Vec<ExtendedFullViewingKey>>();
+
         // Select the candidate notes that are eligible to be spent
         let mut candidate_notes: Vec<_> = self.txs.read().unwrap().iter()
-            .map(|(txid, tx)| tx.notes.iter().map(move |note| (*txid, note)))
+            .map(|(txid, tx)| tx.notes.iter().map(move |note| (*txid, tx.block, note)))
             .flatten()
-            .filter_map(|(txid, note)| {
+            .filter_map(|(txid, block, note)| {
                 // Filter out notes that are already spent
                 if note.spent.is_some() || note.unconfirmed_spent.is_some() {
                     None
@@ -2154,33 +4110,76 @@ impl LightWallet {
                     let extsk = self.zkeys.read().unwrap().iter()
                         .find(|zk| zk.extfvk == note.extfvk)
                         .and_then(|zk| zk.extsk.clone());
-                        //filter only on Notes with a matching from address
-                    if from == LightWallet::note_address(self.config.hrp_sapling_address(), note).unwrap() {
-                        SpendableNote::from(txid, note, anchor_offset, &extsk)
+                    // Filter only on notes of the from addresses' keys
+                    if from_extfvks.contains(&note.extfvk) {
+                        SpendableNote::from(txid, note, anchor_offset, &extsk).map(|sn| (block, note.output_index, sn))
                     }   else {
                         None
                     }
                 }
             }).collect();
 
-        // Sort by highest value-notes first.
-        candidate_notes.sort_by(|a, b| b.note.value.cmp(&a.note.value));
+        let notes: Vec<_> = match from_notes {
+            // Spend exactly the notes that were asked for
+            Some(outpoints) => {
+                outpoints.iter().map(|outpoint| {
+                    let pos = candidate_notes.iter()
+                        .position(|(_, output_index, sn)| sn.txid == outpoint.txid && *output_index == Some(outpoint.output_index));
+
+                    match pos {
+                        Some(pos) => Ok(candidate_notes.remove(pos).2),
+                        None => {
+                            let e = format!("Note {} can't be spent from {}. NOTE: notes need {} confirmations before they can be spent.",
+                                            outpoint, from, self.config.anchor_offset + 1);
+                            error!("{}", e);
+                            Err(e)
+                        }
+                    }
+                }).collect::<Result<Vec<_>, String>>()?
+            },
+            None => {
+                // Order the notes according to the selection strategy
+                match strategy {
+                    NoteSelectionStrategy::LargestFirst => {
+                        candidate_notes.sort_by(|(_, _, a), (_, _, b)| b.note.value.cmp(&a.note.value));
+                    },
+                    NoteSelectionStrategy::OldestFirst => {
+                        candidate_notes.sort_by_key(|(block, _, _)| *block);
+                    },
+                    NoteSelectionStrategy::FewestInputs => {
+                        candidate_notes.sort_by(|(_, _, a), (_, _, b)| b.note.value.cmp(&a.note.value));
 
-        // Select the minimum number of notes required to satisfy the target value
-        let notes: Vec<_> = candidate_notes.iter()
-            .scan(0, |running_total, spendable| {
-                let value = spendable.note.value;
-                let ret = if *running_total < u64::from(target_value) {
-                    Some(spendable)
-                } else {
-                    None
+                        // If a single note can cover the target value, spend the smallest such note
+                        if let Some(pos) = candidate_notes.iter().rposition(|(_, _, sn)| sn.note.value >= u64::from(target_value)) {
+                            let single = candidate_notes.remove(pos);
+                            candidate_notes.insert(0, single);
+                        }
+                    },
                 };
-                *running_total = *running_total + value;
-                ret
-            })
-            .collect();
 
-        let mut builder = Builder::new(height);
+                // Dust goes last, keeping the strategy's order otherwise
+                if min_note_value > 0 {
+                    candidate_notes.sort_by_key(|(_, _, sn)| sn.note.value < min_note_value);
+                }
+
+                // Select the notes, in order, until they satisfy the target value
+                candidate_notes.into_iter()
+                    .map(|(_, _, spendable)| spendable)
+                    .scan(0, |running_total, spendable| {
+                        let value = spendable.note.value;
+                        let ret = if *running_total < u64::from(target_value) {
+                            Some(spendable)
+                        } else {
+                            None
+                        };
+                        *running_total = *running_total + value;
+                        ret
+                    })
+                    .collect()
+            }
+        };
+
+        let mut builder = Builder::new_with_rng(height, self.tx_rng());
 
         //set fre
         builder.set_fee(Amount::from_u64(*fee).unwrap());
@@ -2191,10 +4190,15 @@ impl LightWallet {
         // Specifically, if you send an outgoing transaction that is sent to a shielded address,
         // ZecWallet will add all your t-address funds into that transaction, and send them to your shielded
         // address as change.
-        let tinputs: Vec<_> = self.get_utxos().iter()
-                                .filter(|utxo| utxo.address == from)
-                                .filter(|utxo| utxo.unconfirmed_spent.is_none()) // Remove any unconfirmed spends
-                                .map(|utxo| utxo.clone())
+        let utxos = if options.verified_utxos_only {
+            self.get_verified_utxos()
+        } else {
+            self.get_utxos().into_iter()
+                .filter(|utxo| utxo.unconfirmed_spent.is_none()) // Remove any unconfirmed spends
+                .collect()
+        };
+        let tinputs: Vec<_> = utxos.into_iter()
+                                .filter(|utxo| inputs.contains(&utxo.address.as_str()))
                                 .collect();
 
         // Create a map from address -> sk for all taddrs, so we can spend from the
@@ -2243,7 +4247,7 @@ impl LightWallet {
         }
 
         // Create the transaction
-        println!("{}: Adding {} notes and {} utxos", now() - start_time, notes.len(), tinputs.len());
+        info!("{}: Adding {} notes and {} utxos", now() - start_time, notes.len(), tinputs.len());
 
         for selected in notes.iter() {
             if let Err(e) = builder.add_sapling_spend(
@@ -2259,6 +4263,19 @@ impl LightWallet {
         }
 
 
+        // The builder sends the change to the address of the first note spent. If there are no
+        // notes, or the change has to go back to the source, it is sent to the `from` address below.
+        let change_value = selected_value - u64::from(target_value);
+        let change_to_source = options.change_to_source && !notes.is_empty();
+        let change = if change_value == 0 {
+            None
+        } else if notes.is_empty() || change_to_source {
+            Some((from.to_string(), change_value))
+        } else {
+            PaymentAddress::from_parts(notes[0].diversifier, notes[0].note.pk_d.clone())
+                .map(|pa| (encode_payment_address(self.config.hrp_sapling_address(), &pa), change_value))
+        };
+
         // Use the ovk belonging to the address being sent from, if not using any notes
         // use the first address in the wallet for the ovk.
         let ovk = if notes.len() == 0 {
@@ -2272,7 +4289,7 @@ impl LightWallet {
         // the builder will automatically send change back to the sapling address if notes are used.
         if notes.len() == 0 && selected_value - u64::from(target_value) > 0 {
 
-            println!("{}: Adding change output", now() - start_time);
+            info!("{}: Adding change output", now() - start_time);
 
             let from_addr = address::RecipientAddress::from_str(from,
                             self.config.hrp_sapling_address(),
@@ -2293,6 +4310,27 @@ impl LightWallet {
             }
         }
 
+        // An explicit output with the change leaves nothing for the builder to send to the first
+        // note's address. Notes are only spent from z-addresses, so `from` is one
+        if change_to_source && change_value > 0 {
+            info!("{}: Adding change output to {}", now() - start_time, from);
+
+            let from_addr = match decode_payment_address(self.config.hrp_sapling_address(), from) {
+                Ok(Some(pa)) => pa,
+                _ => {
+                    let e = format!("Couldn't send the change back to {}, it is not a z-address", from);
+                    error!("{}", e);
+                    return Err(e);
+                }
+            };
+
+            if let Err(e) = builder.add_sapling_output(ovk, from_addr, Amount::from_u64(change_value).unwrap(), None) {
+                let e = format!("Error adding change output: {:?}", e);
+                error!("{}", e);
+                return Err(e);
+            }
+        }
+
 
 
 
@@ -2313,7 +4351,7 @@ impl LightWallet {
                 }
             };
 
-            println!("{}: Adding outputs", now() - start_time);
+            info!("{}: Adding outputs", now() - start_time);
 
             if let Err(e) = match to {
                 address::RecipientAddress::Shielded(to) => {
@@ -2330,11 +4368,16 @@ impl LightWallet {
         }
 
 
-        println!("{}: Building transaction", now() - start_time);
-        let (tx, _) = match builder.build(
-            consensus_branch_id,
-            &prover::InMemTxProver::new(spend_params, output_params),
-        ) {
+        info!("{}: Building transaction", now() - start_time);
+        #[cfg(feature = "testing")]
+        let built = match *self.tx_rng_seed.read().unwrap() {
+            Some(_) => builder.build(consensus_branch_id, &prover::TestTxProver),
+            None    => builder.build(consensus_branch_id, &prover::InMemTxProver::new(spend_params, output_params)),
+        };
+        #[cfg(not(feature = "testing"))]
+        let built = builder.build(consensus_branch_id, &prover::InMemTxProver::new(spend_params, output_params));
+
+        let (tx, _) = match built {
             Ok(res) => res,
             Err(e) => {
                 let e = format!("Error creating transaction: {:?}", e);
@@ -2342,14 +4385,94 @@ impl LightWallet {
                 return Err(e);
             }
         };
-        println!("{}: Transaction created", now() - start_time);
-        println!("Transaction ID: {}", tx.txid());
+        info!("{}: Transaction created", now() - start_time);
+        info!("Transaction ID: {}", tx.txid());
 
         // Create the TX bytes
         let mut raw_tx = vec![];
         tx.write(&mut raw_tx).unwrap();
 
+        let outputs = self.built_outputs(&tx, &ovk, tos, &change);
+
+        Ok(BuiltTransaction {
+            tx,
+            raw_tx,
+            notes,
+            tinputs,
+            height,
+            total_value,
+            fee: *fee,
+            change,
+            outputs,
+        })
+    }
+
+    // The outputs of a transaction built by `build_transaction`, read back from the transaction in
+    // the order they are in it. Sapling outputs are decrypted with `ovk`, the outgoing viewing key
+    // they were built with, so padding outputs that don't decrypt are left out. The change is the
+    // output left over for `change` once the outputs paying `tos` are accounted for, so a recipient
+    // paid the same amount at the change address isn't mistaken for it.
+    fn built_outputs(&self, tx: &Transaction, ovk: &OutgoingViewingKey, tos: &[(&str, u64, Option<String>)],
+                     change: &Option<(String, u64)>) -> Vec<BuiltOutput> {
+        let mut outputs = tx.shielded_outputs.iter().filter_map(|output| {
+            try_sapling_output_recovery(
+                ovk,
+                &output.cv,
+                &output.cmu,
+                &output.ephemeral_key.as_prime_order(&JUBJUB).unwrap(),
+                &output.enc_ciphertext,
+                &output.out_ciphertext
+            ).map(|(note, payment_address, memo)| BuiltOutput {
+                address: encode_payment_address(self.config.hrp_sapling_address(), &payment_address),
+                value: note.value,
+                memo_included: memo.as_bytes() != Memo::default().as_bytes(),
+                is_change: false,
+            })
+        }).collect::<Vec<_>>();
+
+        outputs.extend(tx.vout.iter().map(|vout| BuiltOutput {
+            address: self.address_from_pubkeyhash(vout.script_pubkey.address()).unwrap_or_default(),
+            value: i64::from(vout.value) as u64,
+            memo_included: false,
+            is_change: false,
+        }));
+
+        let mut paid = vec![false; outputs.len()];
+        for (to, value, _) in tos {
+            if let Some(i) = (0..outputs.len()).find(|i| !paid[*i] && outputs[*i].address == *to && outputs[*i].value == *value) {
+                paid[i] = true;
+            }
+        }
+        if let Some((address, value)) = change {
+            if let Some(i) = (0..outputs.len()).find(|i| !paid[*i] && outputs[*i].address == *address && outputs[*i].value == *value) {
+                outputs[i].is_change = true;
+            }
+        }
+
+        outputs
+    }
+
+    /// Build the transaction as `build_transaction` does, broadcast it with `broadcast_fn`, and mark
+    /// the notes and utxos it spent as spent. Returns the txid, the raw transaction, the change and
+    /// the outputs of the transaction.
+    pub fn send_to_address<F> (
+        &self,
+        consensus_branch_id: u32,
+        spend_params: &[u8],
+        output_params: &[u8],
+        from: &str,
+        tos: Vec<(&str, u64, Option<String>)>,
+        fee: &u64,
+        options: &SendOptions,
+        broadcast_fn: F
+    ) -> Result<(String, Vec<u8>, Option<(String, u64)>, Vec<BuiltOutput>), String>
+        where F: Fn(Box<[u8]>) -> Result<String, String>
+    {
+        let BuiltTransaction { tx, raw_tx, notes, tinputs, height, total_value, change, outputs, .. } =
+            self.build_transaction(consensus_branch_id, spend_params, output_params, from, &tos, fee, options)?;
+
         let txid = broadcast_fn(raw_tx.clone().into_boxed_slice())?;
+        self.reset_auto_lock();
 
         // Mark notes as spent.
         {
@@ -2403,10 +4526,23 @@ impl LightWallet {
                         }
                     }).collect::<Vec<_>>();
 
+                    // Remember the memo we attached to each output, so they can be shown for this tx later
+                    let memos = tos.iter()
+                        .filter(|(addr, _, _)| LightWallet::is_shielded_address(&addr.to_string(), &self.config))
+                        .filter_map(|(addr, amt, maybe_memo)| match maybe_memo {
+                            Some(m) if !m.is_empty() => Some((addr.to_string(), *amt, m.clone())),
+                            _ => None,
+                        })
+                        .collect::<Vec<_>>();
+                    if !memos.is_empty() {
+                        self.sent_memos.write().unwrap().insert(tx.txid(), memos);
+                    }
+
                     // Create a new WalletTx
                     let mut wtx = WalletTx::new(height as i32, now() as u64, &tx.txid());
                     wtx.outgoing_metadata = outgoing_metadata;
                     wtx.total_shielded_value_spent = total_value + fee;
+                    wtx.raw_tx = Some(raw_tx.clone());
 
                     // Add it into the mempool
                     mempool_txs.insert(tx.txid(), wtx);
@@ -2417,7 +4553,52 @@ impl LightWallet {
             }
         }
 
-        Ok((txid, raw_tx))
+        Ok((txid, raw_tx, change, outputs))
+    }
+
+    /// Forget the sends that are still unconfirmed after `min_age_secs` seconds, and release the
+    /// notes and utxos they spent so they can be spent again. Returns the forgotten txids.
+    /// Mined transactions are never touched.
+    pub fn forget_unconfirmed(&self, min_age_secs: u64) -> Vec<TxId> {
+        let mut forgotten = self.accounts.read().unwrap().iter()
+            .flat_map(|account| account.forget_unconfirmed(min_age_secs))
+            .collect::<Vec<TxId>>();
+
+        let cutoff = (now() as u64).saturating_sub(min_age_secs);
+
+        let txids = {
+            // The memos we sent in a forgotten tx go with it, unless the tx was mined after all
+            let mut mempool_txs = self.mempool_txs.write().unwrap();
+            let txs = self.txs.read().unwrap();
+            let mut sent_memos = self.sent_memos.write().unwrap();
+            let txids = mempool_txs.values()
+                .filter(|wtx| wtx.datetime <= cutoff)
+                .map(|wtx| wtx.txid.clone())
+                .collect::<HashSet<TxId>>();
+            mempool_txs.retain(|txid, _| !txids.contains(txid));
+            for txid in txids.iter().filter(|txid| !txs.contains_key(txid)) {
+                sent_memos.remove(txid);
+            }
+
+            txids
+        };
+
+        for wtx in self.txs.write().unwrap().values_mut() {
+            for nd in wtx.notes.iter_mut() {
+                if nd.unconfirmed_spent.map_or(false, |txid| txids.contains(&txid)) {
+                    nd.unconfirmed_spent = None;
+                }
+            }
+
+            for utxo in wtx.utxos.iter_mut() {
+                if utxo.unconfirmed_spent.map_or(false, |txid| txids.contains(&txid)) {
+                    utxo.unconfirmed_spent = None;
+                }
+            }
+        }
+
+        forgotten.extend(txids);
+        forgotten
     }
 
     // After some blocks have been mined, we need to remove the Txns from the mempool_tx structure
@@ -2427,19 +4608,41 @@ impl LightWallet {
     pub fn cleanup_mempool(&self) {
         const DEFAULT_TX_EXPIRY_DELTA: i32 = 20;
 
+        for account in self.accounts.read().unwrap().iter() {
+            account.cleanup_mempool();
+        }
+
         let current_height = self.blocks.read().unwrap().last().map(|b| b.height).unwrap_or(0);
 
         {
-            // Remove all expired Txns
-            self.mempool_txs.write().unwrap().retain( | _, wtx| {
-                current_height < (wtx.block + DEFAULT_TX_EXPIRY_DELTA)
+            // Remove all expired Txns, along with the memos we sent in them if they were never mined
+            let mut mempool_txs = self.mempool_txs.write().unwrap();
+            let txs = self.txs.read().unwrap();
+            let mut sent_memos = self.sent_memos.write().unwrap();
+            mempool_txs.retain( | txid, wtx| {
+                let expired = current_height >= (wtx.block + DEFAULT_TX_EXPIRY_DELTA);
+                if expired && !txs.contains_key(txid) {
+                    sent_memos.remove(txid);
+                }
+                !expired
             });
         }
 
         {
-            // Remove all txns where the txid is added to the wallet directly
-            self.mempool_txs.write().unwrap().retain ( |txid, _| {
-                self.txs.read().unwrap().get(txid).is_none()
+            // Remove all txns where the txid is added to the wallet directly. The mined tx keeps the
+            // raw tx we built, so it doesn't have to wait for the full tx to be downloaded
+            let mut mempool_txs = self.mempool_txs.write().unwrap();
+            let mut txs = self.txs.write().unwrap();
+            mempool_txs.retain ( |txid, mempool_wtx| {
+                match txs.get_mut(txid) {
+                    Some(wtx) => {
+                        if wtx.raw_tx.is_none() {
+                            wtx.raw_tx = mempool_wtx.raw_tx.take();
+                        }
+                        false
+                    },
+                    None => true
+                }
             });
         }
     }
@@ -10,9 +10,10 @@
 /// 2. Move any funds in these addresses to the first address
 /// 3. Re-derive the addresses
 
-use super::LightWallet;
+use super::{LightWallet, SendOptions};
 use crate::lightclient::LightClient;
 
+use log::info;
 use json::object;
 use bip39::{Mnemonic, Language};
 
@@ -80,10 +81,11 @@ impl BugBip39Derivation {
                          + balance_json["tbalance"].as_u64().unwrap();
 
         let txid = if amount > 0 {
-            println!("Sending funds to ourself.");
+            info!("Sending funds to ourself.");
             let fee: u64 = DEFAULT_FEE.try_into().unwrap();
-            match client.do_send(client.do_address()["z_addresses"][0].as_str().unwrap(), vec![(&zaddr, amount-fee, None)], &fee) {
-                Ok(txid) => txid,
+            match client.do_send(client.do_address()["z_addresses"][0].as_str().unwrap(), vec![(&zaddr, amount-fee, None)], &fee,
+                                 SendOptions { confirm_large: true, ..SendOptions::default() }) {
+                Ok(sent) => sent["txid"].to_string(),
                 Err(e) => {
                     let r = object!{
                         "has_bug" => true,
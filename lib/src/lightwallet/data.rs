@@ -1,5 +1,6 @@
 use std::io::{self, Read, Write};
 
+use log::warn;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use pairing::bls12_381::{Bls12};
 use ff::{PrimeField};
@@ -41,7 +42,7 @@ impl BlockData {
 
         let endtag = reader.read_u64::<LittleEndian>()?;
         if endtag != 11 {
-            println!("End tag for blockdata {}", endtag);
+            warn!("End tag for blockdata {}", endtag);
         }
 
 
@@ -72,6 +73,7 @@ pub struct SaplingNoteData {
     pub unconfirmed_spent: Option<TxId>, // If this note was spent in a send, but has not yet been confirmed.
     pub memo:  Option<Memo>,
     pub is_change: bool,
+    pub output_index: Option<u64>,       // Index of this note in the tx's shielded outputs. Not known for notes from older wallets until they are rescanned
     // TODO: We need to remove the unconfirmed_spent (i.e., set it to None) if the Tx has expired
 }
 
@@ -107,7 +109,7 @@ pub fn read_note<R: Read>(mut reader: R) -> io::Result<(u64, Fs)> {
 
 impl SaplingNoteData {
     fn serialized_version() -> u64 {
-        2
+        3
     }
 
     pub fn new(
@@ -137,6 +139,7 @@ impl SaplingNoteData {
             unconfirmed_spent: None,
             memo: None,
             is_change: output.is_change,
+            output_index: Some(output.index as u64),
         }
     }
 
@@ -194,6 +197,12 @@ impl SaplingNoteData {
 
         let is_change: bool = reader.read_u8()? > 0;
 
+        let output_index = if version >= 3 {
+            Optional::read(&mut reader, |r| r.read_u64::<LittleEndian>())?
+        } else {
+            None
+        };
+
         Ok(SaplingNoteData {
             account,
             extfvk,
@@ -206,6 +215,7 @@ impl SaplingNoteData {
             unconfirmed_spent: None,
             memo,
             is_change,
+            output_index,
         })
     }
 
@@ -236,6 +246,8 @@ impl SaplingNoteData {
 
         writer.write_u8(if self.is_change {1} else {0})?;
 
+        Optional::write(&mut writer, &self.output_index, |w, i| w.write_u64::<LittleEndian>(*i))?;
+
         // Note that we don't write the unconfirmed_spent field, because if the wallet is restarted,
         // we don't want to be beholden to any expired txns
 
@@ -273,7 +285,7 @@ impl Utxo {
         let address_len = reader.read_i32::<LittleEndian>()?;
         let mut address_bytes = vec![0; address_len as usize];
         reader.read_exact(&mut address_bytes)?;
-        let address = String::from_utf8(address_bytes).unwrap();
+        let address = String::from_utf8(address_bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
         assert_eq!(address.chars().take(1).collect::<Vec<char>>()[0], 't');
 
         let mut txid_bytes = [0; 32];
@@ -343,7 +355,7 @@ impl OutgoingTxMetadata {
         let address_len = reader.read_u64::<LittleEndian>()?;
         let mut address_bytes = vec![0; address_len as usize];
         reader.read_exact(&mut address_bytes)?;
-        let address = String::from_utf8(address_bytes).unwrap();
+        let address = String::from_utf8(address_bytes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
 
         let value = reader.read_u64::<LittleEndian>()?;
 
@@ -405,11 +417,15 @@ pub struct WalletTx {
 
     // Value Balance of this Tx.
     pub value_balance : u64,
+
+    // The serialized transaction, once the wallet has it: for its own sends when they are built,
+    // otherwise when the full tx is downloaded and scanned. Added in v6
+    pub raw_tx: Option<Vec<u8>>,
 }
 
 impl WalletTx {
     pub fn serialized_version() -> u64 {
-        return 5;
+        return 6;
     }
 
     pub fn new(height: i32, datetime: u64, txid: &TxId) -> Self {
@@ -425,6 +441,7 @@ impl WalletTx {
             outgoing_metadata_change: vec![],
             full_tx_scanned: false,
             value_balance: 0,
+            raw_tx: None,
         }
     }
 
@@ -468,6 +485,12 @@ impl WalletTx {
             0
         };
 
+        let raw_tx = if version >= 6 {
+            Optional::read(&mut reader, |r| Vector::read(r, |r| r.read_u8()))?
+        } else {
+            None
+        };
+
         Ok(WalletTx{
             block,
             datetime,
@@ -479,7 +502,8 @@ impl WalletTx {
             outgoing_metadata,
             outgoing_metadata_change,
             full_tx_scanned,
-            value_balance
+            value_balance,
+            raw_tx,
         })
     }
 
@@ -508,10 +532,25 @@ impl WalletTx {
 
         writer.write_u64::<LittleEndian>(self.value_balance)?;
 
+        Optional::write(&mut writer, &self.raw_tx, |w, raw| Vector::write(w, raw, |w, b| w.write_u8(*b)))?;
+
         Ok(())
     }
 }
 
+/// A note picked for a spend that is signed and proven outside the wallet, like on a hardware
+/// wallet. It has what the signer needs besides the spending key: the note, and its witness at the
+/// spend's anchor.
+pub struct ExternalSpendNote {
+    pub txid: TxId,
+    pub output_index: u64,
+    pub diversifier: Diversifier,
+    pub note: Note<Bls12>,
+    pub nullifier: [u8; 32],
+    pub witness: IncrementalWitness<Node>,
+    pub extfvk: ExtendedFullViewingKey,
+}
+
 pub struct SpendableNote {
     pub txid: TxId,
     pub nullifier: [u8; 32],
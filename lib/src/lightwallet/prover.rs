@@ -15,6 +15,11 @@ use zcash_primitives::{
 };
 use zcash_proofs::sapling::SaplingProvingContext;
 
+#[cfg(feature = "testing")]
+use ff::Field;
+#[cfg(feature = "testing")]
+use zcash_primitives::{jubjub::FixedGenerators, primitives::ValueCommitment};
+
 /// An implementation of [`TxProver`] using Sapling Spend and Output parameters provided
 /// in-memory.
 pub struct InMemTxProver {
@@ -122,3 +127,64 @@ impl TxProver for InMemTxProver {
         ctx.binding_sig(value_balance, sighash, &JUBJUB)
     }
 }
+
+/// A [`TxProver`] for tests that need reproducible transactions. Groth16 proofs are randomized
+/// with the OS RNG, so this prover writes all-zero proofs, commits to the values with zero
+/// randomness and returns an all-zero binding signature. The transactions it makes parse, but
+/// don't verify.
+#[cfg(feature = "testing")]
+pub struct TestTxProver;
+
+#[cfg(feature = "testing")]
+impl TxProver for TestTxProver {
+    type SaplingProvingContext = ();
+
+    fn new_sapling_proving_context(&self) -> Self::SaplingProvingContext {}
+
+    fn spend_proof(
+        &self,
+        _ctx: &mut Self::SaplingProvingContext,
+        proof_generation_key: ProofGenerationKey<Bls12>,
+        _diversifier: Diversifier,
+        _rcm: Fs,
+        ar: Fs,
+        value: u64,
+        _anchor: Fr,
+        _witness: MerklePath<Node>,
+    ) -> Result<
+        (
+            [u8; GROTH_PROOF_SIZE],
+            edwards::Point<Bls12, Unknown>,
+            PublicKey<Bls12>,
+        ),
+        (),
+    > {
+        let cv = ValueCommitment::<Bls12> { value, randomness: Fs::zero() }.cm(&JUBJUB).into();
+        let rk = PublicKey::<Bls12>(proof_generation_key.ak.clone().into())
+            .randomize(ar, FixedGenerators::SpendingKeyGenerator, &JUBJUB);
+
+        Ok(([0u8; GROTH_PROOF_SIZE], cv, rk))
+    }
+
+    fn output_proof(
+        &self,
+        _ctx: &mut Self::SaplingProvingContext,
+        _esk: Fs,
+        _payment_address: PaymentAddress<Bls12>,
+        _rcm: Fs,
+        value: u64,
+    ) -> ([u8; GROTH_PROOF_SIZE], edwards::Point<Bls12, Unknown>) {
+        let cv = ValueCommitment::<Bls12> { value, randomness: Fs::zero() }.cm(&JUBJUB).into();
+
+        ([0u8; GROTH_PROOF_SIZE], cv)
+    }
+
+    fn binding_sig(
+        &self,
+        _ctx: &mut Self::SaplingProvingContext,
+        _value_balance: Amount,
+        _sighash: &[u8; 32],
+    ) -> Result<Signature, ()> {
+        Signature::read(&[0u8; 64][..]).map_err(|_| ())
+    }
+}
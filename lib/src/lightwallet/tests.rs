@@ -653,26 +653,16 @@ fn test_multi_serialization() {
 
 fn get_test_config() -> LightClientConfig {
     LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "test".to_string(),
-        sapling_activation_height: 0,
-        consensus_branch_id: "000000".to_string(),
         anchor_offset: 0,
-        data_dir: None,
-        address_params: AddressParameters::new()
+        ..LightClientConfig::create_unconnected("test".to_string(), None)
     }
 }
 
 
 fn get_main_config() -> LightClientConfig {
     LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "main".to_string(),
-        sapling_activation_height: 0,
-        consensus_branch_id: "000000".to_string(),
         anchor_offset: 0,
-        data_dir: None,
-        address_params: AddressParameters::new()
+        ..LightClientConfig::create_unconnected("main".to_string(), None)
     }
 }
 
@@ -713,13 +703,8 @@ fn get_test_wallet(amount: u64) -> (LightWallet, TxId, BlockHash) {
 #[test]
 fn test_unconfirmed_txns() {
     let config = LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "test".to_string(),
-        sapling_activation_height: 0,
-        consensus_branch_id: "000000".to_string(),
-        anchor_offset: 5, // offset = 5
-        data_dir: None,
-        address_params: AddressParameters::new()
+        anchor_offset: 5,
+        ..LightClientConfig::create_unconnected("test".to_string(), None)
     };
 
     let branch_id = u32::from_str_radix("2bb40e60", 16).unwrap();
@@ -747,7 +732,7 @@ fn test_unconfirmed_txns() {
     let zaddr1 = encode_payment_address(wallet.config.hrp_sapling_address(), &wallet.zkeys.read().unwrap().get(0).unwrap().zaddress);
     let zaddr2 = wallet.add_zaddr();
     const AMOUNT_SENT: u64 = 50;
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
         vec![(&zaddr2, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -823,7 +808,7 @@ fn test_witness_updates() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
         vec![(&ext_address, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -887,7 +872,7 @@ fn test_z_spend_to_z() {
     }
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&ext_address, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1005,7 +990,7 @@ fn test_self_txns_ttoz_withmemo() {
     let (ss, so) =get_sapling_params().unwrap();
 
     // Create a tx and send to address. This should consume both the UTXO and the note
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&zaddr, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1063,7 +1048,7 @@ fn test_self_txns_ttoz_nomemo() {
     let (ss, so) =get_sapling_params().unwrap();
 
     // Create a tx and send to address. This should consume both the UTXO and the note
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&zaddr, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1099,7 +1084,7 @@ fn test_self_txns_ztoz() {
     let (ss, so) =get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&zaddr2, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1119,7 +1104,7 @@ fn test_self_txns_ztoz() {
     }
 
     // Another self tx, this time without a memo
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
         vec![(&zaddr2, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
@@ -1153,7 +1138,7 @@ fn test_multi_z() {
     let (ss, so) =get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&zaddr2, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1208,7 +1193,7 @@ fn test_multi_z() {
     let amount_all:u64 = (AMOUNT1 - AMOUNT_SENT - fee) + (AMOUNT_SENT) - fee;
     let taddr = wallet.address_from_sk(&SecretKey::from_slice(&[1u8; 32]).unwrap());
 
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                                         vec![(&taddr, amount_all, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_ext_txid = sent_tx.txid();
@@ -1250,7 +1235,7 @@ fn test_z_spend_to_taddr() {
     const AMOUNT_SENT: u64 = 30;
     let fee: u64 = DEFAULT_FEE.try_into().unwrap();
 
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                                         vec![(&taddr, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
@@ -1298,7 +1283,7 @@ fn test_z_spend_to_taddr() {
     }
 
     // Create a new Tx, but this time with a memo.
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
         vec![(&taddr, AMOUNT_SENT, Some("T address memo".to_string()))], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid2 = sent_tx.txid();
@@ -1373,7 +1358,7 @@ fn test_t_spend_to_z() {
     let (ss, so) =get_sapling_params().unwrap();
 
     // Create a tx and send to address. This should consume both the UTXO and the note
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&ext_address, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1447,7 +1432,7 @@ fn test_z_incoming_memo() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&my_address, AMOUNT1 - fee, Some(memo.clone()))], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
@@ -1489,7 +1474,7 @@ fn test_z_incoming_hex_memo() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&my_address, AMOUNT1 - fee, Some(memo.clone()))], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
@@ -1542,7 +1527,7 @@ fn test_add_new_zt_hd_after_incoming() {
     assert_eq!(wallet.zkeys.read().unwrap().len(), 6);   // Starts with 1+5 addresses
 
     // Create a tx and send to the last address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&my_address, AMOUNT1 - fee, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
 
@@ -1585,7 +1570,7 @@ fn test_z_to_t_withinwallet() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&taddr, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
@@ -1645,7 +1630,7 @@ fn test_multi_t() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a Tx and send to the second t address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&taddr2, AMOUNT_SENT1, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid1 = sent_tx.txid();
@@ -1689,7 +1674,7 @@ fn test_multi_t() {
     let taddr3 = wallet.add_taddr();
 
     // Create a Tx and send to the second t address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&taddr3, AMOUNT_SENT2, None)], |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid2 = sent_tx.txid();
@@ -1726,7 +1711,7 @@ fn test_multi_t() {
     let outgoing_memo = "Outgoing Memo".to_string();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&ext_address, AMOUNT_SENT_EXT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -1784,7 +1769,7 @@ fn test_multi_spends() {
                     (taddr2.as_str(), TAMOUNT2, None),
                     (taddr3.as_str(), TAMOUNT3, None) ];
     
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so, tos, |_| Ok(' '.to_string())).unwrap();
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so, tos, |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid = sent_tx.txid();
 
@@ -1856,7 +1841,7 @@ fn test_multi_spends() {
 
     let tos = vec![ (ext_address.as_str(), EXT_ZADDR_AMOUNT, Some(ext_memo.clone())),
                     (ext_taddr.as_str(), ext_taddr_amount, None)];
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so, tos, |_| Ok(' '.to_string())).unwrap();
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so, tos, |_| Ok(' '.to_string())).unwrap();
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
     let sent_txid2 = sent_tx.txid();
 
@@ -1998,7 +1983,7 @@ fn test_z_mempool_expiry() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&ext_address, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -2115,7 +2100,7 @@ fn test_rollback() {
     // Create a tx and send to address
     const AMOUNT_SENT: u64 = 30000;
     let fee: u64 = DEFAULT_FEE.try_into().unwrap();
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&taddr, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
 
     let sent_tx = Transaction::read(&raw_tx[..]).unwrap();
@@ -2166,13 +2151,8 @@ fn test_rollback() {
 #[test]
 fn test_t_z_derivation() {
     let lc = LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "main".to_string(),
-        sapling_activation_height: 0,
-        consensus_branch_id: "000000".to_string(),
         anchor_offset: 1,
-        data_dir: None,
-        address_params: AddressParameters::new()
+        ..LightClientConfig::create_unconnected("main".to_string(), None)
     };
 
     let seed_phrase = Some("chimney better bulb horror rebuild whisper improve intact letter giraffe brave rib appear bulk aim burst snap salt hill sad merge tennis phrase raise".to_string());
@@ -2323,13 +2303,9 @@ fn test_lock_unlock() {
 #[test]
 fn test_import_birthday_adjust() {
     let config = LightClientConfig {
-        server: "0.0.0.0:0".parse().unwrap(),
-        chain_name: "main".to_string(),
         sapling_activation_height: 5,
-        consensus_branch_id: "000000".to_string(),
         anchor_offset: 0,
-        data_dir: None,
-        address_params: AddressParameters::new()
+        ..LightClientConfig::create_unconnected("main".to_string(), None)
     };
 
     let privkey = "secret-extended-key-main1q0p44m9zqqqqpqyxfvy5w2vq6ahvxyrwsk2w4h2zleun4cft4llmnsjlv77lhuuknv6x9jgu5g2clf3xq0wz9axxxq8klvv462r5pa32gjuj5uhxnvps6wsrdg6xll05unwks8qpgp4psmvy5e428uxaggn4l29duk82k3sv3njktaaj453fdmfmj2fup8rls4egqxqtj2p5a3yt4070khn99vzxj5ag5qjngc4v2kq0ctl9q2rpc2phu4p3e26egu9w88mchjf83sqgh3cev";
@@ -2350,6 +2326,9 @@ fn test_import_birthday_adjust() {
         // Import key with birthday before the current birthday
         wallet.add_imported_sk(privkey.to_string(), 7);
         assert_eq!(wallet.birthday, 7);
+
+        // The creation height is not affected by imports
+        assert_eq!(wallet.get_creation_height(), 10);
     }
 
     {
@@ -2596,7 +2575,7 @@ fn test_encrypted_zreceive() {
     let (ss, so) = get_sapling_params().unwrap();
 
     // Create a tx and send to address
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&ext_address, AMOUNT_SENT, Some(outgoing_memo.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     assert_eq!(wallet.have_spending_key_for_zaddress(wallet.get_all_zaddresses().get(0).unwrap()), true);
@@ -2654,7 +2633,7 @@ fn test_encrypted_zreceive() {
     const ZAMOUNT2:u64 = 30;
     let outgoing_memo2 = "Outgoing Memo2".to_string();
 
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&zaddr2, ZAMOUNT2, Some(outgoing_memo2.clone()))], |_| Ok(' '.to_string())).unwrap();
 
     // Now lock the wallet again
@@ -2709,7 +2688,7 @@ fn test_encrypted_treceive() {
     const AMOUNT_SENT: u64 = 30;
     let fee: u64 = DEFAULT_FEE.try_into().unwrap();
 
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                                         vec![(&taddr, AMOUNT_SENT, None)], |_| Ok(' '.to_string())).unwrap();
 
     // Now that we have the transaction, we'll encrypt the wallet
@@ -2755,7 +2734,7 @@ fn test_encrypted_treceive() {
     let taddr2 = wallet.add_taddr();
     const TAMOUNT2:u64 = 50;
 
-    let (_, raw_tx) = wallet.send_to_address(branch_id, &ss, &so,
+    let (_, raw_tx, _) = wallet.send_to_address(branch_id, &ss, &so,
                             vec![(&taddr2, TAMOUNT2, None)], |_| Ok(' '.to_string())).unwrap();
 
     // Now lock the wallet again
@@ -2795,3 +2774,64 @@ fn test_encrypted_treceive() {
         assert_eq!(utxo2.unconfirmed_spent, None);
     }
 }
+
+#[test]
+fn test_split_memo() {
+    use super::MAX_MEMO_BYTES;
+
+    // Short memos are not split
+    let short = "Hello".to_string();
+    assert_eq!(LightWallet::split_memo(&short), vec![short.clone()]);
+    assert_eq!(LightWallet::parse_memo_chunk(&short), None);
+
+    // Long memos, including multi-byte chars, are split into chunks that fit
+    let long = "ARRR ✓ ".repeat(300);
+    let chunks = LightWallet::split_memo(&long);
+    assert!(chunks.len() > 1);
+    for (i, c) in chunks.iter().enumerate() {
+        assert!(c.as_bytes().len() <= MAX_MEMO_BYTES);
+        let (index, total, _) = LightWallet::parse_memo_chunk(c).unwrap();
+        assert_eq!(index, i + 1);
+        assert_eq!(total, chunks.len());
+    }
+
+    // Chunks can be reassembled in any order
+    let mut shuffled = chunks.clone();
+    shuffled.reverse();
+    assert_eq!(LightWallet::join_memo_chunks(&shuffled), Some(long.clone()));
+
+    // But not if any are missing
+    assert_eq!(LightWallet::join_memo_chunks(&chunks[1..]), None);
+}
+
+#[test]
+fn test_note_selection_strategy_names() {
+    use super::NoteSelectionStrategy;
+
+    for strategy in &[NoteSelectionStrategy::FewestInputs, NoteSelectionStrategy::OldestFirst, NoteSelectionStrategy::LargestFirst] {
+        assert_eq!(NoteSelectionStrategy::from_str(strategy.as_str()), Some(*strategy));
+    }
+
+    assert_eq!(NoteSelectionStrategy::from_str("random"), None);
+    assert_eq!(NoteSelectionStrategy::default(), NoteSelectionStrategy::LargestFirst);
+}
+
+#[test]
+fn test_note_outpoint() {
+    use super::NoteOutpoint;
+
+    let mut txid_bytes = [0u8; 32];
+    for (i, b) in txid_bytes.iter_mut().enumerate() {
+        *b = i as u8;
+    }
+    let outpoint = NoteOutpoint { txid: TxId(txid_bytes), output_index: 3 };
+
+    // The outpoint uses the same (reversed) txid as the rest of the wallet
+    let s = format!("{}", outpoint);
+    assert_eq!(s, format!("{}:3", TxId(txid_bytes)));
+    assert_eq!(NoteOutpoint::from_str(&s), Ok(outpoint));
+
+    assert!(NoteOutpoint::from_str("abcd:1").is_err());
+    assert!(NoteOutpoint::from_str(&format!("{}", TxId(txid_bytes))).is_err());
+    assert!(NoteOutpoint::from_str(&format!("{}:x", TxId(txid_bytes))).is_err());
+}
@@ -2,14 +2,18 @@ use std::io::{self, Read, Write};
 use std::io::{Error, ErrorKind};
 
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use blake2b_simd::Params as Blake2bParams;
 use pairing::bls12_381::{Bls12};
 
 use sodiumoxide::crypto::secretbox;
 
+use zcash_client_backend::encoding::encode_payment_address;
+
 use zcash_primitives::{
     serialize::{Vector, Optional},
     zip32::{ExtendedFullViewingKey, ExtendedSpendingKey},
     primitives::{Diversifier, PaymentAddress},
+    JUBJUB,
 };
 
 use crate::lightclient::{LightClientConfig};
@@ -30,6 +34,43 @@ pub struct WalletDiversifiers {
   pub zaddress: String
 }
 
+impl WalletDiversifiers {
+  fn serialized_version() -> u8 {
+    return 1;
+  }
+
+  pub fn read<R: Read>(mut inp: R, config: &LightClientConfig) -> io::Result<Self> {
+    let version = inp.read_u8()?;
+    assert!(version <= Self::serialized_version());
+
+    let extfvk = ExtendedFullViewingKey::read(&mut inp)?;
+
+    let mut d = [0u8; 11];
+    inp.read_exact(&mut d)?;
+    let diversifier = Diversifier(d);
+
+    // The address isn't stored, since the key and diversifier determine it
+    let zaddress = match extfvk.fvk.vk.to_payment_address(diversifier, &JUBJUB) {
+      Some(pa) => encode_payment_address(config.hrp_sapling_address(), &pa),
+      None     => return Err(Error::new(ErrorKind::InvalidData, "Invalid diversifier"))
+    };
+
+    Ok(WalletDiversifiers {
+      extfvk,
+      diversifier,
+      zaddress,
+    })
+  }
+
+  pub fn write<W: Write>(&self, mut out: W) -> io::Result<()> {
+    out.write_u8(Self::serialized_version())?;
+
+    ExtendedFullViewingKey::write(&self.extfvk, &mut out)?;
+
+    out.write_all(&self.diversifier.0)
+  }
+}
+
 // A struct that holds z-address private keys or view keys
 #[derive(Clone, Debug, PartialEq)]
 pub struct WalletZKey {
@@ -114,6 +155,29 @@ impl WalletZKey {
     self.extsk.is_some() || self.enc_key.is_some() || self.hdkey_num.is_some()
   }
 
+  /// The ZIP-32 derivation path of this key, relative to the wallet's seed. Imported keys weren't
+  /// derived here, so their path is unknown.
+  pub fn derivation_path(&self, coin_type: u32) -> Option<String> {
+    self.hdkey_num.map(|n| format!("m/32'/{}'/{}'", coin_type, n))
+  }
+
+  /// The ZIP-32 fingerprint of this key's full viewing key: BLAKE2b-256 of `ak || nk || ovk`,
+  /// personalized with "ZcashSaplingFVFP". Its first 4 bytes are the tag that child keys store
+  /// as their parent's, which is the short form other tools display.
+  pub fn fingerprint(&self) -> [u8; 32] {
+    let mut fvk_bytes = vec![];
+    self.extfvk.fvk.write(&mut fvk_bytes).unwrap();
+
+    let hash = Blake2bParams::new()
+        .hash_length(32)
+        .personal(b"ZcashSaplingFVFP")
+        .hash(&fvk_bytes);
+
+    let mut fingerprint = [0u8; 32];
+    fingerprint.copy_from_slice(hash.as_bytes());
+    fingerprint
+  }
+
   fn serialized_version() -> u8 {
       return 1;
   }
@@ -262,6 +326,31 @@ impl WalletZKey {
     self.lock()
   }
 
+  // A copy of this key that is encrypted with `new_key` instead of `old_key`. Only imported spending keys
+  // are encrypted on their own, the HD keys are rebuilt from the seed. This key isn't changed, so if
+  // `old_key` is wrong, it stays as it was.
+  pub fn reencrypt(&self, old_key: &secretbox::Key, new_key: &secretbox::Key) -> io::Result<Self> {
+    let mut zk = self.clone();
+
+    if self.keytype == WalletZKeyType::ImportedSpendingKey {
+      let (enc_key, nonce) = match (&self.enc_key, &self.nonce) {
+        (Some(enc_key), Some(nonce)) => (enc_key, secretbox::Nonce::from_slice(nonce).unwrap()),
+        _ => return Err(Error::new(ErrorKind::InvalidInput, "Can't re-encrypt an imported key that isn't encrypted"))
+      };
+
+      let sk_bytes = match secretbox::open(enc_key, &nonce, old_key) {
+        Ok(s) => s,
+        Err(_) => return Err(io::Error::new(ErrorKind::InvalidData, "Decryption failed. Is your password correct?"))
+      };
+
+      let nonce = secretbox::gen_nonce();
+      zk.enc_key = Some(secretbox::seal(&sk_bytes, &nonce, new_key));
+      zk.nonce = Some(nonce.as_ref().to_vec());
+    }
+
+    Ok(zk)
+  }
+
   pub fn remove_encryption(&mut self) -> io::Result<()> {
     if self.locked {
       return Err(Error::new(ErrorKind::InvalidInput, "Can't remove encryption while locked"));
@@ -296,15 +385,7 @@ pub mod tests {
   use super::WalletZKey;
 
   fn get_config() -> LightClientConfig {
-    LightClientConfig {
-      server: "0.0.0.0:0".parse().unwrap(),
-      chain_name: "main".to_string(),
-      sapling_activation_height: 0,
-      consensus_branch_id: "000000".to_string(),
-      anchor_offset: 0,
-      data_dir: None,
-      address_params: AddressParameters::new()
-    }
+    LightClientConfig::create_unconnected("main".to_string(), None)
   }
 
   #[test]